@@ -1,2 +1,3 @@
 pub mod emission;
+pub mod error;
 pub mod generator;
@@ -1,30 +1,167 @@
-use crate::assembly::generator::AssemblyNode;
+use crate::assembly::error::CodegenError;
+use crate::assembly::generator::{AssemblyCondition, AssemblyNode, AssemblyRegister, AssemblyType};
 use std::fs::File;
-use std::io::{Error, ErrorKind, Write};
+use std::io::Write;
 
-fn convert_aast(aast: &Box<AssemblyNode>) -> std::io::Result<String> {
+/// Name of the part of a register holding an operand of `size` bytes.
+fn register(reg: &AssemblyRegister, size: u8) -> &'static str {
+    let (byte, word, long, quad) = match reg {
+        AssemblyRegister::AX => ("%al", "%ax", "%eax", "%rax"),
+        AssemblyRegister::CX => ("%cl", "%cx", "%ecx", "%rcx"),
+        AssemblyRegister::DX => ("%dl", "%dx", "%edx", "%rdx"),
+        AssemblyRegister::SI => ("%sil", "%si", "%esi", "%rsi"),
+        AssemblyRegister::DI => ("%dil", "%di", "%edi", "%rdi"),
+        AssemblyRegister::BP => ("%bpl", "%bp", "%ebp", "%rbp"),
+        AssemblyRegister::SP => ("%spl", "%sp", "%esp", "%rsp"),
+        AssemblyRegister::R8 => ("%r8b", "%r8w", "%r8d", "%r8"),
+        AssemblyRegister::R9 => ("%r9b", "%r9w", "%r9d", "%r9"),
+        AssemblyRegister::R10 => ("%r10b", "%r10w", "%r10d", "%r10"),
+        AssemblyRegister::R11 => ("%r11b", "%r11w", "%r11d", "%r11"),
+    };
+    match size {
+        1 => byte,
+        2 => word,
+        8 => quad,
+        _ => long,
+    }
+}
+
+fn condition_code(condition: &AssemblyCondition) -> &'static str {
+    match condition {
+        AssemblyCondition::E => "e",
+        AssemblyCondition::NE => "ne",
+        AssemblyCondition::L => "l",
+        AssemblyCondition::LE => "le",
+        AssemblyCondition::G => "g",
+        AssemblyCondition::GE => "ge",
+        AssemblyCondition::B => "b",
+        AssemblyCondition::BE => "be",
+        AssemblyCondition::A => "a",
+        AssemblyCondition::AE => "ae",
+    }
+}
+
+/// Instruction suffix for operands of type `ty`.
+fn suffix(ty: &AssemblyType) -> &'static str {
+    match ty {
+        AssemblyType::Byte => "b",
+        AssemblyType::Word => "w",
+        AssemblyType::Longword => "l",
+        AssemblyType::Quadword => "q",
+    }
+}
+
+/// An operand of type `ty`, naming the part of a register of that size.
+fn operand(aast: &AssemblyNode, ty: &AssemblyType) -> Result<String, CodegenError> {
+    match aast {
+        AssemblyNode::Register(reg) => Ok(register(reg, ty.size()).to_string()),
+        _ => convert_aast(aast),
+    }
+}
+
+/// Labels are local to the object file.
+fn local_label(name: &str) -> String {
+    format!(".L{name}")
+}
+
+/// A function or variable definition, visible to other object files if
+/// `global`.
+fn definition(aast: &AssemblyNode, global: bool) -> Result<String, CodegenError> {
+    let mut output = String::new();
+    match aast {
+        AssemblyNode::Binary {
+            op: super::generator::AssemblyOperations::Function,
+            lhs,
+            rhs,
+        } => {
+            let name = convert_aast(lhs)?;
+            if global {
+                output += format!("    .globl {name}\n").as_str();
+            }
+            output += "    .text\n";
+            output += format!("{name}:\n").as_str();
+            output += "    pushq %rbp\n";
+            output += "    movq %rsp, %rbp\n";
+            output += convert_aast(rhs)?.as_str();
+        }
+        AssemblyNode::Binary {
+            op: super::generator::AssemblyOperations::StaticVariable(ty),
+            lhs,
+            rhs,
+        } => {
+            let name = convert_aast(lhs)?;
+            if global {
+                output += format!("    .globl {name}\n").as_str();
+            }
+            // Zero-initialized variables take no space in the file.
+            let value = match **rhs {
+                AssemblyNode::Int(0) => None,
+                AssemblyNode::Int(value) => Some(value),
+                _ => {
+                    return Err(CodegenError::InvalidOperand {
+                        instruction: "static variable",
+                        operand: format!("{:?}", rhs),
+                    })
+                }
+            };
+            output += match value {
+                Some(_) => "    .data\n",
+                None => "    .bss\n",
+            };
+            let size = ty.size();
+            output += format!("    .balign {size}\n").as_str();
+            output += format!("{name}:\n").as_str();
+            let directive = match ty {
+                AssemblyType::Byte => "byte",
+                AssemblyType::Word => "short",
+                AssemblyType::Longword => "long",
+                AssemblyType::Quadword => "quad",
+            };
+            output += match value {
+                Some(value) => format!("    .{directive} {value}\n"),
+                None => format!("    .zero {size}\n"),
+            }
+            .as_str();
+        }
+        _ => {
+            return Err(CodegenError::UnexpectedNode {
+                node: format!("definition {:?}", aast),
+            })
+        }
+    }
+    Ok(output)
+}
+
+fn convert_aast(aast: &AssemblyNode) -> Result<String, CodegenError> {
     let mut output = String::new();
 
-    match &**aast {
+    match aast {
         AssemblyNode::Int(x) => output += format!("${x}").as_str(),
         AssemblyNode::Str(x) => output += x,
-        AssemblyNode::Register(reg) => match *reg {
-            super::generator::AssemblyRegister::AX => output += "%eax",
-            super::generator::AssemblyRegister::R10 => output += "%r10d",
-        },
+        AssemblyNode::Register(reg) => output += register(reg, 4),
+        AssemblyNode::Condition(condition) => output += condition_code(condition),
         AssemblyNode::AllocateStack(depth) => {
             output += format!("    subq ${depth}, %rsp\n").as_str()
         }
+        AssemblyNode::DeallocateStack(depth) => {
+            output += format!("    addq ${depth}, %rsp\n").as_str()
+        }
         AssemblyNode::Stack(depth) => output += format!("{depth}(%rbp)").as_str(),
+        AssemblyNode::Data(name) => output += format!("{name}(%rip)").as_str(),
         AssemblyNode::Terminal { op } => match op {
             super::generator::AssemblyOperations::Return => {
                 output += "    movq %rbp, %rsp\n";
                 output += "    popq %rbp\n";
                 output += "    ret\n";
             }
+            super::generator::AssemblyOperations::Cdq(AssemblyType::Quadword) => {
+                output += "    cqo\n"
+            }
+            super::generator::AssemblyOperations::Cdq(_) => output += "    cdq\n",
             _ => {
-                let error = format!("Unexpected terminal assembly AST node {:?}", op);
-                return Err(Error::new(ErrorKind::InvalidInput, error));
+                return Err(CodegenError::UnexpectedNode {
+                    node: format!("terminal {:?}", op),
+                });
             }
         },
         AssemblyNode::Unary { op, node } => match op {
@@ -34,37 +171,168 @@ fn convert_aast(aast: &Box<AssemblyNode>) -> std::io::Result<String> {
                     output += "    .section .note.GNU-stack,\"\",@progbits\n";
                 }
             }
+            super::generator::AssemblyOperations::Static => {
+                output += definition(node, false)?.as_str()
+            }
+            // Defined in another file, so there is nothing to emit.
+            super::generator::AssemblyOperations::StaticVariable(_) => {}
             super::generator::AssemblyOperations::Imm => match &**node {
                 AssemblyNode::Int(_) => output += convert_aast(node)?.as_str(),
-                _ => return Err(Error::new(ErrorKind::InvalidInput, "Imm got bad type")),
+                _ => {
+                    return Err(CodegenError::InvalidOperand {
+                        instruction: "imm",
+                        operand: format!("{:?}", node),
+                    })
+                }
             },
-            super::generator::AssemblyOperations::Neg => {
-                output += format!("    negl {}\n", convert_aast(node)?).as_str()
+            super::generator::AssemblyOperations::Neg(ty)
+            | super::generator::AssemblyOperations::Not(ty)
+            | super::generator::AssemblyOperations::Idiv(ty)
+            | super::generator::AssemblyOperations::Div(ty) => {
+                let instruction = match op {
+                    super::generator::AssemblyOperations::Neg(_) => "neg",
+                    super::generator::AssemblyOperations::Not(_) => "not",
+                    super::generator::AssemblyOperations::Idiv(_) => "idiv",
+                    _ => "div",
+                };
+                output +=
+                    format!("    {instruction}{} {}\n", suffix(ty), operand(node, ty)?).as_str()
+            }
+            super::generator::AssemblyOperations::Jmp => {
+                output += format!("    jmp {}\n", local_label(&convert_aast(node)?)).as_str()
             }
-            super::generator::AssemblyOperations::Not => {
-                output += format!("    notl {}\n", convert_aast(node)?).as_str()
+            super::generator::AssemblyOperations::Label => {
+                output += format!("{}:\n", local_label(&convert_aast(node)?)).as_str()
+            }
+            super::generator::AssemblyOperations::Push => {
+                // The stack holds 8-byte values.
+                let src = match &**node {
+                    AssemblyNode::Register(reg) => register(reg, 8).to_string(),
+                    _ => convert_aast(node)?,
+                };
+                output += format!("    pushq {src}\n").as_str()
+            }
+            super::generator::AssemblyOperations::Call => {
+                // Going through the PLT lets the function live in a shared
+                // library; the linker calls it directly when it does not.
+                let name = convert_aast(node)?;
+                if std::env::consts::OS == "linux" {
+                    output += format!("    call {name}@PLT\n").as_str()
+                } else {
+                    output += format!("    call {name}\n").as_str()
+                }
             }
             _ => {
-                let error = format!("Unexpected unary assembly AST node {:?}", op);
-                return Err(Error::new(ErrorKind::InvalidInput, error));
+                return Err(CodegenError::UnexpectedNode {
+                    node: format!("unary {:?}", op),
+                });
             }
         },
         AssemblyNode::Binary { op, lhs, rhs } => match op {
-            super::generator::AssemblyOperations::Function => {
-                let name = convert_aast(lhs)?;
-                output += format!("    .globl {name}\n").as_str();
-                output += format!("{name}:\n").as_str();
-                output += format!("    pushq %rbp\n").as_str();
-                output += format!("    movq %rsp, %rbp\n").as_str();
-                output += convert_aast(rhs)?.as_str();
+            super::generator::AssemblyOperations::Function
+            | super::generator::AssemblyOperations::StaticVariable(_) => {
+                output += definition(aast, true)?.as_str()
             }
-            super::generator::AssemblyOperations::Mov => {
-                output +=
-                    format!("    movl {}, {}\n", convert_aast(lhs)?, convert_aast(rhs)?).as_str();
+            super::generator::AssemblyOperations::Mov(ty)
+            | super::generator::AssemblyOperations::Add(ty)
+            | super::generator::AssemblyOperations::Sub(ty)
+            | super::generator::AssemblyOperations::Mult(ty)
+            | super::generator::AssemblyOperations::And(ty)
+            | super::generator::AssemblyOperations::Or(ty)
+            | super::generator::AssemblyOperations::Xor(ty)
+            | super::generator::AssemblyOperations::Cmp(ty) => {
+                let instruction = match op {
+                    super::generator::AssemblyOperations::Mov(_) => "mov",
+                    super::generator::AssemblyOperations::Add(_) => "add",
+                    super::generator::AssemblyOperations::Sub(_) => "sub",
+                    super::generator::AssemblyOperations::Mult(_) => "imul",
+                    super::generator::AssemblyOperations::And(_) => "and",
+                    super::generator::AssemblyOperations::Or(_) => "or",
+                    super::generator::AssemblyOperations::Xor(_) => "xor",
+                    _ => "cmp",
+                };
+                output += format!(
+                    "    {instruction}{} {}, {}\n",
+                    suffix(ty),
+                    operand(lhs, ty)?,
+                    operand(rhs, ty)?
+                )
+                .as_str();
+            }
+            super::generator::AssemblyOperations::MovSignExtend(from, to)
+            | super::generator::AssemblyOperations::MovZeroExtend(from, to) => {
+                let instruction = match op {
+                    super::generator::AssemblyOperations::MovSignExtend(..) => "movs",
+                    _ => "movz",
+                };
+                output += format!(
+                    "    {instruction}{}{} {}, {}\n",
+                    suffix(from),
+                    suffix(to),
+                    operand(lhs, from)?,
+                    operand(rhs, to)?
+                )
+                .as_str();
+            }
+            super::generator::AssemblyOperations::JmpCC => {
+                let target = local_label(&convert_aast(rhs)?);
+                output += format!("    j{} {target}\n", convert_aast(lhs)?).as_str();
+            }
+            super::generator::AssemblyOperations::SetCC => {
+                // setcc writes a single byte.
+                let dst = match &**rhs {
+                    AssemblyNode::Register(reg) => register(reg, 1).to_string(),
+                    _ => convert_aast(rhs)?,
+                };
+                output += format!("    set{} {dst}\n", convert_aast(lhs)?).as_str();
+            }
+            super::generator::AssemblyOperations::JumpTable => {
+                // Entries are offsets from the table, which keeps the
+                // code position independent.
+                let table = local_label(&convert_aast(lhs)?);
+                output += format!("    leaq {table}(%rip), %rdx\n").as_str();
+                output += "    movslq (%rdx,%rax,4), %rax\n";
+                output += "    addq %rdx, %rax\n";
+                output += "    jmp *%rax\n";
+                output += "    .pushsection .rodata\n";
+                output += "    .balign 4\n";
+                output += format!("{table}:\n").as_str();
+                let AssemblyNode::Sequence(targets) = &**rhs else {
+                    return Err(CodegenError::InvalidOperand {
+                        instruction: "jump table",
+                        operand: format!("{:?}", rhs),
+                    });
+                };
+                for target in targets {
+                    let target = local_label(&convert_aast(target)?);
+                    output += format!("    .long {target} - {table}\n").as_str();
+                }
+                output += "    .popsection\n";
+            }
+            super::generator::AssemblyOperations::Sal(ty)
+            | super::generator::AssemblyOperations::Sar(ty)
+            | super::generator::AssemblyOperations::Shr(ty) => {
+                let instruction = match op {
+                    super::generator::AssemblyOperations::Sal(_) => "sal",
+                    super::generator::AssemblyOperations::Sar(_) => "sar",
+                    _ => "shr",
+                };
+                // The shift count is an immediate or %cl.
+                let count = match &**lhs {
+                    AssemblyNode::Register(reg) => register(reg, 1).to_string(),
+                    _ => convert_aast(lhs)?,
+                };
+                output += format!(
+                    "    {instruction}{} {count}, {}\n",
+                    suffix(ty),
+                    operand(rhs, ty)?
+                )
+                .as_str();
             }
             _ => {
-                let error = format!("Unexpected binary assembly AST node {:?}", op);
-                return Err(Error::new(ErrorKind::InvalidInput, error));
+                return Err(CodegenError::UnexpectedNode {
+                    node: format!("binary {:?}", op),
+                });
             }
         },
         AssemblyNode::Sequence(vec_deque) => {
@@ -77,14 +345,18 @@ fn convert_aast(aast: &Box<AssemblyNode>) -> std::io::Result<String> {
     Ok(output)
 }
 
-pub fn emit(aast: &Box<AssemblyNode>, assembly: &str, debug_mode: bool) -> std::io::Result<()> {
+pub fn emit(aast: &AssemblyNode, assembly: &str, debug_mode: bool) -> Result<(), CodegenError> {
     let res = convert_aast(aast)?;
     if debug_mode {
         println!("{:?}", res)
     };
 
-    let mut file = File::create(assembly)?;
-    file.write_all(res.as_bytes())?;
+    File::create(assembly)
+        .and_then(|mut file| file.write_all(res.as_bytes()))
+        .map_err(|error| CodegenError::Io {
+            path: assembly.to_string(),
+            error,
+        })?;
 
     Ok(())
 }
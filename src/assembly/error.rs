@@ -0,0 +1,48 @@
+use crate::diagnostics::diagnostic::Diagnostic;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum CodegenError {
+    /// The TACKY or assembly AST contained a node the pass does not handle.
+    UnexpectedNode {
+        node: String,
+    },
+    InvalidOperand {
+        instruction: &'static str,
+        operand: String,
+    },
+    /// A stack operand was found before stack slots were assigned.
+    UnexpectedStack,
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+}
+
+impl CodegenError {
+    pub fn is_internal(&self) -> bool {
+        !matches!(self, CodegenError::Io { .. })
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        Diagnostic::bare(self.to_string())
+    }
+}
+
+impl fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CodegenError::UnexpectedNode { node } => {
+                write!(f, "unexpected assembly AST node {node}")
+            }
+            CodegenError::InvalidOperand {
+                instruction,
+                operand,
+            } => write!(f, "invalid operand {operand} for `{instruction}`"),
+            CodegenError::UnexpectedStack => {
+                write!(f, "stack operand found before stack slots were assigned")
+            }
+            CodegenError::Io { path, error } => write!(f, "could not write `{path}`: {error}"),
+        }
+    }
+}
@@ -1,6 +1,38 @@
-use crate::parser::intermediate::{IntermediateNode, IntermediateOperations};
-use std::collections::{HashMap, VecDeque};
-use std::io::{Error, ErrorKind};
+use crate::assembly::error::CodegenError;
+use crate::parser::intermediate::{value_type, IntermediateNode, IntermediateOperations};
+use crate::parser::types::{Symbols, Type};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// Size of the operands of an instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AssemblyType {
+    Byte,
+    Word,
+    Longword,
+    Quadword,
+}
+
+impl AssemblyType {
+    pub fn size(&self) -> u8 {
+        match self {
+            AssemblyType::Byte => 1,
+            AssemblyType::Word => 2,
+            AssemblyType::Longword => 4,
+            AssemblyType::Quadword => 8,
+        }
+    }
+}
+
+impl From<Type> for AssemblyType {
+    fn from(ty: Type) -> AssemblyType {
+        match ty.size() {
+            1 => AssemblyType::Byte,
+            2 => AssemblyType::Word,
+            4 => AssemblyType::Longword,
+            _ => AssemblyType::Quadword,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum AssemblyOperations {
@@ -10,25 +42,96 @@ pub enum AssemblyOperations {
     //Expression,
     Return,
     Imm,
-    Mov,
-    Neg,
-    Not,
+    Mov(AssemblyType),
+    /// Sign or zero extends the source, of the first type, to the
+    /// destination, of the second.
+    MovSignExtend(AssemblyType, AssemblyType),
+    MovZeroExtend(AssemblyType, AssemblyType),
+    Neg(AssemblyType),
+    Not(AssemblyType),
     Pseudo,
+    Add(AssemblyType),
+    Sub(AssemblyType),
+    Mult(AssemblyType),
+    Idiv(AssemblyType),
+    /// Unsigned division of %edx:%eax, or %rdx:%rax.
+    Div(AssemblyType),
+    /// Sign extends %eax into %edx (`cdq`), or %rax into %rdx (`cqo`).
+    Cdq(AssemblyType),
+    And(AssemblyType),
+    Or(AssemblyType),
+    Xor(AssemblyType),
+    Sal(AssemblyType),
+    Sar(AssemblyType),
+    /// Logical right shift, for unsigned operands.
+    Shr(AssemblyType),
+    Cmp(AssemblyType),
+    Jmp,
+    JmpCC,
+    SetCC,
+    /// Jumps to the entry of the table named by `lhs` indexed by %rax.
+    /// `rhs` is the sequence of target labels, emitted to `.rodata`.
+    JumpTable,
+    Label,
+    Push,
+    Call,
+    StaticVariable(AssemblyType),
+    Static,
 }
 
+/// Condition codes for `JmpCC` and `SetCC`: the first six after a signed
+/// `Cmp`, the last four after an unsigned one.
 #[derive(Debug, Clone)]
+pub enum AssemblyCondition {
+    E,
+    NE,
+    L,
+    LE,
+    G,
+    GE,
+    B,
+    BE,
+    A,
+    AE,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AssemblyRegister {
     AX,
+    CX,
+    DX,
+    SI,
+    DI,
+    BP,
+    SP,
+    R8,
+    R9,
     R10,
+    R11,
 }
 
+/// Registers holding the first six integer arguments of a call.
+const ARGUMENT_REGISTERS: [AssemblyRegister; 6] = [
+    AssemblyRegister::DI,
+    AssemblyRegister::SI,
+    AssemblyRegister::DX,
+    AssemblyRegister::CX,
+    AssemblyRegister::R8,
+    AssemblyRegister::R9,
+];
+
 #[derive(Debug, Clone)]
 pub enum AssemblyNode {
-    Int(i32),
+    Int(i64),
     Str(String),
     Register(AssemblyRegister),
+    Condition(AssemblyCondition),
     Stack(i64),
+    /// A variable with static storage duration, addressed relative to
+    /// %rip.
+    Data(String),
     AllocateStack(i64),
+    DeallocateStack(i64),
     Terminal {
         op: AssemblyOperations,
     },
@@ -44,22 +147,174 @@ pub enum AssemblyNode {
     Sequence(VecDeque<Box<AssemblyNode>>),
 }
 
-pub fn convert_ast(ast: &Box<IntermediateNode>) -> std::io::Result<Box<AssemblyNode>> {
-    match &**ast {
-        IntermediateNode::Int(val) => Ok(Box::new(AssemblyNode::Int(*val))),
+fn imm(value: i64) -> Box<AssemblyNode> {
+    Box::new(AssemblyNode::Unary {
+        op: AssemblyOperations::Imm,
+        node: Box::new(AssemblyNode::Int(value)),
+    })
+}
+
+fn register(reg: AssemblyRegister) -> Box<AssemblyNode> {
+    Box::new(AssemblyNode::Register(reg))
+}
+
+fn mov(ty: AssemblyType, src: Box<AssemblyNode>, dst: Box<AssemblyNode>) -> AssemblyNode {
+    AssemblyNode::Binary {
+        op: AssemblyOperations::Mov(ty),
+        lhs: src,
+        rhs: dst,
+    }
+}
+
+/// `dst = rhs <condition> lhs`: compares the operands of type `ty` (`cmp
+/// lhs, rhs` computes `rhs - lhs`), then sets the `int` `dst` to 0 or 1.
+fn set_condition(
+    ty: AssemblyType,
+    lhs: Box<AssemblyNode>,
+    rhs: Box<AssemblyNode>,
+    condition: AssemblyCondition,
+    dst: Box<AssemblyNode>,
+) -> VecDeque<Box<AssemblyNode>> {
+    VecDeque::from([
+        Box::new(AssemblyNode::Binary {
+            op: AssemblyOperations::Cmp(ty),
+            lhs,
+            rhs,
+        }),
+        Box::new(mov(AssemblyType::Longword, imm(0), dst.clone())),
+        Box::new(AssemblyNode::Binary {
+            op: AssemblyOperations::SetCC,
+            lhs: Box::new(AssemblyNode::Condition(condition)),
+            rhs: dst,
+        }),
+    ])
+}
+
+/// Where the parameter at `index` is when the function starts: in a
+/// register, or on the stack above the saved %rbp and return address.
+fn param_location(index: usize) -> Box<AssemblyNode> {
+    match ARGUMENT_REGISTERS.get(index) {
+        Some(reg) => register(reg.clone()),
+        None => Box::new(AssemblyNode::Stack(16 + 8 * (index as i64 - 6))),
+    }
+}
+
+/// Moves the argument `src` of type `ty` to `dst`. Values narrower than
+/// 4 bytes are extended to 4 bytes, which compilers expect of callers.
+fn argument(src: Box<AssemblyNode>, ty: Type, dst: Box<AssemblyNode>) -> AssemblyNode {
+    let from = AssemblyType::from(ty);
+    let op = match from {
+        AssemblyType::Byte | AssemblyType::Word if ty.is_signed() => {
+            AssemblyOperations::MovSignExtend(from, AssemblyType::Longword)
+        }
+        AssemblyType::Byte | AssemblyType::Word => {
+            AssemblyOperations::MovZeroExtend(from, AssemblyType::Longword)
+        }
+        _ => AssemblyOperations::Mov(from),
+    };
+    AssemblyNode::Binary {
+        op,
+        lhs: src,
+        rhs: dst,
+    }
+}
+
+/// Calls `name` following the System V AMD64 ABI: the first six
+/// arguments go in registers and the rest are pushed in reverse order,
+/// with %rsp 16-byte aligned at the `call`. The result, of type `ty`,
+/// is stored in `dst`.
+fn call(
+    name: Box<AssemblyNode>,
+    args: &[(Box<AssemblyNode>, Type)],
+    dst: Box<AssemblyNode>,
+    ty: AssemblyType,
+    variadic: bool,
+) -> Vec<AssemblyNode> {
+    let stack_args = args.len().saturating_sub(ARGUMENT_REGISTERS.len()) as i64;
+    let padding = 8 * (stack_args % 2);
+    let mut instructions = Vec::new();
+    if padding != 0 {
+        instructions.push(AssemblyNode::AllocateStack(padding));
+    }
+    for ((arg, ty), reg) in args.iter().zip(ARGUMENT_REGISTERS) {
+        instructions.push(argument(arg.clone(), *ty, register(reg)));
+    }
+    for (arg, ty) in args.iter().skip(ARGUMENT_REGISTERS.len()).rev() {
+        // pushq reads 8 bytes, so a narrower value in memory goes through
+        // %eax.
+        let arg = match **arg {
+            AssemblyNode::Unary {
+                op: AssemblyOperations::Imm,
+                ..
+            }
+            | AssemblyNode::Register(_) => arg.clone(),
+            _ if ty.size() == 8 => arg.clone(),
+            _ => {
+                instructions.push(argument(arg.clone(), *ty, register(AssemblyRegister::AX)));
+                register(AssemblyRegister::AX)
+            }
+        };
+        instructions.push(AssemblyNode::Unary {
+            op: AssemblyOperations::Push,
+            node: arg,
+        });
+    }
+    if variadic {
+        // %al holds the number of vector registers used for arguments,
+        // and no argument is ever passed in one.
+        instructions.push(mov(
+            AssemblyType::Longword,
+            imm(0),
+            register(AssemblyRegister::AX),
+        ));
+    }
+    instructions.push(AssemblyNode::Unary {
+        op: AssemblyOperations::Call,
+        node: name,
+    });
+    let pushed = 8 * stack_args + padding;
+    if pushed != 0 {
+        instructions.push(AssemblyNode::DeallocateStack(pushed));
+    }
+    instructions.push(mov(ty, register(AssemblyRegister::AX), dst));
+    instructions
+}
+
+/// Appends `node` to `queue`, splicing in the instructions of a sequence.
+fn push_flattened(queue: &mut VecDeque<Box<AssemblyNode>>, node: Box<AssemblyNode>) {
+    match *node {
+        AssemblyNode::Sequence(nodes) => queue.extend(nodes),
+        _ => queue.push_back(node),
+    }
+}
+
+/// Type of a TACKY operand.
+fn operand_type(node: &IntermediateNode, symbols: &Symbols) -> Result<Type, CodegenError> {
+    value_type(node, symbols).ok_or_else(|| CodegenError::UnexpectedNode {
+        node: format!("operand {:?} without a type", node),
+    })
+}
+
+pub fn convert_ast(
+    ast: &IntermediateNode,
+    symbols: &Symbols,
+) -> Result<Box<AssemblyNode>, CodegenError> {
+    match ast {
+        IntermediateNode::Int(val) => Ok(Box::new(AssemblyNode::Int(val.value))),
         IntermediateNode::Str(val) => Ok(Box::new(AssemblyNode::Str(val.clone()))),
         IntermediateNode::Unary { op, node } => match op {
             IntermediateOperations::Program => Ok(Box::new(AssemblyNode::Unary {
                 op: AssemblyOperations::Program,
-                node: convert_ast(node)?,
+                node: convert_ast(node, symbols)?,
             })),
             IntermediateOperations::Return => {
+                let ty = operand_type(node, symbols)?.into();
                 Ok(Box::new(AssemblyNode::Sequence(VecDeque::from([
-                    Box::new(AssemblyNode::Binary {
-                        op: AssemblyOperations::Mov,
-                        lhs: convert_ast(node)?,
-                        rhs: Box::new(AssemblyNode::Register(AssemblyRegister::AX)),
-                    }),
+                    Box::new(mov(
+                        ty,
+                        convert_ast(node, symbols)?,
+                        register(AssemblyRegister::AX),
+                    )),
                     Box::new(AssemblyNode::Terminal {
                         op: AssemblyOperations::Return,
                     }),
@@ -67,86 +322,413 @@ pub fn convert_ast(ast: &Box<IntermediateNode>) -> std::io::Result<Box<AssemblyN
             }
             IntermediateOperations::Constant => Ok(Box::new(AssemblyNode::Unary {
                 op: AssemblyOperations::Imm,
-                node: convert_ast(node)?,
+                node: convert_ast(node, symbols)?,
             })),
             IntermediateOperations::Var => Ok(Box::new(AssemblyNode::Unary {
                 op: AssemblyOperations::Pseudo,
-                node: convert_ast(node)?,
+                node: convert_ast(node, symbols)?,
             })),
-            _ => {
-                let error = format!("Unexpected unary AST node {:?}", op);
-                Err(Error::new(ErrorKind::InvalidInput, error))
+            IntermediateOperations::Jump => Ok(Box::new(AssemblyNode::Unary {
+                op: AssemblyOperations::Jmp,
+                node: convert_ast(node, symbols)?,
+            })),
+            IntermediateOperations::Label => Ok(Box::new(AssemblyNode::Unary {
+                op: AssemblyOperations::Label,
+                node: convert_ast(node, symbols)?,
+            })),
+            IntermediateOperations::StaticVariable => {
+                let ty = match &**node {
+                    IntermediateNode::Str(name) => symbols.get(name),
+                    _ => None,
+                };
+                let Some(&ty) = ty else {
+                    return Err(CodegenError::UnexpectedNode {
+                        node: format!("static variable {:?} without a type", node),
+                    });
+                };
+                Ok(Box::new(AssemblyNode::Unary {
+                    op: AssemblyOperations::StaticVariable(ty.into()),
+                    node: convert_ast(node, symbols)?,
+                }))
             }
+            IntermediateOperations::Static => Ok(Box::new(AssemblyNode::Unary {
+                op: AssemblyOperations::Static,
+                node: convert_ast(node, symbols)?,
+            })),
+            _ => Err(CodegenError::UnexpectedNode {
+                node: format!("unary {:?}", op),
+            }),
         },
         IntermediateNode::Binary { op, lhs, rhs } => match op {
-            IntermediateOperations::Function => Ok(Box::new(AssemblyNode::Binary {
-                op: AssemblyOperations::Function,
-                lhs: convert_ast(lhs)?,
-                rhs: convert_ast(rhs)?,
-            })),
-            IntermediateOperations::Complement => {
-                let src = convert_ast(lhs)?;
-                let dst = convert_ast(rhs)?;
+            IntermediateOperations::StaticVariable => {
+                let IntermediateNode::Int(value) = **rhs else {
+                    return Err(CodegenError::InvalidOperand {
+                        instruction: "static variable",
+                        operand: format!("{:?}", rhs),
+                    });
+                };
+                Ok(Box::new(AssemblyNode::Binary {
+                    op: AssemblyOperations::StaticVariable(value.ty.into()),
+                    lhs: convert_ast(lhs, symbols)?,
+                    rhs: convert_ast(rhs, symbols)?,
+                }))
+            }
+            IntermediateOperations::Function => {
+                let IntermediateNode::Sequence(body) = &**rhs else {
+                    return Err(CodegenError::UnexpectedNode {
+                        node: format!("function body {:?}", rhs),
+                    });
+                };
+                // Parameters are copied out of the registers and stack
+                // slots they arrive in, before anything can clobber them.
+                let mut instructions = VecDeque::new();
+                let mut params = 0;
+                for instruction in body {
+                    let converted = match &**instruction {
+                        IntermediateNode::Unary {
+                            op: IntermediateOperations::Param,
+                            node,
+                        } => {
+                            params += 1;
+                            Box::new(mov(
+                                operand_type(node, symbols)?.into(),
+                                param_location(params - 1),
+                                convert_ast(node, symbols)?,
+                            ))
+                        }
+                        _ => convert_ast(instruction, symbols)?,
+                    };
+                    push_flattened(&mut instructions, converted);
+                }
+                Ok(Box::new(AssemblyNode::Binary {
+                    op: AssemblyOperations::Function,
+                    lhs: convert_ast(lhs, symbols)?,
+                    rhs: Box::new(AssemblyNode::Sequence(instructions)),
+                }))
+            }
+            IntermediateOperations::Copy => Ok(Box::new(mov(
+                operand_type(lhs, symbols)?.into(),
+                convert_ast(lhs, symbols)?,
+                convert_ast(rhs, symbols)?,
+            ))),
+            IntermediateOperations::JumpIfZero | IntermediateOperations::JumpIfNotZero => {
+                let condition = match op {
+                    IntermediateOperations::JumpIfZero => AssemblyCondition::E,
+                    _ => AssemblyCondition::NE,
+                };
                 Ok(Box::new(AssemblyNode::Sequence(VecDeque::from([
                     Box::new(AssemblyNode::Binary {
-                        op: AssemblyOperations::Mov,
-                        lhs: src,
-                        rhs: dst.clone(),
+                        op: AssemblyOperations::Cmp(operand_type(lhs, symbols)?.into()),
+                        lhs: imm(0),
+                        rhs: convert_ast(lhs, symbols)?,
                     }),
-                    Box::new(AssemblyNode::Unary {
-                        op: AssemblyOperations::Not,
-                        node: dst,
+                    Box::new(AssemblyNode::Binary {
+                        op: AssemblyOperations::JmpCC,
+                        lhs: Box::new(AssemblyNode::Condition(condition)),
+                        rhs: convert_ast(rhs, symbols)?,
                     }),
                 ]))))
             }
-            IntermediateOperations::Negate => {
-                let src = convert_ast(lhs)?;
-                let dst = convert_ast(rhs)?;
+            IntermediateOperations::Not => Ok(Box::new(AssemblyNode::Sequence(set_condition(
+                operand_type(lhs, symbols)?.into(),
+                imm(0),
+                convert_ast(lhs, symbols)?,
+                AssemblyCondition::E,
+                convert_ast(rhs, symbols)?,
+            )))),
+            IntermediateOperations::Complement | IntermediateOperations::Negate => {
+                let ty = operand_type(lhs, symbols)?.into();
+                let src = convert_ast(lhs, symbols)?;
+                let dst = convert_ast(rhs, symbols)?;
+                let op = match op {
+                    IntermediateOperations::Complement => AssemblyOperations::Not(ty),
+                    _ => AssemblyOperations::Neg(ty),
+                };
                 Ok(Box::new(AssemblyNode::Sequence(VecDeque::from([
-                    Box::new(AssemblyNode::Binary {
-                        op: AssemblyOperations::Mov,
-                        lhs: src,
-                        rhs: dst.clone(),
-                    }),
-                    Box::new(AssemblyNode::Unary {
-                        op: AssemblyOperations::Neg,
-                        node: dst,
-                    }),
+                    Box::new(mov(ty, src, dst.clone())),
+                    Box::new(AssemblyNode::Unary { op, node: dst }),
                 ]))))
             }
-            _ => {
-                let error = format!("Unexpected binary AST node {:?}", op);
-                Err(Error::new(ErrorKind::InvalidInput, error))
+            IntermediateOperations::SignExtend
+            | IntermediateOperations::ZeroExtend
+            | IntermediateOperations::Truncate => {
+                let from = operand_type(lhs, symbols)?.into();
+                let to_type = operand_type(rhs, symbols)?;
+                let to = to_type.into();
+                let mut src = convert_ast(lhs, symbols)?;
+                // The assembler rejects an immediate too wide for the
+                // destination, so truncate it here.
+                if let AssemblyNode::Unary {
+                    op: AssemblyOperations::Imm,
+                    node,
+                } = &*src
+                {
+                    if let AssemblyNode::Int(value) = **node {
+                        src = imm(to_type.convert(value));
+                    }
+                }
+                let dst = convert_ast(rhs, symbols)?;
+                let instructions = match op {
+                    IntermediateOperations::SignExtend => vec![AssemblyNode::Binary {
+                        op: AssemblyOperations::MovSignExtend(from, to),
+                        lhs: src,
+                        rhs: dst,
+                    }],
+                    // Writing a 4-byte register clears the upper half, so
+                    // there is no instruction zero extending 4 bytes.
+                    IntermediateOperations::ZeroExtend if from == AssemblyType::Longword => vec![
+                        mov(from, src, register(AssemblyRegister::R11)),
+                        mov(to, register(AssemblyRegister::R11), dst),
+                    ],
+                    IntermediateOperations::ZeroExtend => vec![AssemblyNode::Binary {
+                        op: AssemblyOperations::MovZeroExtend(from, to),
+                        lhs: src,
+                        rhs: dst,
+                    }],
+                    // The low bytes of a value are the truncated value.
+                    _ => vec![mov(to, src, dst)],
+                };
+                Ok(Box::new(AssemblyNode::Sequence(
+                    instructions.into_iter().map(Box::new).collect(),
+                )))
             }
+            _ => Err(CodegenError::UnexpectedNode {
+                node: format!("binary {:?}", op),
+            }),
         },
+        IntermediateNode::Ternary {
+            op: op @ (IntermediateOperations::Call | IntermediateOperations::VariadicCall),
+            lhs,
+            rhs,
+            dst,
+        } => {
+            let IntermediateNode::Sequence(args) = &**rhs else {
+                return Err(CodegenError::InvalidOperand {
+                    instruction: "call",
+                    operand: format!("{:?}", rhs),
+                });
+            };
+            let args = args
+                .iter()
+                .map(|arg| Ok((convert_ast(arg, symbols)?, operand_type(arg, symbols)?)))
+                .collect::<Result<Vec<_>, CodegenError>>()?;
+            let variadic = matches!(op, IntermediateOperations::VariadicCall);
+            let instructions = call(
+                convert_ast(lhs, symbols)?,
+                &args,
+                convert_ast(dst, symbols)?,
+                operand_type(dst, symbols)?.into(),
+                variadic,
+            );
+            Ok(Box::new(AssemblyNode::Sequence(
+                instructions.into_iter().map(Box::new).collect(),
+            )))
+        }
+        IntermediateNode::Ternary { op, lhs, rhs, dst } => {
+            // Both operands have the same type, as type checking made sure.
+            let ty = operand_type(lhs, symbols)?;
+            let size = AssemblyType::from(ty);
+            let src1 = convert_ast(lhs, symbols)?;
+            let src2 = convert_ast(rhs, symbols)?;
+            let dst = convert_ast(dst, symbols)?;
+            let instructions = match op {
+                IntermediateOperations::Divide | IntermediateOperations::Remainder => {
+                    let result = match op {
+                        IntermediateOperations::Divide => AssemblyRegister::AX,
+                        _ => AssemblyRegister::DX,
+                    };
+                    // The dividend is %edx:%eax, with %edx holding the sign
+                    // for signed division and zero for unsigned division.
+                    let (extend, divide) = if ty.is_signed() {
+                        (
+                            AssemblyNode::Terminal {
+                                op: AssemblyOperations::Cdq(size),
+                            },
+                            AssemblyOperations::Idiv(size),
+                        )
+                    } else {
+                        (
+                            mov(size, imm(0), register(AssemblyRegister::DX)),
+                            AssemblyOperations::Div(size),
+                        )
+                    };
+                    vec![
+                        mov(size, src1, register(AssemblyRegister::AX)),
+                        extend,
+                        AssemblyNode::Unary {
+                            op: divide,
+                            node: src2,
+                        },
+                        mov(size, register(result), dst),
+                    ]
+                }
+                IntermediateOperations::JumpTable => vec![
+                    mov(size, src1, register(AssemblyRegister::AX)),
+                    AssemblyNode::Binary {
+                        op: AssemblyOperations::JumpTable,
+                        lhs: dst,
+                        rhs: src2,
+                    },
+                ],
+                IntermediateOperations::LeftShift | IntermediateOperations::RightShift => {
+                    let op = match op {
+                        IntermediateOperations::LeftShift => AssemblyOperations::Sal(size),
+                        _ if ty.is_signed() => AssemblyOperations::Sar(size),
+                        _ => AssemblyOperations::Shr(size),
+                    };
+                    // A shift count which is not an immediate must be in %cl.
+                    // An immediate count must fit in a byte, and the
+                    // processor masks the count to the operand width anyway.
+                    let mut instructions = vec![mov(size, src1, dst.clone())];
+                    let count = match *src2 {
+                        AssemblyNode::Unary {
+                            op: AssemblyOperations::Imm,
+                            ref node,
+                        } => match **node {
+                            AssemblyNode::Int(value) => {
+                                imm(value & (8 * i64::from(size.size()) - 1))
+                            }
+                            _ => src2,
+                        },
+                        _ => {
+                            instructions.push(mov(size, src2, register(AssemblyRegister::CX)));
+                            register(AssemblyRegister::CX)
+                        }
+                    };
+                    instructions.push(AssemblyNode::Binary {
+                        op,
+                        lhs: count,
+                        rhs: dst,
+                    });
+                    instructions
+                }
+                IntermediateOperations::Equal
+                | IntermediateOperations::NotEqual
+                | IntermediateOperations::LessThan
+                | IntermediateOperations::LessOrEqual
+                | IntermediateOperations::GreaterThan
+                | IntermediateOperations::GreaterOrEqual => {
+                    let condition = match (op, ty.is_signed()) {
+                        (IntermediateOperations::Equal, _) => AssemblyCondition::E,
+                        (IntermediateOperations::NotEqual, _) => AssemblyCondition::NE,
+                        (IntermediateOperations::LessThan, true) => AssemblyCondition::L,
+                        (IntermediateOperations::LessThan, false) => AssemblyCondition::B,
+                        (IntermediateOperations::LessOrEqual, true) => AssemblyCondition::LE,
+                        (IntermediateOperations::LessOrEqual, false) => AssemblyCondition::BE,
+                        (IntermediateOperations::GreaterThan, true) => AssemblyCondition::G,
+                        (IntermediateOperations::GreaterThan, false) => AssemblyCondition::A,
+                        (_, true) => AssemblyCondition::GE,
+                        (_, false) => AssemblyCondition::AE,
+                    };
+                    return Ok(Box::new(AssemblyNode::Sequence(set_condition(
+                        size, src2, src1, condition, dst,
+                    ))));
+                }
+                _ => {
+                    let op = match op {
+                        IntermediateOperations::Add => AssemblyOperations::Add(size),
+                        IntermediateOperations::Subtract => AssemblyOperations::Sub(size),
+                        IntermediateOperations::Multiply => AssemblyOperations::Mult(size),
+                        IntermediateOperations::BitwiseAnd => AssemblyOperations::And(size),
+                        IntermediateOperations::BitwiseOr => AssemblyOperations::Or(size),
+                        IntermediateOperations::BitwiseXor => AssemblyOperations::Xor(size),
+                        _ => {
+                            return Err(CodegenError::UnexpectedNode {
+                                node: format!("ternary {:?}", op),
+                            })
+                        }
+                    };
+                    vec![
+                        mov(size, src1, dst.clone()),
+                        AssemblyNode::Binary {
+                            op,
+                            lhs: src2,
+                            rhs: dst,
+                        },
+                    ]
+                }
+            };
+            Ok(Box::new(AssemblyNode::Sequence(
+                instructions.into_iter().map(Box::new).collect(),
+            )))
+        }
         IntermediateNode::Sequence(vec_deque) => {
-            let seq: VecDeque<Box<AssemblyNode>> =
-                vec_deque.iter().map(|f| convert_ast(f).unwrap()).collect();
             let mut queue: VecDeque<Box<AssemblyNode>> = VecDeque::new();
-            for s in seq {
-                match *s {
-                    AssemblyNode::Sequence(s) => s.iter().for_each(|x| queue.push_back(x.clone())),
-                    _ => queue.push_back(s),
-                }
+            for f in vec_deque {
+                push_flattened(&mut queue, convert_ast(f, symbols)?);
             }
             Ok(Box::new(AssemblyNode::Sequence(queue)))
         }
     }
 }
 
+/// Collects the names of the variables with static storage duration
+/// the program defines or declares.
+fn static_variables(aast: &AssemblyNode, names: &mut HashSet<String>) {
+    match aast {
+        AssemblyNode::Unary {
+            op: AssemblyOperations::StaticVariable(_),
+            node: name,
+        }
+        | AssemblyNode::Binary {
+            op: AssemblyOperations::StaticVariable(_),
+            lhs: name,
+            ..
+        } => {
+            if let AssemblyNode::Str(name) = &**name {
+                names.insert(name.clone());
+            }
+        }
+        AssemblyNode::Unary {
+            op: AssemblyOperations::Program | AssemblyOperations::Static,
+            node,
+        } => static_variables(node, names),
+        AssemblyNode::Sequence(vec_deque) => {
+            vec_deque.iter().for_each(|x| static_variables(x, names))
+        }
+        _ => {}
+    }
+}
+
+/// Stack slots of the variables and temporaries of a function.
+#[derive(Default)]
+pub struct StackFrame {
+    slots: HashMap<String, i64>,
+    size: i64,
+}
+
+impl StackFrame {
+    /// Offset from %rbp of the slot of `name`, allocated on first use
+    /// and aligned to the size of `ty`.
+    fn slot(&mut self, name: &str, ty: Type) -> i64 {
+        if let Some(offset) = self.slots.get(name) {
+            return *offset;
+        }
+        let size = ty.size();
+        self.size = (self.size + size + size - 1) / size * size;
+        self.slots.insert(name.to_string(), -self.size);
+        -self.size
+    }
+}
+
+/// Replaces each pseudo-register with its stack slot, or with its data
+/// operand if it names a variable in `statics`.
 pub fn process_stack(
     aast: &mut Box<AssemblyNode>,
-    stack_map: &mut HashMap<String, i64>,
-) -> Result<(), Error> {
+    frame: &mut StackFrame,
+    statics: &HashSet<String>,
+    symbols: &Symbols,
+) -> Result<(), CodegenError> {
     match &mut **aast {
         AssemblyNode::Int(_) => Ok(()),
         AssemblyNode::Str(_) => Ok(()),
         AssemblyNode::Register(_) => Ok(()),
+        AssemblyNode::Condition(_) => Ok(()),
         AssemblyNode::AllocateStack(_) => Ok(()),
-        AssemblyNode::Stack(_) => Err(Error::new(
-            ErrorKind::InvalidInput,
-            "There should be no stack in AAST yet",
-        )),
+        AssemblyNode::DeallocateStack(_) => Ok(()),
+        // Stack arguments of the function, already in place.
+        AssemblyNode::Stack(_) => Ok(()),
+        AssemblyNode::Data(_) => Ok(()),
         AssemblyNode::Terminal { op: _ } => Ok(()),
         AssemblyNode::Unary { op, node } => {
             match op {
@@ -154,45 +736,56 @@ pub fn process_stack(
                     let pseudo_name = match &**node {
                         AssemblyNode::Str(x) => x.clone(),
                         _ => {
-                            return Err(Error::new(
-                                ErrorKind::InvalidInput,
-                                "Pseudo must reference Str in AAST",
-                            ))
+                            return Err(CodegenError::InvalidOperand {
+                                instruction: "pseudo",
+                                operand: format!("{:?}", node),
+                            })
                         }
                     };
-                    if !stack_map.contains_key(&pseudo_name) {
-                        stack_map
-                            .insert(pseudo_name.clone(), -4i64 * (stack_map.len() as i64 + 1i64));
+                    if statics.contains(&pseudo_name) {
+                        **aast = AssemblyNode::Data(pseudo_name);
+                        return Ok(());
                     }
-                    *aast = Box::new(AssemblyNode::Stack(stack_map[&pseudo_name]));
+                    let Some(&ty) = symbols.get(&pseudo_name) else {
+                        return Err(CodegenError::UnexpectedNode {
+                            node: format!("pseudo {pseudo_name} without a type"),
+                        });
+                    };
+                    **aast = AssemblyNode::Stack(frame.slot(&pseudo_name, ty));
                 }
                 _ => {
-                    process_stack(node, stack_map)?;
+                    process_stack(node, frame, statics, symbols)?;
                 }
             };
             Ok(())
         }
+        // Each function has its own stack frame.
+        AssemblyNode::Binary {
+            op: AssemblyOperations::Function,
+            lhs: _,
+            rhs,
+        } => process_stack(rhs, &mut StackFrame::default(), statics, symbols),
         AssemblyNode::Binary { op: _, lhs, rhs } => {
-            process_stack(lhs, stack_map)?;
-            process_stack(rhs, stack_map)?;
-            Ok(())
-        }
-        AssemblyNode::Sequence(vec_deque) => {
-            vec_deque.iter_mut().for_each(|x| {
-                process_stack(x, stack_map).unwrap();
-            });
+            process_stack(lhs, frame, statics, symbols)?;
+            process_stack(rhs, frame, statics, symbols)?;
             Ok(())
         }
+        AssemblyNode::Sequence(vec_deque) => vec_deque
+            .iter_mut()
+            .try_for_each(|x| process_stack(x, frame, statics, symbols)),
     }
 }
 
 pub fn find_stack_size(aast: &mut Box<AssemblyNode>) -> i64 {
-    return match &mut **aast {
+    match &mut **aast {
         AssemblyNode::Int(_) => 0i64,
         AssemblyNode::Str(_) => 0i64,
         AssemblyNode::Register(_) => 0i64,
+        AssemblyNode::Condition(_) => 0i64,
         AssemblyNode::Stack(x) => -*x,
+        AssemblyNode::Data(_) => 0i64,
         AssemblyNode::AllocateStack(_) => 0i64,
+        AssemblyNode::DeallocateStack(_) => 0i64,
         AssemblyNode::Terminal { op: _ } => 0i64,
         AssemblyNode::Unary { op: _, node } => find_stack_size(node),
         AssemblyNode::Binary { op: _, lhs, rhs } => find_stack_size(lhs).max(find_stack_size(rhs)),
@@ -203,91 +796,234 @@ pub fn find_stack_size(aast: &mut Box<AssemblyNode>) -> i64 {
             }
             stack_size
         }
-    };
+    }
 }
 
-pub fn fix_instructions(aast: &mut Box<AssemblyNode>) -> Result<(), Error> {
+pub fn fix_instructions(aast: &mut Box<AssemblyNode>) -> Result<(), CodegenError> {
     match &mut **aast {
         AssemblyNode::Int(_) => Ok(()),
         AssemblyNode::Str(_) => Ok(()),
         AssemblyNode::Register(_) => Ok(()),
+        AssemblyNode::Condition(_) => Ok(()),
         AssemblyNode::AllocateStack(_) => Ok(()),
-        AssemblyNode::Stack(_) => Err(Error::new(
-            ErrorKind::InvalidInput,
-            "There should be no stack in AAST yet",
-        )),
+        AssemblyNode::DeallocateStack(_) => Ok(()),
+        AssemblyNode::Stack(_) => Err(CodegenError::UnexpectedStack),
+        AssemblyNode::Data(_) => Ok(()),
         AssemblyNode::Terminal { op: _ } => Ok(()),
         AssemblyNode::Unary { op: _, node } => {
             fix_instructions(node)?;
             Ok(())
         }
+        AssemblyNode::Binary {
+            op: AssemblyOperations::Function,
+            lhs: _,
+            rhs,
+        } => match &mut **rhs {
+            AssemblyNode::Sequence(body) => fix_function_body(body),
+            _ => Err(CodegenError::InvalidOperand {
+                instruction: "function",
+                operand: format!("{:?}", rhs),
+            }),
+        },
         AssemblyNode::Binary { op: _, lhs, rhs } => {
             fix_instructions(lhs)?;
             fix_instructions(rhs)?;
             Ok(())
         }
-        AssemblyNode::Sequence(vec_deque) => {
-            let mut stack_size = 0i64;
-            vec_deque.iter_mut().for_each(|x| {
-                stack_size = stack_size.max(find_stack_size(x));
-            });
-            vec_deque.push_front(Box::new(AssemblyNode::AllocateStack(stack_size)));
-            let mut i = 0;
-            while i < vec_deque.len() {
-                match &mut *vec_deque[i] {
-                    AssemblyNode::Binary {
-                        op: AssemblyOperations::Mov,
-                        lhs,
-                        rhs,
-                    } => {
-                        match &**lhs {
-                            AssemblyNode::Stack(_) => {
-                                match &**rhs {
-                                    AssemblyNode::Stack(_) => {
-                                        let reg =
-                                            Box::new(AssemblyNode::Register(AssemblyRegister::R10));
-                                        let node = Box::new(AssemblyNode::Binary {
-                                            op: AssemblyOperations::Mov,
-                                            lhs: reg.clone(),
-                                            rhs: rhs.clone(),
-                                        });
-                                        *rhs = reg;
-                                        vec_deque.insert(i + 1, node);
-                                    }
-                                    _ => { /* Skip */ }
-                                }
-                            }
-                            _ => { /* Skip */ }
-                        }
-                    }
-                    _ => { /* Skip */ }
-                }
+        AssemblyNode::Sequence(vec_deque) => vec_deque.iter_mut().try_for_each(fix_instructions),
+    }
+}
+
+/// Whether an operand is in memory, on the stack or in a data section.
+fn is_memory(aast: &AssemblyNode) -> bool {
+    matches!(aast, AssemblyNode::Stack(_) | AssemblyNode::Data(_))
+}
+
+fn is_immediate(aast: &AssemblyNode) -> bool {
+    matches!(
+        aast,
+        AssemblyNode::Unary {
+            op: AssemblyOperations::Imm,
+            ..
+        }
+    )
+}
+
+/// Whether an operand is an immediate which does not fit in the 32 bits
+/// instructions other than `mov` sign extend to 64 bits.
+fn is_large_immediate(aast: &AssemblyNode) -> bool {
+    match aast {
+        AssemblyNode::Unary {
+            op: AssemblyOperations::Imm,
+            node,
+        } => matches!(**node, AssemblyNode::Int(value) if i32::try_from(value).is_err()),
+        _ => false,
+    }
+}
+
+/// Allocates the stack frame of a function and rewrites instructions
+/// whose operands are not valid together. A rewritten instruction is
+/// checked again, as it may need more than one fix.
+fn fix_function_body(vec_deque: &mut VecDeque<Box<AssemblyNode>>) -> Result<(), CodegenError> {
+    let mut stack_size = 0i64;
+    vec_deque.iter_mut().for_each(|x| {
+        stack_size = stack_size.max(find_stack_size(x));
+    });
+    // %rsp is 16-byte aligned at the call, so after the return address
+    // and the saved %rbp the frame must be a multiple of 16.
+    stack_size = (stack_size + 15) / 16 * 16;
+    vec_deque.push_front(Box::new(AssemblyNode::AllocateStack(stack_size)));
+    let mut i = 0;
+    while i < vec_deque.len() {
+        match &mut *vec_deque[i] {
+            AssemblyNode::Binary {
+                op: AssemblyOperations::Mov(AssemblyType::Quadword),
+                lhs,
+                rhs,
+            } if is_large_immediate(lhs) && is_memory(rhs) => {
+                // Only a register can receive a 64-bit immediate.
+                let load = mov(
+                    AssemblyType::Quadword,
+                    lhs.clone(),
+                    register(AssemblyRegister::R10),
+                );
+                *lhs = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op:
+                    AssemblyOperations::Add(AssemblyType::Quadword)
+                    | AssemblyOperations::Sub(AssemblyType::Quadword)
+                    | AssemblyOperations::Mult(AssemblyType::Quadword)
+                    | AssemblyOperations::And(AssemblyType::Quadword)
+                    | AssemblyOperations::Or(AssemblyType::Quadword)
+                    | AssemblyOperations::Xor(AssemblyType::Quadword)
+                    | AssemblyOperations::Cmp(AssemblyType::Quadword),
+                lhs,
+                rhs: _,
+            } if is_large_immediate(lhs) => {
+                // Other instructions take at most a 32-bit immediate.
+                let load = mov(
+                    AssemblyType::Quadword,
+                    lhs.clone(),
+                    register(AssemblyRegister::R10),
+                );
+                *lhs = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Unary {
+                op: AssemblyOperations::Push,
+                node,
+            } if is_large_immediate(node) => {
+                let load = mov(
+                    AssemblyType::Quadword,
+                    node.clone(),
+                    register(AssemblyRegister::R10),
+                );
+                *node = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op:
+                    AssemblyOperations::Mov(ty)
+                    | AssemblyOperations::Add(ty)
+                    | AssemblyOperations::Sub(ty)
+                    | AssemblyOperations::And(ty)
+                    | AssemblyOperations::Or(ty)
+                    | AssemblyOperations::Xor(ty)
+                    | AssemblyOperations::Cmp(ty),
+                lhs,
+                rhs,
+            } if is_memory(lhs) && is_memory(rhs) => {
+                // At most one operand may be in memory: load the
+                // source into %r10 first.
+                let load = mov(*ty, lhs.clone(), register(AssemblyRegister::R10));
+                *lhs = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op: AssemblyOperations::Cmp(ty),
+                lhs: _,
+                rhs,
+            } if is_immediate(rhs) => {
+                // The second operand of cmp cannot be an immediate.
+                let load = mov(*ty, rhs.clone(), register(AssemblyRegister::R11));
+                *rhs = register(AssemblyRegister::R11);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op: AssemblyOperations::Mult(ty),
+                lhs: _,
+                rhs,
+            } if is_memory(rhs) => {
+                // imul cannot write to memory: multiply in %r11.
+                let load = mov(*ty, rhs.clone(), register(AssemblyRegister::R11));
+                let store = mov(*ty, register(AssemblyRegister::R11), rhs.clone());
+                *rhs = register(AssemblyRegister::R11);
+                vec_deque.insert(i + 1, Box::new(store));
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Unary {
+                op: AssemblyOperations::Idiv(ty) | AssemblyOperations::Div(ty),
+                node,
+            } if is_immediate(node) => {
+                // idiv and div cannot take an immediate operand.
+                let load = mov(*ty, node.clone(), register(AssemblyRegister::R10));
+                *node = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op:
+                    AssemblyOperations::MovSignExtend(from, _)
+                    | AssemblyOperations::MovZeroExtend(from, _),
+                lhs,
+                rhs: _,
+            } if is_immediate(lhs) => {
+                // movs and movz cannot take an immediate source.
+                let load = mov(*from, lhs.clone(), register(AssemblyRegister::R10));
+                *lhs = register(AssemblyRegister::R10);
+                vec_deque.insert(i, Box::new(load));
+            }
+            AssemblyNode::Binary {
+                op:
+                    AssemblyOperations::MovSignExtend(_, to) | AssemblyOperations::MovZeroExtend(_, to),
+                lhs: _,
+                rhs,
+            } if is_memory(rhs) => {
+                // movs and movz can only write to a register.
+                let store = mov(*to, register(AssemblyRegister::R11), rhs.clone());
+                *rhs = register(AssemblyRegister::R11);
+                vec_deque.insert(i + 1, Box::new(store));
+            }
+            _ => {
                 i += 1;
             }
-            Ok(())
         }
     }
+    Ok(())
 }
 
 pub fn generate(
-    ast: &Box<IntermediateNode>,
+    ast: &IntermediateNode,
+    symbols: &Symbols,
     debug_mode: bool,
-) -> std::io::Result<Box<AssemblyNode>> {
-    let mut res = convert_ast(ast)?;
+) -> Result<Box<AssemblyNode>, CodegenError> {
+    let mut res = convert_ast(ast, symbols)?;
 
     println!("    - Convert");
     if debug_mode {
         println!("{:?}", res);
     }
 
-    let mut stack_map: HashMap<String, i64> = HashMap::new();
-    let _ = process_stack(&mut res, &mut stack_map)?;
+    let mut statics = HashSet::new();
+    static_variables(&res, &mut statics);
+    process_stack(&mut res, &mut StackFrame::default(), &statics, symbols)?;
     println!("    - Stack update");
     if debug_mode {
         println!("{:?}", res);
     }
 
-    let _ = fix_instructions(&mut res);
+    fix_instructions(&mut res)?;
     println!("    - Instructions update");
     if debug_mode {
         println!("{:?}", res);
@@ -295,3 +1031,70 @@ pub fn generate(
 
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::lex;
+    use crate::parser::{intermediate::intermediate, parser::parse, semantic::analyze};
+
+    fn assembly(source: &str) -> Box<AssemblyNode> {
+        let ast = parse(&mut lex(source, "test.c", false).unwrap(), false).unwrap();
+        let (ast, mut symbols) = analyze(*ast, false).unwrap();
+        let tacky = intermediate(&ast, &mut symbols, false).unwrap();
+        generate(&tacky, &symbols, false).unwrap()
+    }
+
+    /// Every shift instruction in `aast` with its count.
+    fn shifts(aast: &AssemblyNode, found: &mut Vec<String>) {
+        match aast {
+            AssemblyNode::Binary {
+                op:
+                    op @ (AssemblyOperations::Sal(_)
+                    | AssemblyOperations::Sar(_)
+                    | AssemblyOperations::Shr(_)),
+                lhs,
+                ..
+            } => found.push(match &**lhs {
+                AssemblyNode::Unary { node, .. } => match **node {
+                    AssemblyNode::Int(value) => format!("{op:?} {value}"),
+                    ref node => panic!("unexpected count {node:?}"),
+                },
+                AssemblyNode::Register(reg) => format!("{op:?} {reg:?}"),
+                node => panic!("unexpected count {node:?}"),
+            }),
+            AssemblyNode::Unary { node, .. } => shifts(node, found),
+            AssemblyNode::Binary { lhs, rhs, .. } => {
+                shifts(lhs, found);
+                shifts(rhs, found);
+            }
+            AssemblyNode::Sequence(nodes) => nodes.iter().for_each(|node| shifts(node, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn constant_shift_counts_are_masked_to_operand_width() {
+        let aast = assembly(
+            "int main(void) {\n\
+                 int a = 1;\n\
+                 long b = 1;\n\
+                 unsigned c = 1u;\n\
+                 int n = 3;\n\
+                 return (a << 300) + (b >> 100) + (c >> 33) + (a << 31) + (a >> n);\n\
+             }",
+        );
+        let mut found = Vec::new();
+        shifts(&aast, &mut found);
+        assert_eq!(
+            found,
+            [
+                "Sal(Longword) 12",
+                "Sar(Quadword) 36",
+                "Shr(Longword) 1",
+                "Sal(Longword) 31",
+                "Sar(Longword) CX",
+            ]
+        );
+    }
+}
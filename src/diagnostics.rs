@@ -0,0 +1,3 @@
+pub mod diagnostic;
+pub mod error;
+pub mod renderer;
@@ -0,0 +1,83 @@
+use crate::lexer::span::Span;
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Level {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+            Level::Note => write!(f, "note"),
+            Level::Help => write!(f, "help"),
+        }
+    }
+}
+
+/// A message about the input, optionally pointing at the place in the
+/// source it refers to. Rendered by `diagnostics::renderer`.
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Short text printed next to the caret under the source snippet.
+    pub label: Option<String>,
+    /// Notes and help hints printed after the snippet.
+    pub children: Vec<(Level, String)>,
+}
+
+impl Diagnostic {
+    pub fn new(level: Level, message: impl Into<String>, span: Option<Span>) -> Diagnostic {
+        Diagnostic {
+            level,
+            message: message.into(),
+            span,
+            label: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>, span: &Span) -> Diagnostic {
+        Diagnostic::new(Level::Error, message, Some(span.clone()))
+    }
+
+    pub fn warning(message: impl Into<String>, span: &Span) -> Diagnostic {
+        Diagnostic::new(Level::Warning, message, Some(span.clone()))
+    }
+
+    /// Error without a source location, e.g. a failure in the driver.
+    pub fn bare(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Level::Error, message, None)
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.children.push((Level::Note, note.into()));
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Diagnostic {
+        self.children.push((Level::Help, help.into()));
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(span) = &self.span {
+            write!(f, "{span}: ")?;
+        }
+        write!(f, "{}: {}", self.level, self.message)
+    }
+}
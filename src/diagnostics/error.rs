@@ -0,0 +1,156 @@
+use super::diagnostic::Diagnostic;
+use crate::assembly::error::CodegenError;
+use crate::lexer::error::LexError;
+use crate::parser::error::{ParseError, SemanticError};
+use crate::preprocessor::error::PreprocessError;
+use std::fmt;
+
+/// Exit code when the input program is rejected.
+pub const EXIT_USER_ERROR: i32 = 1;
+/// Exit code when reading or writing files or running external tools fails.
+pub const EXIT_IO_ERROR: i32 = 74;
+/// Exit code for bugs in the compiler itself.
+pub const EXIT_INTERNAL_ERROR: i32 = 70;
+
+#[derive(Debug)]
+pub enum DriverError {
+    NoInput,
+    Io {
+        path: String,
+        error: std::io::Error,
+    },
+    ToolFailed {
+        tool: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+}
+
+impl DriverError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::bare(self.to_string());
+        match self {
+            DriverError::NoInput => diagnostic.with_help("pass the path of a file ending in `.c`"),
+            DriverError::ToolFailed { stderr, .. } if !stderr.trim().is_empty() => {
+                diagnostic.with_note(stderr.trim().to_string())
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriverError::NoInput => write!(f, "no input file"),
+            DriverError::Io { path, error } => write!(f, "`{path}`: {error}"),
+            DriverError::ToolFailed {
+                tool,
+                status: Some(status),
+                ..
+            } => write!(f, "`{tool}` failed with exit code {status}"),
+            DriverError::ToolFailed { tool, .. } => write!(f, "`{tool}` was terminated"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Preprocess(PreprocessError),
+    Lex(LexError),
+    Parse(ParseError),
+    Semantic(SemanticError),
+    Codegen(CodegenError),
+    Driver(DriverError),
+}
+
+impl CompileError {
+    pub fn is_internal(&self) -> bool {
+        match self {
+            CompileError::Semantic(error) => error.is_internal(),
+            CompileError::Codegen(error) => error.is_internal(),
+            _ => false,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            _ if self.is_internal() => EXIT_INTERNAL_ERROR,
+            CompileError::Driver(DriverError::NoInput) => EXIT_USER_ERROR,
+            CompileError::Driver(_)
+            | CompileError::Preprocess(PreprocessError::Io { .. })
+            | CompileError::Codegen(CodegenError::Io { .. }) => EXIT_IO_ERROR,
+            _ => EXIT_USER_ERROR,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = match self {
+            CompileError::Preprocess(error) => error.diagnostic(),
+            CompileError::Lex(error) => error.diagnostic(),
+            CompileError::Parse(error) => error.diagnostic(),
+            CompileError::Semantic(error) => error.diagnostic(),
+            CompileError::Codegen(error) => error.diagnostic(),
+            CompileError::Driver(error) => error.diagnostic(),
+        };
+        if self.is_internal() {
+            let message = format!("internal compiler error: {}", diagnostic.message);
+            Diagnostic {
+                message,
+                ..diagnostic
+            }
+            .with_note("this is a bug in the compiler, not in the input program")
+        } else {
+            diagnostic
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Preprocess(error) => error.fmt(f),
+            CompileError::Lex(error) => error.fmt(f),
+            CompileError::Parse(error) => error.fmt(f),
+            CompileError::Semantic(error) => error.fmt(f),
+            CompileError::Codegen(error) => error.fmt(f),
+            CompileError::Driver(error) => error.fmt(f),
+        }
+    }
+}
+
+impl From<PreprocessError> for CompileError {
+    fn from(error: PreprocessError) -> CompileError {
+        CompileError::Preprocess(error)
+    }
+}
+
+impl From<LexError> for CompileError {
+    fn from(error: LexError) -> CompileError {
+        CompileError::Lex(error)
+    }
+}
+
+impl From<ParseError> for CompileError {
+    fn from(error: ParseError) -> CompileError {
+        CompileError::Parse(error)
+    }
+}
+
+impl From<SemanticError> for CompileError {
+    fn from(error: SemanticError) -> CompileError {
+        CompileError::Semantic(error)
+    }
+}
+
+impl From<CodegenError> for CompileError {
+    fn from(error: CodegenError) -> CompileError {
+        CompileError::Codegen(error)
+    }
+}
+
+impl From<DriverError> for CompileError {
+    fn from(error: DriverError) -> CompileError {
+        CompileError::Driver(error)
+    }
+}
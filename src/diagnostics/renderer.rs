@@ -0,0 +1,168 @@
+use super::diagnostic::{Diagnostic, Level};
+use crate::lexer::span::Span;
+use std::collections::HashMap;
+use std::fs;
+use std::io::IsTerminal;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const CYAN: &str = "\x1b[1;36m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Renders diagnostics in the style of rustc/gcc:
+///
+/// ```text
+/// file.c:3:12: error: message
+///     3 |     return 2 +;
+///       |               ^ label
+///   = note: ...
+///   = help: ...
+/// ```
+pub struct Renderer {
+    color: bool,
+    sources: HashMap<String, Option<String>>,
+}
+
+impl Renderer {
+    pub fn new(color: bool) -> Renderer {
+        Renderer {
+            color,
+            sources: HashMap::new(),
+        }
+    }
+
+    /// Colour is used when stderr is a terminal and `NO_COLOR` is not set.
+    /// Diagnostics are written to stderr, so that is the stream checked,
+    /// rather than stdout, which may be redirected on its own.
+    pub fn for_stderr() -> Renderer {
+        let color = std::io::stderr().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+        Renderer::new(color)
+    }
+
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{style}{text}{RESET}")
+        } else {
+            text.to_string()
+        }
+    }
+
+    fn level_style(level: Level) -> &'static str {
+        match level {
+            Level::Error => RED,
+            Level::Warning => YELLOW,
+            Level::Note | Level::Help => CYAN,
+        }
+    }
+
+    fn source_line(&mut self, span: &Span) -> Option<String> {
+        let source = self
+            .sources
+            .entry(span.file.to_string())
+            .or_insert_with(|| fs::read_to_string(&*span.file).ok())
+            .as_ref()?;
+        source
+            .lines()
+            .nth((span.line as usize).checked_sub(1)?)
+            .map(String::from)
+    }
+
+    pub fn render(&mut self, diagnostic: &Diagnostic) -> String {
+        let style = Renderer::level_style(diagnostic.level);
+        let mut output = String::new();
+        if let Some(span) = &diagnostic.span {
+            output += &self.paint(BOLD, &format!("{span}: "));
+        }
+        output += &self.paint(style, &format!("{}:", diagnostic.level));
+        output += &self.paint(BOLD, &format!(" {}", diagnostic.message));
+        output += "\n";
+
+        let mut gutter = 4;
+        if let Some(span) = &diagnostic.span {
+            if let Some(line) = self.source_line(span) {
+                let number = span.line.to_string();
+                gutter = gutter.max(number.len());
+                let prefix: String = line.chars().take(span.column as usize - 1).collect();
+                let indent: String = prefix
+                    .chars()
+                    .map(|c| if c == '\t' { '\t' } else { ' ' })
+                    .collect();
+                // One caret per character the span covers, which can be
+                // fewer than its bytes.
+                let bytes = span.end.saturating_sub(span.start);
+                let mut covered = 0;
+                let length = line
+                    .chars()
+                    .skip(prefix.chars().count())
+                    .take_while(|c| {
+                        let inside = covered < bytes;
+                        covered += c.len_utf8();
+                        inside
+                    })
+                    .count();
+                let mut underline = "^".repeat(length.max(1));
+                if let Some(label) = &diagnostic.label {
+                    underline += " ";
+                    underline += label;
+                }
+                output += &self.paint(BLUE, &format!("{number:>gutter$} | "));
+                output += &line;
+                output += "\n";
+                output += &self.paint(BLUE, &format!("{:>gutter$} | ", ""));
+                output += &indent;
+                output += &self.paint(style, &underline);
+                output += "\n";
+            }
+        }
+        for (level, text) in &diagnostic.children {
+            output += &format!("{:>gutter$} ", "");
+            output += &self.paint(BOLD, &format!("= {level}:"));
+            output += &format!(" {text}\n");
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::rc::Rc;
+
+    /// The caret line under `line` for a span from byte `start` to `end`
+    /// of it.
+    fn underline(name: &str, line: &str, start: usize, end: usize) -> String {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, line).unwrap();
+        let file: Rc<str> = Rc::from(path.to_str().unwrap());
+        let column = line[..start].chars().count() as u32 + 1;
+        let span = Span::new(file, 1, column, start, end);
+        let output = Renderer::new(false).render(&Diagnostic::error("message", &span));
+        fs::remove_file(&path).unwrap();
+        output.lines().nth(2).unwrap().to_string()
+    }
+
+    #[test]
+    fn underline_counts_characters() {
+        let line = "s = \"h\u{e9}llo\" + x;";
+        let literal = line.find('"').unwrap();
+        let end = line.rfind('"').unwrap() + 1;
+        assert_eq!(
+            underline("renderer_characters.c", line, literal, end),
+            "     |     ^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn underline_stops_at_line_end() {
+        assert_eq!(
+            underline("renderer_line_end.c", "x = 1", 4, 40),
+            "     |     ^"
+        );
+        assert_eq!(
+            underline("renderer_empty.c", "x = 1", 5, 5),
+            "     |      ^"
+        );
+    }
+}
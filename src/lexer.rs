@@ -1 +1,5 @@
+pub mod error;
+#[allow(clippy::module_inception)]
 pub mod lexer;
+pub mod literal;
+pub mod span;
@@ -0,0 +1,52 @@
+use super::literal::LiteralError;
+use super::span::Span;
+use crate::diagnostics::diagnostic::Diagnostic;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum LexError {
+    UnexpectedCharacter { character: char, span: Span },
+    InvalidLiteral { error: LiteralError, span: Span },
+    InvalidLineMarker { span: Span },
+    UnterminatedComment { span: Span },
+}
+
+impl LexError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        match self {
+            LexError::UnexpectedCharacter { span, .. } => {
+                Diagnostic::error(self.to_string(), span).with_label("not a valid token")
+            }
+            LexError::InvalidLiteral { error, span } => {
+                let diagnostic = Diagnostic::error(self.to_string(), span);
+                match error {
+                    LiteralError::InvalidSuffix { .. } => {
+                        diagnostic.with_help("identifiers cannot start with a digit")
+                    }
+                    LiteralError::NoFittingType { kind } if !kind.is_unsigned() => {
+                        diagnostic.with_help("add a `u` suffix to make the constant unsigned")
+                    }
+                    _ => diagnostic,
+                }
+            }
+            LexError::InvalidLineMarker { span } => Diagnostic::error(self.to_string(), span)
+                .with_label("expected `# <line> \"<file>\"`"),
+            LexError::UnterminatedComment { span } => {
+                Diagnostic::error(self.to_string(), span).with_label("comment starts here")
+            }
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::UnexpectedCharacter { character, .. } => {
+                write!(f, "unexpected character `{}`", character.escape_default())
+            }
+            LexError::InvalidLiteral { error, .. } => error.fmt(f),
+            LexError::InvalidLineMarker { .. } => write!(f, "malformed line marker"),
+            LexError::UnterminatedComment { .. } => write!(f, "unterminated comment"),
+        }
+    }
+}
@@ -1,15 +1,61 @@
-use regex::Regex;
+use super::error::LexError;
+use super::literal::{self, Encoding, Literal, LiteralError};
+use super::span::Span;
 use std::collections::VecDeque;
-use std::fs;
-use std::io::{Error, ErrorKind};
+use std::fmt;
+use std::rc::Rc;
 
 #[derive(Clone, PartialEq, Debug, Copy)]
 pub enum Token {
     Identifier,
     Constant,
+    FloatConstant,
+    CharLiteral,
+    StringLiteral,
+    AutoKeyword,
+    BreakKeyword,
+    CaseKeyword,
+    CharKeyword,
+    ConstKeyword,
+    ContinueKeyword,
+    DefaultKeyword,
+    DoKeyword,
+    DoubleKeyword,
+    ElseKeyword,
+    EnumKeyword,
+    ExternKeyword,
+    FloatKeyword,
+    ForKeyword,
+    GotoKeyword,
+    IfKeyword,
+    InlineKeyword,
     IntKeyword,
-    VoidKeyword,
+    LongKeyword,
+    RegisterKeyword,
+    RestrictKeyword,
     ReturnKeyword,
+    ShortKeyword,
+    SignedKeyword,
+    SizeofKeyword,
+    StaticKeyword,
+    StructKeyword,
+    SwitchKeyword,
+    TypedefKeyword,
+    UnionKeyword,
+    UnsignedKeyword,
+    VoidKeyword,
+    VolatileKeyword,
+    WhileKeyword,
+    AlignasKeyword,
+    AlignofKeyword,
+    AtomicKeyword,
+    BoolKeyword,
+    ComplexKeyword,
+    GenericKeyword,
+    ImaginaryKeyword,
+    NoreturnKeyword,
+    StaticAssertKeyword,
+    ThreadLocalKeyword,
     OpenParenthesis,
     CloseParenthesis,
     OpenBrace,
@@ -18,108 +64,758 @@ pub enum Token {
     Tilde,
     Hyphen,
     TwoHyphens,
+    Plus,
+    TwoPluses,
+    Asterisk,
+    Slash,
+    Percent,
+    Ampersand,
+    TwoAmpersands,
+    Pipe,
+    TwoPipes,
+    Caret,
+    TwoLessThans,
+    TwoGreaterThans,
+    Exclamation,
+    TwoEquals,
+    ExclamationEqual,
+    LessThan,
+    GreaterThan,
+    LessThanEqual,
+    GreaterThanEqual,
+    Equal,
+    PlusEqual,
+    HyphenEqual,
+    AsteriskEqual,
+    SlashEqual,
+    PercentEqual,
+    AmpersandEqual,
+    PipeEqual,
+    CaretEqual,
+    TwoLessThansEqual,
+    TwoGreaterThansEqual,
+    QuestionMark,
+    Colon,
+    Comma,
+    Period,
+    Arrow,
+    OpenBracket,
+    CloseBracket,
+    Ellipsis,
+    EndOfFile,
 }
 
+/// Punctuators with their spelling, longest first so the first match is
+/// the maximal munch.
+const PUNCTUATORS: [(&str, Token); 46] = [
+    ("<<=", Token::TwoLessThansEqual),
+    (">>=", Token::TwoGreaterThansEqual),
+    ("...", Token::Ellipsis),
+    ("--", Token::TwoHyphens),
+    ("++", Token::TwoPluses),
+    ("&&", Token::TwoAmpersands),
+    ("||", Token::TwoPipes),
+    ("<<", Token::TwoLessThans),
+    (">>", Token::TwoGreaterThans),
+    ("==", Token::TwoEquals),
+    ("!=", Token::ExclamationEqual),
+    ("<=", Token::LessThanEqual),
+    (">=", Token::GreaterThanEqual),
+    ("+=", Token::PlusEqual),
+    ("-=", Token::HyphenEqual),
+    ("*=", Token::AsteriskEqual),
+    ("/=", Token::SlashEqual),
+    ("%=", Token::PercentEqual),
+    ("&=", Token::AmpersandEqual),
+    ("|=", Token::PipeEqual),
+    ("^=", Token::CaretEqual),
+    ("->", Token::Arrow),
+    ("(", Token::OpenParenthesis),
+    (")", Token::CloseParenthesis),
+    ("{", Token::OpenBrace),
+    ("}", Token::CloseBrace),
+    ("[", Token::OpenBracket),
+    ("]", Token::CloseBracket),
+    (";", Token::Semicolon),
+    ("~", Token::Tilde),
+    ("-", Token::Hyphen),
+    ("+", Token::Plus),
+    ("*", Token::Asterisk),
+    ("/", Token::Slash),
+    ("%", Token::Percent),
+    ("&", Token::Ampersand),
+    ("|", Token::Pipe),
+    ("^", Token::Caret),
+    ("!", Token::Exclamation),
+    ("<", Token::LessThan),
+    (">", Token::GreaterThan),
+    ("=", Token::Equal),
+    ("?", Token::QuestionMark),
+    (":", Token::Colon),
+    (",", Token::Comma),
+    (".", Token::Period),
+];
+
+const KEYWORDS: [(&str, Token); 44] = [
+    ("auto", Token::AutoKeyword),
+    ("break", Token::BreakKeyword),
+    ("case", Token::CaseKeyword),
+    ("char", Token::CharKeyword),
+    ("const", Token::ConstKeyword),
+    ("continue", Token::ContinueKeyword),
+    ("default", Token::DefaultKeyword),
+    ("do", Token::DoKeyword),
+    ("double", Token::DoubleKeyword),
+    ("else", Token::ElseKeyword),
+    ("enum", Token::EnumKeyword),
+    ("extern", Token::ExternKeyword),
+    ("float", Token::FloatKeyword),
+    ("for", Token::ForKeyword),
+    ("goto", Token::GotoKeyword),
+    ("if", Token::IfKeyword),
+    ("inline", Token::InlineKeyword),
+    ("int", Token::IntKeyword),
+    ("long", Token::LongKeyword),
+    ("register", Token::RegisterKeyword),
+    ("restrict", Token::RestrictKeyword),
+    ("return", Token::ReturnKeyword),
+    ("short", Token::ShortKeyword),
+    ("signed", Token::SignedKeyword),
+    ("sizeof", Token::SizeofKeyword),
+    ("static", Token::StaticKeyword),
+    ("struct", Token::StructKeyword),
+    ("switch", Token::SwitchKeyword),
+    ("typedef", Token::TypedefKeyword),
+    ("union", Token::UnionKeyword),
+    ("unsigned", Token::UnsignedKeyword),
+    ("void", Token::VoidKeyword),
+    ("volatile", Token::VolatileKeyword),
+    ("while", Token::WhileKeyword),
+    ("_Alignas", Token::AlignasKeyword),
+    ("_Alignof", Token::AlignofKeyword),
+    ("_Atomic", Token::AtomicKeyword),
+    ("_Bool", Token::BoolKeyword),
+    ("_Complex", Token::ComplexKeyword),
+    ("_Generic", Token::GenericKeyword),
+    ("_Imaginary", Token::ImaginaryKeyword),
+    ("_Noreturn", Token::NoreturnKeyword),
+    ("_Static_assert", Token::StaticAssertKeyword),
+    ("_Thread_local", Token::ThreadLocalKeyword),
+];
+
 impl Token {
-    const VALUES: [Self; 13] = [
-        Self::Identifier,
-        Self::Constant,
-        Self::IntKeyword,
-        Self::VoidKeyword,
-        Self::ReturnKeyword,
-        Self::OpenParenthesis,
-        Self::CloseParenthesis,
-        Self::OpenBrace,
-        Self::CloseBrace,
-        Self::Semicolon,
-        Self::Tilde,
-        Self::Hyphen,
-        Self::TwoHyphens,
-    ];
-
-    fn expr(&self) -> &str {
+    fn keyword(text: &str) -> Option<Token> {
+        KEYWORDS
+            .iter()
+            .find(|(spelling, _)| *spelling == text)
+            .map(|(_, token)| *token)
+    }
+
+    pub fn is_keyword(&self) -> bool {
+        KEYWORDS.iter().any(|(_, token)| token == self)
+    }
+
+    fn spelling(&self) -> Option<&'static str> {
+        PUNCTUATORS
+            .iter()
+            .chain(KEYWORDS.iter())
+            .find(|(_, token)| token == self)
+            .map(|(spelling, _)| *spelling)
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match *self {
-            Token::Identifier => r"[a-zA-Z_]\w*\b",
-            Token::Constant => r"[0-9]+\b",
-            Token::IntKeyword => r"int\b",
-            Token::VoidKeyword => r"void\b",
-            Token::ReturnKeyword => r"return\b",
-            Token::OpenParenthesis => r"\(",
-            Token::CloseParenthesis => r"\)",
-            Token::OpenBrace => r"\{",
-            Token::CloseBrace => r"\}",
-            Token::Semicolon => r";",
-            Token::Tilde => r"~",
-            Token::Hyphen => r"-",
-            Token::TwoHyphens => r"--",
-        }
-    }
-
-    fn regex() -> [(Token, Regex); 13] {
-        Token::VALUES
-            .clone()
-            .map(|x| (x, Regex::new(x.expr()).unwrap()))
+            Token::Identifier => write!(f, "identifier"),
+            Token::Constant => write!(f, "constant"),
+            Token::FloatConstant => write!(f, "floating constant"),
+            Token::CharLiteral => write!(f, "character constant"),
+            Token::StringLiteral => write!(f, "string literal"),
+            Token::EndOfFile => write!(f, "end of file"),
+            _ => write!(f, "`{}`", self.spelling().unwrap_or("?")),
+        }
     }
 }
 
+/// A comment, kept as trivia by a lexer created with
+/// `Lexer::retaining_comments`.
+#[derive(Clone, Debug)]
+pub struct Comment {
+    /// Full text including the `//` or `/* */` delimiters.
+    pub text: String,
+    pub span: Span,
+}
+
 #[derive(Debug)]
 pub struct TokenValue {
     pub token: Token,
     pub data: Option<String>,
+    /// Decoded value for constants and literals.
+    pub literal: Option<Literal>,
+    pub span: Span,
+    /// Comments between the previous token and this one, and between the
+    /// pieces of a concatenated string literal. Always empty unless the
+    /// lexer retains comments.
+    pub comments: Vec<Comment>,
+}
+
+fn is_identifier_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_'
 }
 
-pub fn lex(preprocessed: &str, debug_mode: bool) -> std::io::Result<VecDeque<TokenValue>> {
-    let mut to_parse = fs::read_to_string(preprocessed)?;
-    let whitespace = Regex::new(r"\A\s+").unwrap();
-    let regex = Token::regex();
-    let mut tokens: VecDeque<TokenValue> = VecDeque::new();
-    while !to_parse.is_empty() {
-        if whitespace.is_match(&to_parse) {
-            to_parse = String::from(to_parse.trim_start());
+fn is_identifier_continue(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// Cursor based lexer walking the input once, byte by byte.
+pub struct Lexer<'a> {
+    source: &'a str,
+    bytes: &'a [u8],
+    file: Rc<str>,
+    offset: usize,
+    line: u32,
+    column: u32,
+    retain_comments: bool,
+    /// Comments seen since the last token, if they are retained.
+    comments: Vec<Comment>,
+}
+
+/// Position in the input where a token starts.
+#[derive(Clone, Copy)]
+struct Mark {
+    offset: usize,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str, file: &str) -> Lexer<'a> {
+        Lexer {
+            source,
+            bytes: source.as_bytes(),
+            file: Rc::from(file),
+            offset: 0,
+            line: 1,
+            column: 1,
+            retain_comments: false,
+            comments: Vec::new(),
+        }
+    }
+
+    /// Makes the lexer keep comments, attached to the token following
+    /// them, for tools such as formatters which must not lose them.
+    pub fn retaining_comments(mut self) -> Lexer<'a> {
+        self.retain_comments = true;
+        self
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.get(self.offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<u8> {
+        let c = self.peek()?;
+        self.offset += 1;
+        if c == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if c & 0xC0 != 0x80 {
+            // Continuation bytes of a UTF-8 sequence do not start a new column.
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn bump_while(&mut self, predicate: impl Fn(u8) -> bool) {
+        while self.peek().is_some_and(&predicate) {
+            self.bump();
+        }
+    }
+
+    fn mark(&self) -> Mark {
+        Mark {
+            offset: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    fn span(&self, mark: Mark) -> Span {
+        self.span_between(mark, self.mark())
+    }
+
+    fn span_between(&self, start: Mark, end: Mark) -> Span {
+        Span::new(
+            self.file.clone(),
+            start.line,
+            start.column,
+            start.offset,
+            end.offset,
+        )
+    }
+
+    /// Skips whitespace, comments and line markers.
+    fn skip_whitespace(&mut self) -> Result<(), LexError> {
+        loop {
+            self.bump_while(|c| c.is_ascii_whitespace() || c == b'\x0b');
+            let rest = &self.bytes[self.offset..];
+            if rest.starts_with(b"//") || rest.starts_with(b"/*") {
+                self.comment()?;
+            } else if self.peek() != Some(b'#') || !self.at_line_start() || !self.line_marker()? {
+                return Ok(());
+            }
+        }
+    }
+
+    fn comment(&mut self) -> Result<(), LexError> {
+        let mark = self.mark();
+        if self.bytes[self.offset + 1] == b'/' {
+            // A line comment ends at the first newline not spliced away by
+            // a backslash.
+            while let Some(c) = self.peek() {
+                let spliced = self.source[..self.offset]
+                    .trim_end_matches('\r')
+                    .ends_with('\\');
+                if c == b'\n' && !spliced {
+                    break;
+                }
+                self.bump();
+            }
         } else {
-            let mut candidates: Vec<_> = regex
-                .iter()
-                .map(|x| (x.0, x.1.find(&to_parse)))
-                .filter(|x| x.1.is_some() && x.1.unwrap().start() == 0)
-                .map(|x| (x.0, x.1.unwrap()))
-                .collect();
-            if candidates.is_empty() {
-                return Err(Error::new(ErrorKind::InvalidInput, "Bad token"));
+            let Some(end) = self.source[self.offset + 2..].find("*/") else {
+                self.bump();
+                self.bump();
+                return Err(LexError::UnterminatedComment {
+                    span: self.span(mark),
+                });
+            };
+            for _ in 0..end + 4 {
+                self.bump();
             }
-            candidates.sort_by(|a, b| {
-                let dynamic = [Token::Constant, Token::Identifier];
-                let b_len = b.1.len()
-                    + if dynamic.contains(&b.0) && !dynamic.contains(&a.0) {
-                        0
-                    } else {
-                        1
-                    };
-                let a_len = a.1.len()
-                    + if dynamic.contains(&a.0) && !dynamic.contains(&b.0) {
-                        0
-                    } else {
-                        1
-                    };
-                b_len.cmp(&a_len)
+        }
+        if self.retain_comments {
+            self.comments.push(Comment {
+                text: self.text(mark).to_string(),
+                span: self.span(mark),
             });
+        }
+        Ok(())
+    }
 
-            let res = candidates.first().unwrap();
-            if res.0 == Token::Identifier || res.0 == Token::Constant {
-                tokens.push_back(TokenValue {
-                    token: res.0,
-                    data: Some(String::from(res.1.as_str())),
-                });
-            } else {
-                tokens.push_back(TokenValue {
-                    token: res.0,
-                    data: None,
-                });
+    fn at_line_start(&self) -> bool {
+        let line = self.source[..self.offset].rsplit('\n').next().unwrap();
+        line.bytes()
+            .all(|c| c.is_ascii_whitespace() || c == b'\x0b')
+    }
+
+    /// Consumes a line marker `# 12 "file.h" 1` or `#line 12 "file.h"`
+    /// left by the preprocessor, so that the following tokens get the
+    /// location they had in the original source. Returns whether the line
+    /// was one.
+    fn line_marker(&mut self) -> Result<bool, LexError> {
+        let rest = &self.source[self.offset..];
+        let length = rest.find('\n').unwrap_or(rest.len());
+        let directive = rest[1..length].trim_start();
+        let directive = match directive.strip_prefix("line") {
+            Some(after) if after.starts_with([' ', '\t']) => after.trim_start(),
+            Some(_) => return Ok(false),
+            None => directive,
+        };
+        if !directive.starts_with(|c: char| c.is_ascii_digit()) {
+            return Ok(false);
+        }
+        let invalid = LexError::InvalidLineMarker {
+            span: Span::new(
+                self.file.clone(),
+                self.line,
+                self.column,
+                self.offset,
+                self.offset + length,
+            ),
+        };
+        let digits = directive
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(directive.len());
+        let Ok(line) = directive[..digits].parse::<u32>() else {
+            return Err(invalid);
+        };
+        let mut after = directive[digits..].trim();
+        let mut file = None;
+        if let Some(quoted) = after.strip_prefix('"') {
+            let mut name = String::new();
+            let mut chars = quoted.char_indices();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => name.extend(chars.next().map(|(_, c)| c)),
+                    Some((index, '"')) => {
+                        after = &quoted[index + 1..];
+                        break;
+                    }
+                    Some((_, c)) => name.push(c),
+                    None => return Err(invalid),
+                }
             }
-            to_parse = to_parse[res.1.end()..].to_string();
+            file = Some(name);
+        }
+        // Line markers may end with flags, e.g. `1` for entering a header.
+        if !after
+            .bytes()
+            .all(|c| c.is_ascii_digit() || c == b' ' || c == b'\t')
+        {
+            return Err(invalid);
         }
+        for _ in 0..=length {
+            self.bump();
+        }
+        self.line = line;
+        self.column = 1;
+        if let Some(file) = file {
+            self.file = Rc::from(file);
+        }
+        Ok(true)
     }
+
+    fn text(&self, mark: Mark) -> &'a str {
+        &self.source[mark.offset..self.offset]
+    }
+
+    fn token(&self, token: Token, data: Option<String>, mark: Mark) -> TokenValue {
+        TokenValue {
+            token,
+            data,
+            literal: None,
+            span: self.span(mark),
+            comments: Vec::new(),
+        }
+    }
+
+    fn identifier(&mut self, mark: Mark) -> TokenValue {
+        self.bump_while(is_identifier_continue);
+        let text = self.text(mark);
+        match Token::keyword(text) {
+            Some(keyword) => self.token(keyword, None, mark),
+            None => self.token(Token::Identifier, Some(text.to_string()), mark),
+        }
+    }
+
+    /// Quote character if a character constant or string literal, possibly
+    /// with an encoding prefix, starts at the cursor.
+    fn literal_prefix(&self) -> Option<u8> {
+        let rest = &self.bytes[self.offset..];
+        ["u8", "u", "U", "L", ""].iter().find_map(|prefix| {
+            let quote = *rest.get(prefix.len())?;
+            let starts = rest.starts_with(prefix.as_bytes())
+                && (quote == b'"' || (quote == b'\'' && *prefix != "u8"));
+            starts.then_some(quote)
+        })
+    }
+
+    /// Consumes a quoted literal, returning its encoding and the text
+    /// between the quotes.
+    fn quoted(&mut self) -> Result<(Encoding, &'a str), LexError> {
+        let mark = self.mark();
+        let quote = self.literal_prefix().unwrap();
+        self.bump_while(|c| c != quote);
+        let encoding = Encoding::from_prefix(self.text(mark)).unwrap();
+        self.bump();
+        let body_start = self.offset;
+        loop {
+            match self.peek() {
+                Some(c) if c == quote => break,
+                Some(b'\\') => {
+                    self.bump();
+                    if self.peek().is_some_and(|c| c != b'\n') {
+                        self.bump();
+                    }
+                }
+                None | Some(b'\n') => {
+                    return Err(LexError::InvalidLiteral {
+                        error: LiteralError::Unterminated {
+                            string: quote == b'"',
+                        },
+                        span: self.span(mark),
+                    })
+                }
+                Some(_) => {
+                    self.bump();
+                }
+            }
+        }
+        let body = &self.source[body_start..self.offset];
+        self.bump();
+        Ok((encoding, body))
+    }
+
+    fn character(&mut self, mark: Mark) -> Result<TokenValue, LexError> {
+        let (encoding, body) = self.quoted()?;
+        let literal =
+            literal::character(body, encoding).map_err(|error| LexError::InvalidLiteral {
+                error,
+                span: self.span(mark),
+            })?;
+        let text = self.text(mark).to_string();
+        Ok(TokenValue {
+            literal: Some(literal),
+            ..self.token(Token::CharLiteral, Some(text), mark)
+        })
+    }
+
+    /// Lexes a string literal together with all directly following string
+    /// literals, which C concatenates into one.
+    fn string(&mut self, mark: Mark) -> Result<TokenValue, LexError> {
+        let mut pieces = Vec::new();
+        let mut span = self.span(mark);
+        while self.literal_prefix() == Some(b'"') {
+            pieces.push(self.quoted()?);
+            span = self.span(mark);
+            self.skip_whitespace()?;
+        }
+        let pieces: Vec<_> = pieces.into_iter().map(|(e, body)| (body, e)).collect();
+        let literal = literal::string(&pieces).map_err(|error| LexError::InvalidLiteral {
+            error,
+            span: span.clone(),
+        })?;
+        Ok(TokenValue {
+            token: Token::StringLiteral,
+            data: Some(self.source[mark.offset..span.end].to_string()),
+            literal: Some(literal),
+            span,
+            comments: Vec::new(),
+        })
+    }
+
+    /// Lexes a preprocessing number and decodes it as an integer or
+    /// floating constant.
+    fn number(&mut self, mark: Mark) -> Result<TokenValue, LexError> {
+        let mut previous = 0u8;
+        while let Some(c) = self.peek() {
+            let sign = (c == b'+' || c == b'-') && b"eEpP".contains(&previous);
+            if !(is_identifier_continue(c) || c == b'.' || sign) {
+                break;
+            }
+            previous = c;
+            self.bump();
+        }
+        let text = self.text(mark);
+        let (token, literal) = if literal::is_floating(text) {
+            (Token::FloatConstant, literal::floating(text))
+        } else {
+            (Token::Constant, literal::integer(text))
+        };
+        let literal = literal.map_err(|error| LexError::InvalidLiteral {
+            error,
+            span: self.span(mark),
+        })?;
+        Ok(TokenValue {
+            literal: Some(literal),
+            ..self.token(token, Some(text.to_string()), mark)
+        })
+    }
+
+    fn punctuator(&mut self, mark: Mark) -> Result<TokenValue, LexError> {
+        let rest = &self.bytes[self.offset..];
+        let found = PUNCTUATORS
+            .iter()
+            .find(|(spelling, _)| rest.starts_with(spelling.as_bytes()));
+        match found {
+            Some((spelling, token)) => {
+                for _ in 0..spelling.len() {
+                    self.bump();
+                }
+                Ok(self.token(*token, None, mark))
+            }
+            None => {
+                let character = self.source[self.offset..].chars().next().unwrap();
+                for _ in 0..character.len_utf8() {
+                    self.bump();
+                }
+                Err(LexError::UnexpectedCharacter {
+                    character,
+                    span: self.span(mark),
+                })
+            }
+        }
+    }
+
+    fn next_token(&mut self) -> Result<TokenValue, LexError> {
+        self.skip_whitespace()?;
+        let mark = self.mark();
+        match self.peek() {
+            None => Ok(self.token(Token::EndOfFile, None, mark)),
+            Some(_) if self.literal_prefix() == Some(b'\'') => self.character(mark),
+            Some(_) if self.literal_prefix() == Some(b'"') => self.string(mark),
+            Some(c) if is_identifier_start(c) => Ok(self.identifier(mark)),
+            Some(c) if c.is_ascii_digit() => self.number(mark),
+            Some(b'.')
+                if self
+                    .bytes
+                    .get(self.offset + 1)
+                    .is_some_and(u8::is_ascii_digit) =>
+            {
+                self.number(mark)
+            }
+            Some(_) => self.punctuator(mark),
+        }
+    }
+
+    pub fn tokenize(mut self) -> Result<VecDeque<TokenValue>, LexError> {
+        let mut tokens: VecDeque<TokenValue> = VecDeque::new();
+        loop {
+            let mut token = self.next_token()?;
+            // A string literal looks past its last piece for another one,
+            // so comments after it belong to the next token.
+            let before = self
+                .comments
+                .iter()
+                .take_while(|comment| comment.span.start < token.span.end)
+                .count();
+            let after = self.comments.split_off(before);
+            token.comments = std::mem::replace(&mut self.comments, after);
+            let done = token.token == Token::EndOfFile;
+            tokens.push_back(token);
+            if done {
+                return Ok(tokens);
+            }
+        }
+    }
+}
+
+pub fn lex(source: &str, file: &str, debug_mode: bool) -> Result<VecDeque<TokenValue>, LexError> {
+    let tokens = Lexer::new(source, file).tokenize()?;
     if debug_mode {
         println!("{:?}", tokens)
     };
     Ok(tokens)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn comments(source: &str) -> Vec<(Token, Vec<String>)> {
+        Lexer::new(source, "test.c")
+            .retaining_comments()
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| {
+                let texts = token.comments.into_iter().map(|c| c.text).collect();
+                (token.token, texts)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn comments_attach_to_following_token() {
+        assert_eq!(
+            comments("// a\nx /* b */ = 1; /* c */"),
+            vec![
+                (Token::Identifier, vec!["// a".to_string()]),
+                (Token::Equal, vec!["/* b */".to_string()]),
+                (Token::Constant, vec![]),
+                (Token::Semicolon, vec![]),
+                (Token::EndOfFile, vec!["/* c */".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_after_string_attach_to_following_token() {
+        assert_eq!(
+            comments("x = \"a\" /* inside */ \"b\" /* after */ ;"),
+            vec![
+                (Token::Identifier, vec![]),
+                (Token::Equal, vec![]),
+                (Token::StringLiteral, vec!["/* inside */".to_string()]),
+                (Token::Semicolon, vec!["/* after */".to_string()]),
+                (Token::EndOfFile, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_dropped_by_default() {
+        let tokens = Lexer::new("x /* a */ ;", "test.c").tokenize().unwrap();
+        assert!(tokens.iter().all(|token| token.comments.is_empty()));
+    }
+
+    /// File, line and column of each token in `source`.
+    fn locations(source: &str) -> Vec<(String, u32, u32)> {
+        Lexer::new(source, "test.c")
+            .tokenize()
+            .unwrap()
+            .into_iter()
+            .map(|token| {
+                (
+                    token.span.file.to_string(),
+                    token.span.line,
+                    token.span.column,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn line_markers_set_file_and_line() {
+        let location = |file: &str, line, column| (file.to_string(), line, column);
+        assert_eq!(
+            locations("a\n# 10 \"h.h\" 1\n  b\nc\n# 3 \"test.c\" 2\nd\n#line 7\ne"),
+            vec![
+                location("test.c", 1, 1),
+                location("h.h", 10, 3),
+                location("h.h", 11, 1),
+                location("test.c", 3, 1),
+                location("test.c", 7, 1),
+                location("test.c", 7, 2),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_marker_file_names_are_unescaped() {
+        assert_eq!(
+            locations("# 2 \"dir\\\\a \\\"b\\\".h\"\nx")[0],
+            ("dir\\a \"b\".h".to_string(), 2, 1)
+        );
+    }
+
+    #[test]
+    fn malformed_line_markers() {
+        for source in [
+            "# 1 \"unterminated\nx",
+            "# 1 \"a.c\" flag\nx",
+            "# 99999999999\nx",
+        ] {
+            assert!(
+                matches!(
+                    Lexer::new(source, "test.c").tokenize(),
+                    Err(LexError::InvalidLineMarker { .. })
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn preprocessed_tokens_map_to_original_lines() {
+        let source = "int a;\n\n#define X 1\n\n\n\nint b = X;\n#line 50 \"other.c\"\nint c;\n";
+        let (output, _) =
+            crate::preprocessor::preprocessor::preprocess(source, "test.c", &[], false).unwrap();
+        let lines: Vec<_> = locations(&output)
+            .into_iter()
+            .map(|(file, line, _)| (file, line))
+            .collect();
+        let at = |file: &str, line| (file.to_string(), line);
+        assert_eq!(
+            lines,
+            [
+                vec![at("test.c", 1); 3],
+                vec![at("test.c", 7); 5],
+                vec![at("other.c", 50); 3],
+                vec![at("other.c", 51)],
+            ]
+            .concat()
+        );
+    }
+}
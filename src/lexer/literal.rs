@@ -0,0 +1,1017 @@
+use std::fmt;
+
+/// Type of an integer constant, chosen from its value and suffix.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum IntegerKind {
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl IntegerKind {
+    pub fn is_unsigned(&self) -> bool {
+        matches!(
+            self,
+            IntegerKind::UnsignedInt | IntegerKind::UnsignedLong | IntegerKind::UnsignedLongLong
+        )
+    }
+
+    /// Largest value representable in the type (LP64 data model).
+    pub fn max_value(&self) -> u64 {
+        match self {
+            IntegerKind::Int => i32::MAX as u64,
+            IntegerKind::UnsignedInt => u32::MAX as u64,
+            IntegerKind::Long | IntegerKind::LongLong => i64::MAX as u64,
+            IntegerKind::UnsignedLong | IntegerKind::UnsignedLongLong => u64::MAX,
+        }
+    }
+}
+
+impl fmt::Display for IntegerKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegerKind::Int => write!(f, "int"),
+            IntegerKind::UnsignedInt => write!(f, "unsigned int"),
+            IntegerKind::Long => write!(f, "long"),
+            IntegerKind::UnsignedLong => write!(f, "unsigned long"),
+            IntegerKind::LongLong => write!(f, "long long"),
+            IntegerKind::UnsignedLongLong => write!(f, "unsigned long long"),
+        }
+    }
+}
+
+/// Type of a floating constant, chosen from its suffix.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FloatKind {
+    Float,
+    Double,
+    LongDouble,
+}
+
+impl fmt::Display for FloatKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FloatKind::Float => write!(f, "float"),
+            FloatKind::Double => write!(f, "double"),
+            FloatKind::LongDouble => write!(f, "long double"),
+        }
+    }
+}
+
+/// Encoding prefix of a character constant or string literal.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Encoding {
+    Plain,
+    /// `u8"..."`
+    Utf8,
+    /// `L'x'`, `L"..."` (`wchar_t`, 32 bit on Linux)
+    Wide,
+    /// `u'x'`, `u"..."` (`char16_t`)
+    Utf16,
+    /// `U'x'`, `U"..."` (`char32_t`)
+    Utf32,
+}
+
+impl Encoding {
+    pub fn from_prefix(prefix: &str) -> Option<Encoding> {
+        match prefix {
+            "" => Some(Encoding::Plain),
+            "u8" => Some(Encoding::Utf8),
+            "L" => Some(Encoding::Wide),
+            "u" => Some(Encoding::Utf16),
+            "U" => Some(Encoding::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Encoding::Plain => "",
+            Encoding::Utf8 => "u8",
+            Encoding::Wide => "L",
+            Encoding::Utf16 => "u",
+            Encoding::Utf32 => "U",
+        }
+    }
+
+    /// Type of the elements of a string literal with this encoding.
+    pub fn element_type(&self) -> &'static str {
+        match self {
+            Encoding::Plain | Encoding::Utf8 => "char",
+            Encoding::Wide => "wchar_t",
+            Encoding::Utf16 => "char16_t",
+            Encoding::Utf32 => "char32_t",
+        }
+    }
+
+    /// Size in bytes of one code unit.
+    pub fn unit_size(&self) -> usize {
+        match self {
+            Encoding::Plain | Encoding::Utf8 => 1,
+            Encoding::Utf16 => 2,
+            Encoding::Wide | Encoding::Utf32 => 4,
+        }
+    }
+
+    fn max_unit(&self) -> u32 {
+        match self.unit_size() {
+            1 => u8::MAX as u32,
+            2 => u16::MAX as u32,
+            _ => u32::MAX,
+        }
+    }
+
+    fn push_char(&self, c: char, units: &mut Vec<u32>) {
+        match self.unit_size() {
+            1 => {
+                let mut buffer = [0u8; 4];
+                units.extend(c.encode_utf8(&mut buffer).bytes().map(u32::from));
+            }
+            2 => {
+                let mut buffer = [0u16; 2];
+                units.extend(c.encode_utf16(&mut buffer).iter().map(|x| u32::from(*x)));
+            }
+            _ => units.push(c as u32),
+        }
+    }
+}
+
+/// Decoded value of a literal token.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Literal {
+    Integer {
+        value: u64,
+        kind: IntegerKind,
+    },
+    /// `value` holds the bits of the `f64` nearest to the constant; for
+    /// `float` constants it is first rounded to the nearest `f32`. `long
+    /// double` constants are stored with double precision.
+    Float {
+        value: u64,
+        kind: FloatKind,
+    },
+    /// Character constants have type `int` (or the prefix's type), `value`
+    /// is already converted to it.
+    Character {
+        value: i64,
+        encoding: Encoding,
+    },
+    /// Code units of a string literal encoded little-endian, without the
+    /// terminating null.
+    String {
+        bytes: Vec<u8>,
+        encoding: Encoding,
+    },
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum LiteralError {
+    InvalidDigit {
+        digit: char,
+        base: &'static str,
+    },
+    MissingDigits {
+        base: &'static str,
+    },
+    InvalidSuffix {
+        suffix: String,
+        constant: &'static str,
+    },
+    MissingExponentDigits,
+    MissingHexExponent,
+    FloatOutOfRange {
+        kind: FloatKind,
+    },
+    TooLarge,
+    NoFittingType {
+        kind: IntegerKind,
+    },
+    UnknownEscape {
+        escape: char,
+    },
+    MissingEscapeDigits {
+        escape: char,
+    },
+    EscapeOutOfRange {
+        escape: String,
+    },
+    InvalidUniversalCharacter {
+        code_point: u32,
+    },
+    EmptyCharacter,
+    CharacterTooLong,
+    Unterminated {
+        string: bool,
+    },
+    IncompatibleConcatenation {
+        first: Encoding,
+        second: Encoding,
+    },
+}
+
+impl fmt::Display for LiteralError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LiteralError::InvalidDigit { digit, base } => {
+                write!(f, "invalid digit `{digit}` in {base} constant")
+            }
+            LiteralError::MissingDigits { base } => write!(f, "{base} constant has no digits"),
+            LiteralError::InvalidSuffix { suffix, constant } => {
+                write!(f, "invalid suffix `{suffix}` on {constant} constant")
+            }
+            LiteralError::MissingExponentDigits => write!(f, "exponent has no digits"),
+            LiteralError::MissingHexExponent => {
+                write!(f, "hexadecimal floating constant requires an exponent")
+            }
+            LiteralError::FloatOutOfRange { kind } => {
+                write!(f, "floating constant exceeds range of `{kind}`")
+            }
+            LiteralError::TooLarge => write!(f, "integer constant is too large for any type"),
+            LiteralError::NoFittingType { kind } => {
+                write!(f, "integer constant is too large for type `{kind}`")
+            }
+            LiteralError::UnknownEscape { escape } => {
+                write!(f, "unknown escape sequence `\\{}`", escape.escape_default())
+            }
+            LiteralError::MissingEscapeDigits { escape } => {
+                write!(f, "`\\{escape}` used with no following hex digits")
+            }
+            LiteralError::EscapeOutOfRange { escape } => {
+                write!(f, "escape sequence `{escape}` out of range")
+            }
+            LiteralError::InvalidUniversalCharacter { code_point } => {
+                write!(
+                    f,
+                    "`\\u{code_point:04X}` is not a valid universal character"
+                )
+            }
+            LiteralError::EmptyCharacter => write!(f, "empty character constant"),
+            LiteralError::CharacterTooLong => {
+                write!(f, "character constant too long for its type")
+            }
+            LiteralError::Unterminated { string: true } => {
+                write!(f, "missing terminating `\"` character")
+            }
+            LiteralError::Unterminated { string: false } => {
+                write!(f, "missing terminating `'` character")
+            }
+            LiteralError::IncompatibleConcatenation { first, second } => write!(
+                f,
+                "concatenation of string literals with prefixes `{}` and `{}`",
+                first.prefix(),
+                second.prefix()
+            ),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Suffix {
+    None,
+    Long,
+    LongLong,
+}
+
+fn parse_suffix(suffix: &str) -> Result<(bool, Suffix), LiteralError> {
+    let invalid = || LiteralError::InvalidSuffix {
+        suffix: suffix.to_string(),
+        constant: "integer",
+    };
+    let (unsigned, rest) = match suffix.strip_prefix(['u', 'U']) {
+        Some(rest) => (true, rest),
+        None => (false, suffix),
+    };
+    let (long, rest) = match rest {
+        _ if rest.starts_with("ll") || rest.starts_with("LL") => (Suffix::LongLong, &rest[2..]),
+        _ if rest.starts_with(['l', 'L']) => (Suffix::Long, &rest[1..]),
+        _ => (Suffix::None, rest),
+    };
+    let (unsigned, rest) = match rest.strip_prefix(['u', 'U']) {
+        Some(rest) if !unsigned && long != Suffix::None => (true, rest),
+        _ => (unsigned, rest),
+    };
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+    Ok((unsigned, long))
+}
+
+/// Candidate types of an integer constant in order of preference (C17
+/// 6.4.4.1p5).
+fn candidates(decimal: bool, unsigned: bool, long: Suffix) -> &'static [IntegerKind] {
+    use IntegerKind::*;
+    match (unsigned, long, decimal) {
+        (false, Suffix::None, true) => &[Int, Long, LongLong],
+        (false, Suffix::None, false) => &[
+            Int,
+            UnsignedInt,
+            Long,
+            UnsignedLong,
+            LongLong,
+            UnsignedLongLong,
+        ],
+        (true, Suffix::None, _) => &[UnsignedInt, UnsignedLong, UnsignedLongLong],
+        (false, Suffix::Long, true) => &[Long, LongLong],
+        (false, Suffix::Long, false) => &[Long, UnsignedLong, LongLong, UnsignedLongLong],
+        (true, Suffix::Long, _) => &[UnsignedLong, UnsignedLongLong],
+        (false, Suffix::LongLong, true) => &[LongLong],
+        (false, Suffix::LongLong, false) => &[LongLong, UnsignedLongLong],
+        (true, Suffix::LongLong, _) => &[UnsignedLongLong],
+    }
+}
+
+/// Decodes a decimal, octal, hexadecimal (`0x`) or binary (`0b`) integer
+/// constant with an optional `u`/`l`/`ll` suffix.
+pub fn integer(text: &str) -> Result<Literal, LiteralError> {
+    let lower = text.to_ascii_lowercase();
+    let (base, name, digits_start) = if lower.starts_with("0x") {
+        (16, "hexadecimal", 2)
+    } else if lower.starts_with("0b") {
+        (2, "binary", 2)
+    } else if text.starts_with('0') {
+        (8, "octal", 1)
+    } else {
+        (10, "decimal", 0)
+    };
+    let body = &text[digits_start..];
+    let is_digit = |c: char| c.is_ascii_digit() || (base == 16 && c.is_ascii_hexdigit());
+    let digits_end = body.find(|c| !is_digit(c)).unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(digits_end);
+    if digits.is_empty() && base != 8 {
+        return Err(LiteralError::MissingDigits { base: name });
+    }
+    let (unsigned, long) = parse_suffix(suffix)?;
+
+    let mut value: u64 = 0;
+    for c in digits.chars() {
+        let digit = c.to_digit(16).unwrap();
+        if digit >= base {
+            return Err(LiteralError::InvalidDigit {
+                digit: c,
+                base: name,
+            });
+        }
+        value = value
+            .checked_mul(base as u64)
+            .and_then(|value| value.checked_add(digit as u64))
+            .ok_or(LiteralError::TooLarge)?;
+    }
+
+    let candidates = candidates(base == 10, unsigned, long);
+    match candidates.iter().find(|kind| value <= kind.max_value()) {
+        Some(kind) => Ok(Literal::Integer { value, kind: *kind }),
+        None => Err(LiteralError::NoFittingType {
+            kind: *candidates.last().unwrap(),
+        }),
+    }
+}
+
+fn universal_character(code_point: u32) -> Result<char, LiteralError> {
+    let allowed = code_point >= 0xA0 || [0x24, 0x40, 0x60].contains(&code_point);
+    match char::from_u32(code_point) {
+        Some(c) if allowed => Ok(c),
+        _ => Err(LiteralError::InvalidUniversalCharacter { code_point }),
+    }
+}
+
+/// Decodes the characters between the quotes of a character constant or
+/// string literal into code units of `encoding`, resolving escapes.
+fn decode(body: &str, encoding: Encoding) -> Result<Vec<u32>, LiteralError> {
+    let mut units = Vec::new();
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            encoding.push_char(c, &mut units);
+            continue;
+        }
+        let escape = chars
+            .next()
+            .ok_or(LiteralError::UnknownEscape { escape: ' ' })?;
+        let simple = match escape {
+            '\'' | '"' | '?' | '\\' => Some(escape as u32),
+            'a' => Some(0x07),
+            'b' => Some(0x08),
+            'f' => Some(0x0C),
+            'n' => Some(0x0A),
+            'r' => Some(0x0D),
+            't' => Some(0x09),
+            'v' => Some(0x0B),
+            // GNU extension for the escape character.
+            'e' | 'E' => Some(0x1B),
+            _ => None,
+        };
+        if let Some(unit) = simple {
+            units.push(unit);
+            continue;
+        }
+        match escape {
+            '0'..='7' => {
+                let mut value = escape.to_digit(8).unwrap();
+                for _ in 0..2 {
+                    match chars.peek().and_then(|c| c.to_digit(8)) {
+                        Some(digit) => {
+                            value = value * 8 + digit;
+                            chars.next();
+                        }
+                        None => break,
+                    }
+                }
+                if value > encoding.max_unit() {
+                    return Err(LiteralError::EscapeOutOfRange {
+                        escape: format!("\\{value:o}"),
+                    });
+                }
+                units.push(value);
+            }
+            'x' => {
+                let mut digits = String::new();
+                while let Some(c) = chars.peek().filter(|c| c.is_ascii_hexdigit()) {
+                    digits.push(*c);
+                    chars.next();
+                }
+                if digits.is_empty() {
+                    return Err(LiteralError::MissingEscapeDigits { escape });
+                }
+                let out_of_range = || LiteralError::EscapeOutOfRange {
+                    escape: format!("\\x{digits}"),
+                };
+                let value = u32::from_str_radix(&digits, 16).map_err(|_| out_of_range())?;
+                if value > encoding.max_unit() {
+                    return Err(out_of_range());
+                }
+                units.push(value);
+            }
+            'u' | 'U' => {
+                let length = if escape == 'u' { 4 } else { 8 };
+                let mut value = 0u32;
+                for _ in 0..length {
+                    match chars.next().and_then(|c| c.to_digit(16)) {
+                        Some(digit) => value = value.wrapping_mul(16).wrapping_add(digit),
+                        None => return Err(LiteralError::MissingEscapeDigits { escape }),
+                    }
+                }
+                encoding.push_char(universal_character(value)?, &mut units);
+            }
+            _ => return Err(LiteralError::UnknownEscape { escape }),
+        }
+    }
+    Ok(units)
+}
+
+/// Decodes the body of a character constant. Plain constants have type
+/// `int` with the value of a (signed) `char`; multi-character constants
+/// combine the bytes like gcc does.
+pub fn character(body: &str, encoding: Encoding) -> Result<Literal, LiteralError> {
+    let units = decode(body, encoding)?;
+    let value = match (encoding, units.as_slice()) {
+        (_, []) => return Err(LiteralError::EmptyCharacter),
+        (Encoding::Plain, [unit]) => *unit as u8 as i8 as i64,
+        (Encoding::Plain, units) if units.len() <= 4 => units
+            .iter()
+            .fold(0i32, |value, unit| (value << 8) | *unit as i32)
+            as i64,
+        (Encoding::Wide, [unit]) => *unit as i32 as i64,
+        (Encoding::Utf16 | Encoding::Utf32, [unit]) => *unit as i64,
+        _ => return Err(LiteralError::CharacterTooLong),
+    };
+    Ok(Literal::Character { value, encoding })
+}
+
+/// Decodes and concatenates the bodies of adjacent string literals. A piece
+/// without prefix takes the encoding of the prefixed pieces.
+pub fn string(pieces: &[(&str, Encoding)]) -> Result<Literal, LiteralError> {
+    let mut encoding = Encoding::Plain;
+    for (_, piece) in pieces {
+        if *piece == Encoding::Plain || *piece == encoding {
+            continue;
+        }
+        if encoding != Encoding::Plain {
+            return Err(LiteralError::IncompatibleConcatenation {
+                first: encoding,
+                second: *piece,
+            });
+        }
+        encoding = *piece;
+    }
+    let mut bytes = Vec::new();
+    for (body, _) in pieces {
+        for unit in decode(body, encoding)? {
+            bytes.extend_from_slice(&unit.to_le_bytes()[..encoding.unit_size()]);
+        }
+    }
+    Ok(Literal::String { bytes, encoding })
+}
+
+/// Whether a preprocessing number is a floating constant rather than an
+/// integer constant.
+pub fn is_floating(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    if lower.starts_with("0x") {
+        lower.contains(['.', 'p'])
+    } else {
+        !lower.starts_with("0b") && lower.contains(['.', 'e'])
+    }
+}
+
+/// IEEE 754 binary format parameters.
+struct Format {
+    /// Explicitly stored mantissa bits.
+    mantissa_bits: u32,
+    /// Exponent of the largest finite value.
+    max_exponent: i64,
+}
+
+const DOUBLE: Format = Format {
+    mantissa_bits: 52,
+    max_exponent: 1023,
+};
+
+const SINGLE: Format = Format {
+    mantissa_bits: 23,
+    max_exponent: 127,
+};
+
+/// Rounds `mantissa * 2^exponent` to the nearest value of `format`, ties to
+/// even. `sticky` marks non-zero bits below `mantissa` which were dropped.
+/// Returns the raw bits, or `None` on overflow.
+fn round_binary(mantissa: u128, sticky: bool, exponent: i64, format: &Format) -> Option<u64> {
+    if mantissa == 0 {
+        return Some(0);
+    }
+    let precision = format.mantissa_bits as i64 + 1;
+    let min_exponent = 1 - format.max_exponent;
+    let length = 128 - mantissa.leading_zeros() as i64;
+    // Exponent of the leading bit, and of the last bit kept after rounding.
+    let mut leading = exponent + length - 1;
+    let last = (leading - precision + 1).max(min_exponent - precision + 1);
+    let shift = last - exponent;
+    let mut kept = if shift <= 0 {
+        mantissa << -shift
+    } else if shift > 128 {
+        0
+    } else {
+        let kept = mantissa.checked_shr(shift as u32).unwrap_or(0);
+        let rest = match shift {
+            128 => mantissa,
+            _ => mantissa & ((1u128 << shift) - 1),
+        };
+        let half = 1u128 << (shift - 1);
+        let above_half = rest > half || (rest == half && sticky);
+        if above_half || (rest == half && kept & 1 == 1) {
+            kept + 1
+        } else {
+            kept
+        }
+    };
+    if kept >> precision != 0 {
+        kept >>= 1;
+        leading += 1;
+    }
+    if leading > format.max_exponent {
+        return None;
+    }
+    let kept = kept as u64;
+    if kept >> format.mantissa_bits == 0 {
+        // Subnormal: the exponent field is zero.
+        return Some(kept);
+    }
+    // A subnormal which rounded up into the implicit bit is the smallest
+    // normal value, whose exponent is the minimum rather than `leading`.
+    let biased = (leading.max(min_exponent) + format.max_exponent) as u64;
+    Some((biased << format.mantissa_bits) | (kept & ((1 << format.mantissa_bits) - 1)))
+}
+
+fn float_suffix(suffix: &str) -> Result<FloatKind, LiteralError> {
+    match suffix {
+        "" => Ok(FloatKind::Double),
+        "f" | "F" => Ok(FloatKind::Float),
+        "l" | "L" => Ok(FloatKind::LongDouble),
+        _ => Err(LiteralError::InvalidSuffix {
+            suffix: suffix.to_string(),
+            constant: "floating",
+        }),
+    }
+}
+
+/// Splits `text` after the exponent, which starts at `marker` and must
+/// contain at least one digit.
+fn split_exponent(text: &str, marker: usize) -> Result<(&str, &str), LiteralError> {
+    let after = &text[marker + 1..];
+    let sign = usize::from(after.starts_with(['+', '-']));
+    let digits = after[sign..]
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(after.len() - sign);
+    if digits == 0 {
+        return Err(LiteralError::MissingExponentDigits);
+    }
+    Ok(text.split_at(marker + 1 + sign + digits))
+}
+
+fn hexadecimal_float(text: &str) -> Result<Literal, LiteralError> {
+    let body = &text[2..];
+    let marker = body
+        .find(['p', 'P'])
+        .ok_or(LiteralError::MissingHexExponent)?;
+    let (number, suffix) = split_exponent(body, marker)?;
+    let kind = float_suffix(suffix)?;
+    let (digits, exponent) = number.split_at(marker);
+    let exponent = &exponent[1..];
+    let exponent: i64 = exponent.parse().unwrap_or(match exponent.starts_with('-') {
+        true => -100_000,
+        false => 100_000,
+    });
+    let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if integer.is_empty() && fraction.is_empty() {
+        return Err(LiteralError::MissingDigits {
+            base: "hexadecimal",
+        });
+    }
+
+    let mut mantissa = 0u128;
+    let mut sticky = false;
+    let mut scale = 0i64;
+    for (c, fractional) in integer
+        .chars()
+        .map(|c| (c, false))
+        .chain(fraction.chars().map(|c| (c, true)))
+    {
+        let digit = c.to_digit(16).ok_or(LiteralError::InvalidDigit {
+            digit: c,
+            base: "hexadecimal",
+        })?;
+        if mantissa >> 120 == 0 {
+            mantissa = mantissa * 16 + digit as u128;
+            scale -= if fractional { 4 } else { 0 };
+        } else {
+            sticky |= digit != 0;
+            scale += if fractional { 0 } else { 4 };
+        }
+    }
+    let exponent = exponent.saturating_add(scale);
+    let format = if kind == FloatKind::Float {
+        &SINGLE
+    } else {
+        &DOUBLE
+    };
+    let bits = round_binary(mantissa, sticky, exponent.clamp(-100_000, 100_000), format)
+        .ok_or(LiteralError::FloatOutOfRange { kind })?;
+    let value = if kind == FloatKind::Float {
+        (f32::from_bits(bits as u32) as f64).to_bits()
+    } else {
+        bits
+    };
+    Ok(Literal::Float { value, kind })
+}
+
+fn decimal_float(text: &str) -> Result<Literal, LiteralError> {
+    let mut seen_point = false;
+    let number_end = text
+        .find(|c: char| match c {
+            '.' if !seen_point => {
+                seen_point = true;
+                false
+            }
+            _ => !c.is_ascii_digit(),
+        })
+        .unwrap_or(text.len());
+    let (number, suffix) = match text[number_end..].starts_with(['e', 'E']) {
+        true => split_exponent(text, number_end)?,
+        false => text.split_at(number_end),
+    };
+    let kind = float_suffix(suffix)?;
+    // Rust's float parsing is correctly rounded, so it gives the nearest
+    // representable value for any number of digits.
+    let value = match kind {
+        FloatKind::Float => {
+            let value: f32 = number.parse().unwrap();
+            value.is_finite().then(|| (value as f64).to_bits())
+        }
+        _ => {
+            let value: f64 = number.parse().unwrap();
+            value.is_finite().then(|| value.to_bits())
+        }
+    };
+    let value = value.ok_or(LiteralError::FloatOutOfRange { kind })?;
+    Ok(Literal::Float { value, kind })
+}
+
+/// Decodes a decimal (`1.5e-3`) or hexadecimal (`0x1.8p3`) floating
+/// constant with an optional `f` or `l` suffix.
+pub fn floating(text: &str) -> Result<Literal, LiteralError> {
+    if text.to_ascii_lowercase().starts_with("0x") {
+        hexadecimal_float(text)
+    } else {
+        decimal_float(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integer_kind(text: &str) -> (u64, IntegerKind) {
+        match integer(text) {
+            Ok(Literal::Integer { value, kind }) => (value, kind),
+            other => panic!("`{text}` decoded to {other:?}"),
+        }
+    }
+
+    #[test]
+    fn integer_bases() {
+        assert_eq!(integer_kind("0").0, 0);
+        assert_eq!(integer_kind("42").0, 42);
+        assert_eq!(integer_kind("017").0, 0o17);
+        assert_eq!(integer_kind("0x1fA").0, 0x1fa);
+        assert_eq!(integer_kind("0XFF").0, 0xff);
+        assert_eq!(integer_kind("0b101").0, 0b101);
+    }
+
+    #[test]
+    fn decimal_constants_stay_signed() {
+        use IntegerKind::*;
+        assert_eq!(integer_kind("2147483647"), (i32::MAX as u64, Int));
+        assert_eq!(integer_kind("2147483648"), (1 << 31, Long));
+        assert_eq!(integer_kind("4294967295"), (u32::MAX as u64, Long));
+        assert_eq!(integer_kind("9223372036854775807"), (i64::MAX as u64, Long));
+        assert_eq!(integer_kind("1ll").1, LongLong);
+    }
+
+    #[test]
+    fn other_bases_may_become_unsigned() {
+        use IntegerKind::*;
+        assert_eq!(integer_kind("0x7fffffff").1, Int);
+        assert_eq!(integer_kind("0x80000000").1, UnsignedInt);
+        assert_eq!(integer_kind("037777777777").1, UnsignedInt);
+        assert_eq!(integer_kind("0x100000000").1, Long);
+        assert_eq!(integer_kind("0x8000000000000000").1, UnsignedLong);
+        assert_eq!(integer_kind("0x8000000000000000ll").1, UnsignedLongLong);
+    }
+
+    #[test]
+    fn integer_suffixes() {
+        use IntegerKind::*;
+        for (text, kind) in [
+            ("1u", UnsignedInt),
+            ("4294967296U", UnsignedLong),
+            ("1l", Long),
+            ("1L", Long),
+            ("1ul", UnsignedLong),
+            ("1LU", UnsignedLong),
+            ("1lu", UnsignedLong),
+            ("1LL", LongLong),
+            ("1ull", UnsignedLongLong),
+            ("1LLU", UnsignedLongLong),
+            ("0x1uL", UnsignedLong),
+        ] {
+            assert_eq!(integer_kind(text).1, kind, "{text}");
+        }
+        for text in ["1lL", "1uu", "1lul", "1lll", "1x", "1f"] {
+            assert!(
+                matches!(integer(text), Err(LiteralError::InvalidSuffix { .. })),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_integers() {
+        assert_eq!(
+            integer("08"),
+            Err(LiteralError::InvalidDigit {
+                digit: '8',
+                base: "octal"
+            })
+        );
+        assert_eq!(
+            integer("0b102"),
+            Err(LiteralError::InvalidDigit {
+                digit: '2',
+                base: "binary"
+            })
+        );
+        assert_eq!(
+            integer("0x"),
+            Err(LiteralError::MissingDigits {
+                base: "hexadecimal"
+            })
+        );
+        assert_eq!(
+            integer("9223372036854775808"),
+            Err(LiteralError::NoFittingType {
+                kind: IntegerKind::LongLong
+            })
+        );
+        assert_eq!(integer("18446744073709551615u").map(|_| ()), Ok(()));
+        assert_eq!(integer("18446744073709551616"), Err(LiteralError::TooLarge));
+    }
+
+    fn char_value(body: &str, encoding: Encoding) -> i64 {
+        match character(body, encoding) {
+            Ok(Literal::Character { value, .. }) => value,
+            other => panic!("`{body}` decoded to {other:?}"),
+        }
+    }
+
+    fn string_bytes(pieces: &[(&str, Encoding)]) -> (Vec<u8>, Encoding) {
+        match string(pieces) {
+            Ok(Literal::String { bytes, encoding }) => (bytes, encoding),
+            other => panic!("{pieces:?} decoded to {other:?}"),
+        }
+    }
+
+    #[test]
+    fn simple_escapes() {
+        let escapes = r#"\'\"\?\\\a\b\f\n\r\t\v\e"#;
+        assert_eq!(
+            string_bytes(&[(escapes, Encoding::Plain)]).0,
+            b"'\"?\\\x07\x08\x0c\n\r\t\x0b\x1b"
+        );
+        assert_eq!(char_value(r"\n", Encoding::Plain), 10);
+        assert_eq!(
+            character(r"\q", Encoding::Plain),
+            Err(LiteralError::UnknownEscape { escape: 'q' })
+        );
+    }
+
+    #[test]
+    fn octal_and_hexadecimal_escapes() {
+        assert_eq!(char_value(r"\0", Encoding::Plain), 0);
+        assert_eq!(char_value(r"\101", Encoding::Plain), 65);
+        // At most three octal digits, but any number of hex digits.
+        assert_eq!(
+            string_bytes(&[(r"\1011\x41g\x0041", Encoding::Plain)]).0,
+            b"A1AgA"
+        );
+        // Plain `char` is signed.
+        assert_eq!(char_value(r"\377", Encoding::Plain), -1);
+        assert_eq!(char_value(r"\xff", Encoding::Plain), -1);
+        assert_eq!(char_value(r"\xffff", Encoding::Utf16), 0xffff);
+        assert_eq!(
+            character(r"\x100", Encoding::Plain),
+            Err(LiteralError::EscapeOutOfRange {
+                escape: r"\x100".to_string()
+            })
+        );
+        assert_eq!(
+            character(r"\777", Encoding::Plain),
+            Err(LiteralError::EscapeOutOfRange {
+                escape: r"\777".to_string()
+            })
+        );
+        assert_eq!(
+            character(r"\xg", Encoding::Plain),
+            Err(LiteralError::MissingEscapeDigits { escape: 'x' })
+        );
+    }
+
+    #[test]
+    fn universal_character_names() {
+        assert_eq!(char_value(r"\u00e9", Encoding::Utf16), 0xe9);
+        assert_eq!(char_value(r"\U0001F600", Encoding::Utf32), 0x1f600);
+        assert_eq!(
+            string_bytes(&[(r"\u00E9", Encoding::Plain)]).0,
+            "é".as_bytes()
+        );
+        // Outside the basic plane, UTF-16 needs a surrogate pair.
+        assert_eq!(
+            string_bytes(&[(r"\U0001F600", Encoding::Utf16)]).0,
+            [0x3d, 0xd8, 0x00, 0xde]
+        );
+        assert_eq!(
+            character(r"\u0041", Encoding::Plain),
+            Err(LiteralError::InvalidUniversalCharacter { code_point: 0x41 })
+        );
+        assert_eq!(
+            character(r"\ud800", Encoding::Utf16),
+            Err(LiteralError::InvalidUniversalCharacter { code_point: 0xd800 })
+        );
+        assert_eq!(
+            character(r"\u12", Encoding::Utf16),
+            Err(LiteralError::MissingEscapeDigits { escape: 'u' })
+        );
+    }
+
+    #[test]
+    fn character_constants() {
+        assert_eq!(char_value("a", Encoding::Plain), 97);
+        assert_eq!(char_value("ab", Encoding::Plain), 0x6162);
+        assert_eq!(char_value(r"\xff", Encoding::Wide), 0xff);
+        assert_eq!(
+            character("", Encoding::Plain),
+            Err(LiteralError::EmptyCharacter)
+        );
+        assert_eq!(
+            character("abcde", Encoding::Plain),
+            Err(LiteralError::CharacterTooLong)
+        );
+        assert_eq!(
+            character("ab", Encoding::Utf32),
+            Err(LiteralError::CharacterTooLong)
+        );
+    }
+
+    #[test]
+    fn string_concatenation() {
+        assert_eq!(
+            string_bytes(&[("a", Encoding::Plain), (r"\x41", Encoding::Utf16)]),
+            (vec![0x61, 0, 0x41, 0], Encoding::Utf16)
+        );
+        assert_eq!(
+            string(&[("a", Encoding::Utf16), ("b", Encoding::Utf32)]),
+            Err(LiteralError::IncompatibleConcatenation {
+                first: Encoding::Utf16,
+                second: Encoding::Utf32
+            })
+        );
+    }
+
+    fn double(text: &str) -> u64 {
+        match floating(text) {
+            Ok(Literal::Float {
+                value,
+                kind: FloatKind::Double,
+            }) => value,
+            other => panic!("`{text}` decoded to {other:?}"),
+        }
+    }
+
+    fn single(text: &str) -> f32 {
+        match floating(text) {
+            Ok(Literal::Float {
+                value,
+                kind: FloatKind::Float,
+            }) => f64::from_bits(value) as f32,
+            other => panic!("`{text}` decoded to {other:?}"),
+        }
+    }
+
+    #[test]
+    fn hexadecimal_constants_are_exact() {
+        assert_eq!(double("0x1p0"), 1.0f64.to_bits());
+        assert_eq!(double("0x1.8p3"), 12.0f64.to_bits());
+        assert_eq!(double("0X.8P-1"), 0.25f64.to_bits());
+        assert_eq!(double("0x1.fffffffffffffp1023"), f64::MAX.to_bits());
+    }
+
+    #[test]
+    fn hexadecimal_constants_round_to_nearest_even() {
+        // One bit beyond the mantissa: exactly halfway, so ties to even.
+        assert_eq!(double("0x1.00000000000008p0"), 1.0f64.to_bits());
+        assert_eq!(double("0x1.00000000000018p0"), 1.0f64.to_bits() + 2);
+        // Just above halfway rounds up.
+        assert_eq!(double("0x1.000000000000081p0"), 1.0f64.to_bits() + 1);
+        assert_eq!(single("0x1.000001p0f"), 1.0);
+        assert_eq!(single("0x1.000003p0f"), 1.0 + f32::EPSILON * 2.0);
+    }
+
+    #[test]
+    fn decimal_constants_round_to_nearest() {
+        assert_eq!(double("0.1"), 0.1f64.to_bits());
+        assert_eq!(double("1e23"), 1e23f64.to_bits());
+        assert_eq!(
+            double("2.2250738585072014e-308"),
+            f64::MIN_POSITIVE.to_bits()
+        );
+        assert_eq!(single("0.1f"), 0.1f32);
+        assert_eq!(single("16777217.0f"), 16777216.0);
+    }
+
+    #[test]
+    fn subnormals() {
+        assert_eq!(double("0x1p-1074"), 1);
+        assert_eq!(double("0x0.0000000000001p-1022"), 1);
+        assert_eq!(double("0x1p-1075"), 0);
+        assert_eq!(double("0x1.8p-1075"), 1);
+        assert_eq!(double("4.9e-324"), 1);
+        assert_eq!(single("0x1p-149f"), f32::from_bits(1));
+    }
+
+    #[test]
+    fn subnormals_carry_into_smallest_normal() {
+        let min = f64::MIN_POSITIVE.to_bits();
+        assert_eq!(min, 0x0010_0000_0000_0000);
+        assert_eq!(double("0x0.fffffffffffff8p-1022"), min);
+        assert_eq!(double("0x0.fffffffffffffcp-1022"), min);
+        assert_eq!(single("0x0.ffffffp-126f"), f32::MIN_POSITIVE);
+    }
+
+    #[test]
+    fn overflow_is_rejected() {
+        for (text, kind) in [
+            ("0x1p1024", FloatKind::Double),
+            ("0x1.fffffffffffff8p1023", FloatKind::Double),
+            ("1e309", FloatKind::Double),
+            ("0x1p128f", FloatKind::Float),
+            ("1e39f", FloatKind::Float),
+        ] {
+            assert_eq!(
+                floating(text),
+                Err(LiteralError::FloatOutOfRange { kind }),
+                "{text}"
+            );
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt;
+use std::rc::Rc;
+
+/// Location of a token or AST node in the source it was read from.
+///
+/// `line` and `column` are 1-based, `start`/`end` are byte offsets into the
+/// file, with `end` exclusive.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Span {
+    pub file: Rc<str>,
+    pub line: u32,
+    pub column: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: Rc<str>, line: u32, column: u32, start: usize, end: usize) -> Span {
+        Span {
+            file,
+            line,
+            column,
+            start,
+            end,
+        }
+    }
+
+    /// Span covering `self` up to and including `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        if self.file != other.file || other.end < self.start {
+            return self.clone();
+        }
+        Span {
+            file: self.file.clone(),
+            line: self.line,
+            column: self.column,
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
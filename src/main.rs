@@ -1,16 +1,21 @@
+use diagnostics::error::{CompileError, DriverError, EXIT_INTERNAL_ERROR};
+use diagnostics::renderer::Renderer;
+use parser::parser::MAX_NESTING_DEPTH;
 use std::env;
 use std::fs;
-use std::io::{Error, ErrorKind};
 use std::process::Command;
 
 pub mod assembly;
+pub mod diagnostics;
 pub mod lexer;
 pub mod parser;
+pub mod preprocessor;
 
 #[derive(PartialEq)]
 enum Stage {
     Lex,
     Parse,
+    Validate,
     Intermediate,
     Codegen,
     All,
@@ -21,42 +26,118 @@ fn compile(
     assembly: &str,
     stage: &Stage,
     debug_mode: bool,
-) -> std::io::Result<()> {
+) -> Result<(), CompileError> {
     println! {"Compiling..."};
+    let source = fs::read_to_string(preprocessed).map_err(|error| DriverError::Io {
+        path: preprocessed.to_string(),
+        error,
+    })?;
     println! {"   Lexer"};
-    let result = lexer::lexer::lex(preprocessed, debug_mode);
-    if result.is_err() || *stage == Stage::Lex {
-        return result.map(|_x| ());
+    let mut tokens = lexer::lexer::lex(&source, preprocessed, debug_mode)?;
+    if *stage == Stage::Lex {
+        return Ok(());
     }
     println! {"   Parse"};
-    let result = parser::parser::parse(&mut result.unwrap(), debug_mode);
-    if result.is_err() || *stage == Stage::Parse {
-        return result.map(|_x| ());
+    let ast = parser::parser::parse(&mut tokens, debug_mode)?;
+    if *stage == Stage::Parse {
+        return Ok(());
+    }
+    println! {"   Semantic"};
+    let (ast, mut symbols) = parser::semantic::analyze(*ast, debug_mode)?;
+    if *stage == Stage::Validate {
+        return Ok(());
     }
     println! {"   Intermediate"};
-    let result = parser::intermediate::intermediate(&mut result.as_ref().unwrap(), debug_mode);
-    if result.is_err() || *stage == Stage::Intermediate {
-        return result.map(|_x| ());
+    let tacky = parser::intermediate::intermediate(&ast, &mut symbols, debug_mode)?;
+    if *stage == Stage::Intermediate {
+        return Ok(());
     }
     println! {"   Codegen"};
-    let result = assembly::generator::generate(&result.unwrap(), debug_mode);
-    if result.is_err() || *stage == Stage::Codegen {
-        return result.map(|_x| ());
+    let aast = assembly::generator::generate(&tacky, &symbols, debug_mode)?;
+    if *stage == Stage::Codegen {
+        return Ok(());
     }
     println! {"   Emit"};
-    assembly::emission::emit(&result.unwrap(), assembly, debug_mode)?;
+    assembly::emission::emit(&aast, assembly, debug_mode)?;
 
     println! {"Done."};
     Ok(())
 }
 
-fn main() -> std::io::Result<()> {
+/// Runs an external tool, turning a non-zero exit status into an error.
+fn run_tool(tool: &str, args: &[&str]) -> Result<(), DriverError> {
+    let output = Command::new(tool)
+        .args(args)
+        .output()
+        .map_err(|error| DriverError::Io {
+            path: tool.to_string(),
+            error,
+        })?;
+    if !output.status.success() {
+        return Err(DriverError::ToolFailed {
+            tool: tool.to_string(),
+            status: output.status.code(),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Directories given with `-I <dir>` or `-I<dir>`, in order.
+fn include_paths(args: &[String]) -> Vec<String> {
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            paths.extend(args.next().cloned());
+        } else if let Some(path) = arg.strip_prefix("-I") {
+            paths.push(path.to_string());
+        }
+    }
+    paths
+}
+
+/// Runs the built-in preprocessor on `input`, writing the result to
+/// `preprocessed` and printing any warnings.
+fn preprocess(
+    input: &str,
+    preprocessed: &str,
+    include_paths: &[String],
+    debug_mode: bool,
+) -> Result<(), CompileError> {
+    let source = fs::read_to_string(input).map_err(|error| DriverError::Io {
+        path: input.to_string(),
+        error,
+    })?;
+    let (output, warnings) =
+        preprocessor::preprocessor::preprocess(&source, input, include_paths, debug_mode)?;
+    let mut renderer = Renderer::for_stderr();
+    for warning in &warnings {
+        eprint!("{}", renderer.render(warning));
+    }
+    fs::write(preprocessed, output).map_err(|error| DriverError::Io {
+        path: preprocessed.to_string(),
+        error,
+    })?;
+    Ok(())
+}
+
+fn remove_file(path: &str) -> Result<(), DriverError> {
+    fs::remove_file(path).map_err(|error| DriverError::Io {
+        path: path.to_string(),
+        error,
+    })
+}
+
+fn run() -> Result<(), CompileError> {
     let args: Vec<String> = env::args().collect();
 
     let stage = if args.iter().any(|x| x == "--lex") {
         Stage::Lex
     } else if args.iter().any(|x| x == "--parse") {
         Stage::Parse
+    } else if args.iter().any(|x| x == "--validate") {
+        Stage::Validate
     } else if args.iter().any(|x| x == "--tacky") {
         Stage::Intermediate
     } else if args.iter().any(|x| x == "--codegen") {
@@ -70,7 +151,7 @@ fn main() -> std::io::Result<()> {
     //let input = &args[1];
     //if !input.ends_with(".c") {
     if input.is_none() {
-        return Err(Error::new(ErrorKind::InvalidInput, "Not a c file"));
+        return Err(DriverError::NoInput.into());
     }
     let input = input.unwrap();
     let mut preprocessed = input.clone();
@@ -78,9 +159,7 @@ fn main() -> std::io::Result<()> {
     preprocessed += "i";
     println!("{}", input);
     println!("{}", preprocessed);
-    let _cmd_output = Command::new("gcc")
-        .args(["-E", "-P", input, "-o", &preprocessed])
-        .output()?;
+    preprocess(input, &preprocessed, &include_paths(&args), debug_mode)?;
 
     let mut assembly = input.clone();
     assembly.pop();
@@ -92,15 +171,115 @@ fn main() -> std::io::Result<()> {
     if result.is_err() || stage != Stage::All {
         return result;
     }
-    fs::remove_file(preprocessed)?;
+    remove_file(&preprocessed)?;
 
     println!("{}", assembly);
     println!("{}", output);
-    let _cmd_output = Command::new("gcc")
-        .args([&assembly, "-o", &output])
-        .output()?;
+    run_tool("gcc", &[&assembly, "-o", &output])?;
     if !dump_assembly {
-        fs::remove_file(assembly)?;
+        remove_file(&assembly)?;
     }
     Ok(())
 }
+
+/// Stack used per nesting level by the construct which needs the most,
+/// nested statements: about 32 KiB in debug builds and 2 KiB in release
+/// builds, rounded up. `tests::deepest_nesting_fits_the_stack` checks it.
+const STACK_PER_NESTING_LEVEL: usize = if cfg!(debug_assertions) {
+    40 << 10
+} else {
+    4 << 10
+};
+
+/// The passes over the AST recurse once per nesting level, so they run on
+/// a thread with a stack large enough for any input within
+/// `MAX_NESTING_DEPTH`.
+const COMPILER_STACK_SIZE: usize = MAX_NESTING_DEPTH as usize * STACK_PER_NESTING_LEVEL;
+
+fn main() {
+    let compiler = std::thread::Builder::new()
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(|| match run() {
+            Ok(()) => 0,
+            Err(error) => {
+                eprint!("{}", Renderer::for_stderr().render(&error.diagnostic()));
+                error.exit_code()
+            }
+        });
+    // A panic has already been reported by the panic hook.
+    let code = match compiler.map(|compiler| compiler.join()) {
+        Ok(Ok(code)) => code,
+        Ok(Err(_)) => EXIT_INTERNAL_ERROR,
+        Err(error) => {
+            eprintln!("error: could not start the compiler thread: {error}");
+            EXIT_INTERNAL_ERROR
+        }
+    };
+    std::process::exit(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs every pass up to code generation on `source`, on a thread with
+    /// the compiler's stack, returning the message of any error.
+    fn compile_source(source: String) -> Result<(), String> {
+        let compiler = std::thread::Builder::new()
+            .stack_size(COMPILER_STACK_SIZE)
+            .spawn(move || {
+                let compile = || -> Result<(), CompileError> {
+                    let mut tokens = lexer::lexer::lex(&source, "test.c", false)?;
+                    let ast = parser::parser::parse(&mut tokens, false)?;
+                    let (ast, mut symbols) = parser::semantic::analyze(*ast, false)?;
+                    let tacky = parser::intermediate::intermediate(&ast, &mut symbols, false)?;
+                    assembly::generator::generate(&tacky, &symbols, false)?;
+                    Ok(())
+                };
+                compile().map_err(|error| error.to_string())
+            })
+            .unwrap();
+        compiler.join().unwrap()
+    }
+
+    /// `depth` nested blocks, each one statement nesting level.
+    fn nested_blocks(depth: u32) -> String {
+        let depth = depth as usize;
+        format!(
+            "int main(void) {{ {}{} }}",
+            "{".repeat(depth),
+            "}".repeat(depth)
+        )
+    }
+
+    /// A call chain, the expression which needs the most stack per level.
+    fn nested_calls(depth: u32) -> String {
+        let depth = depth as usize;
+        format!(
+            "int f(int x) {{ return x; }}\nint main(void) {{ return {}1{}; }}",
+            "f(".repeat(depth),
+            ")".repeat(depth)
+        )
+    }
+
+    #[test]
+    fn deepest_nesting_fits_the_stack() {
+        assert!(compile_source(nested_blocks(MAX_NESTING_DEPTH)).is_ok());
+        // Each call is a level, as are the return statement and the
+        // innermost constant.
+        assert!(compile_source(nested_calls(MAX_NESTING_DEPTH - 2)).is_ok());
+    }
+
+    #[test]
+    fn nesting_past_the_limit_is_rejected() {
+        for source in [
+            nested_blocks(MAX_NESTING_DEPTH + 1),
+            nested_calls(MAX_NESTING_DEPTH - 1),
+        ] {
+            assert_eq!(
+                compile_source(source),
+                Err("expression or statement too deeply nested".to_string())
+            );
+        }
+    }
+}
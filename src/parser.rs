@@ -1,2 +1,6 @@
+pub mod error;
 pub mod intermediate;
+#[allow(clippy::module_inception)]
 pub mod parser;
+pub mod semantic;
+pub mod types;
@@ -0,0 +1,432 @@
+use crate::diagnostics::diagnostic::Diagnostic;
+use crate::lexer::lexer::Token;
+use crate::lexer::span::Span;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: Token,
+        found: Token,
+        span: Span,
+    },
+    KeywordAsIdentifier {
+        keyword: Token,
+        span: Span,
+    },
+    ExpectedExpression {
+        found: Token,
+        span: Span,
+    },
+    ExpectedUnaryOperator {
+        found: Token,
+        span: Span,
+    },
+    /// The construct is valid C, but uses a type the compiler cannot handle.
+    UnsupportedType {
+        type_name: String,
+        span: Span,
+    },
+    TrailingInput {
+        found: Token,
+        span: Span,
+    },
+    /// More than one of `static` and `extern` in a declaration.
+    MultipleStorageClasses {
+        span: Span,
+    },
+    /// Type specifiers that do not name a type together, like `long char`
+    /// or `signed unsigned`.
+    InvalidTypeSpecifiers {
+        span: Span,
+    },
+    /// A parameter without a name in a function definition.
+    MissingParameterName {
+        span: Span,
+    },
+    /// Expressions or statements nested deeper than the compiler supports.
+    NestingTooDeep {
+        span: Span,
+    },
+    /// The token stream ended without an `EndOfFile` token.
+    UnexpectedEnd,
+}
+
+impl ParseError {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let message = self.to_string();
+        match self {
+            ParseError::UnexpectedToken { expected, span, .. } => {
+                Diagnostic::error(message, span).with_label(format!("expected {expected}"))
+            }
+            ParseError::KeywordAsIdentifier { keyword, span } => Diagnostic::error(message, span)
+                .with_label("expected identifier")
+                .with_note(format!("{keyword} is a reserved keyword")),
+            ParseError::ExpectedExpression { span, .. } => {
+                Diagnostic::error(message, span).with_label("expected expression")
+            }
+            ParseError::ExpectedUnaryOperator { span, .. } => Diagnostic::error(message, span),
+            ParseError::UnsupportedType { span, .. } => {
+                Diagnostic::error(message, span).with_note("only integer types are supported")
+            }
+            ParseError::TrailingInput { span, .. } => Diagnostic::error(message, span)
+                .with_label("invalid top level item")
+                .with_note("only declarations are allowed at file scope"),
+            ParseError::MultipleStorageClasses { span } => Diagnostic::error(message, span)
+                .with_label("second storage class")
+                .with_help("remove one of `static` and `extern`"),
+            ParseError::InvalidTypeSpecifiers { span } => {
+                Diagnostic::error(message, span).with_label("does not name a type")
+            }
+            ParseError::MissingParameterName { span } => {
+                Diagnostic::error(message, span).with_label("expected a parameter name")
+            }
+            ParseError::NestingTooDeep { span } => Diagnostic::error(message, span)
+                .with_label("nested too deeply")
+                .with_note(format!(
+                    "at most {} levels of nesting are supported",
+                    crate::parser::parser::MAX_NESTING_DEPTH
+                )),
+            ParseError::UnexpectedEnd => Diagnostic::bare(message),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken {
+                expected, found, ..
+            } => write!(f, "expected {expected}, found {found}"),
+            ParseError::KeywordAsIdentifier { keyword, .. } => {
+                write!(f, "expected identifier, found keyword {keyword}")
+            }
+            ParseError::ExpectedExpression { found, .. } => {
+                write!(f, "expected expression, found {found}")
+            }
+            ParseError::ExpectedUnaryOperator { found, .. } => {
+                write!(f, "expected unary operator, found {found}")
+            }
+            ParseError::UnsupportedType { type_name, .. } => {
+                write!(f, "type `{type_name}` is not supported")
+            }
+            ParseError::TrailingInput { found, .. } => {
+                write!(f, "expected end of file, found {found}")
+            }
+            ParseError::MultipleStorageClasses { .. } => {
+                write!(f, "multiple storage classes in declaration")
+            }
+            ParseError::InvalidTypeSpecifiers { .. } => {
+                write!(f, "invalid combination of type specifiers")
+            }
+            ParseError::MissingParameterName { .. } => {
+                write!(f, "parameter name omitted in function definition")
+            }
+            ParseError::NestingTooDeep { .. } => {
+                write!(f, "expression or statement too deeply nested")
+            }
+            ParseError::UnexpectedEnd => write!(f, "unexpected end of token stream"),
+        }
+    }
+}
+
+/// Errors found while checking or lowering the AST.
+#[derive(Debug)]
+pub enum SemanticError {
+    /// The AST contained a node the lowering does not know how to handle.
+    /// This is a bug in an earlier stage rather than in the input.
+    UnexpectedNode {
+        node: String,
+        span: Option<Span>,
+    },
+    UndeclaredIdentifier {
+        name: String,
+        span: Span,
+    },
+    /// A second declaration of `name` in the same scope.
+    DuplicateDeclaration {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// The left side of an assignment is not a variable.
+    InvalidLvalue {
+        span: Span,
+    },
+    /// `break` with no loop or switch around it, or `continue` with no
+    /// loop around it.
+    JumpOutsideLoop {
+        statement: &'static str,
+        span: Span,
+    },
+    /// `case` or `default` with no switch around it.
+    CaseOutsideSwitch {
+        statement: &'static str,
+        span: Span,
+    },
+    /// A second `case` with the same value, or a second `default` when
+    /// `value` is `None`, in one switch.
+    DuplicateCase {
+        value: Option<i64>,
+        span: Span,
+        previous: Span,
+    },
+    NonConstantCase {
+        span: Span,
+    },
+    /// A second label with the same name in one function.
+    DuplicateLabel {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// A `goto` to a label its function does not have.
+    UndefinedLabel {
+        name: String,
+        span: Span,
+    },
+    /// Declarations of one function with different parameter lists.
+    ConflictingDeclaration {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// A second definition of a function, or a second initializer for a
+    /// file scope variable.
+    DuplicateDefinition {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// A function definition inside another function.
+    NestedFunction {
+        name: String,
+        span: Span,
+    },
+    NotAFunction {
+        name: String,
+        span: Span,
+    },
+    FunctionAsValue {
+        name: String,
+        span: Span,
+    },
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        variadic: bool,
+        span: Span,
+    },
+    /// A `static` declaration after one with external linkage, or the
+    /// other way around.
+    ConflictingLinkage {
+        name: String,
+        is_static: bool,
+        span: Span,
+        previous: Span,
+    },
+    /// A name declared as both a function and a variable.
+    ConflictingKind {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+    /// A storage class where it cannot be used.
+    InvalidStorageClass {
+        storage: &'static str,
+        context: &'static str,
+        span: Span,
+    },
+    /// A variable with static storage duration initialized with something
+    /// other than a constant.
+    NonConstantInitializer {
+        name: String,
+        span: Span,
+    },
+    /// A local `extern` declaration with an initializer.
+    ExternInitializer {
+        name: String,
+        span: Span,
+    },
+}
+
+impl SemanticError {
+    pub fn is_internal(&self) -> bool {
+        matches!(self, SemanticError::UnexpectedNode { .. })
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let message = self.to_string();
+        match self {
+            SemanticError::UnexpectedNode {
+                span: Some(span), ..
+            } => Diagnostic::error(message, span),
+            SemanticError::UnexpectedNode { span: None, .. } => Diagnostic::bare(message),
+            SemanticError::UndeclaredIdentifier { span, .. } => {
+                Diagnostic::error(message, span).with_label("not found in this scope")
+            }
+            SemanticError::DuplicateDeclaration { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("redeclared here")
+                    .with_note(format!("previous declaration is at {previous}"))
+            }
+            SemanticError::InvalidLvalue { span } => {
+                Diagnostic::error(message, span).with_label("cannot be assigned to")
+            }
+            SemanticError::JumpOutsideLoop { span, .. }
+            | SemanticError::CaseOutsideSwitch { span, .. } => Diagnostic::error(message, span),
+            SemanticError::DuplicateCase { span, previous, .. } => Diagnostic::error(message, span)
+                .with_label("duplicate label")
+                .with_note(format!("previous label is at {previous}")),
+            SemanticError::NonConstantCase { span } => {
+                Diagnostic::error(message, span).with_label("not a constant")
+            }
+            SemanticError::DuplicateLabel { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("redefined here")
+                    .with_note(format!("previous definition is at {previous}"))
+            }
+            SemanticError::UndefinedLabel { span, .. } => {
+                Diagnostic::error(message, span).with_label("no such label in this function")
+            }
+            SemanticError::ConflictingDeclaration { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("type differs from the previous declaration")
+                    .with_note(format!("previous declaration is at {previous}"))
+            }
+            SemanticError::DuplicateDefinition { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("redefined here")
+                    .with_note(format!("previous definition is at {previous}"))
+            }
+            SemanticError::NestedFunction { span, .. } => Diagnostic::error(message, span)
+                .with_help("define the function at file scope instead"),
+            SemanticError::NotAFunction { span, .. } => {
+                Diagnostic::error(message, span).with_label("not a function")
+            }
+            SemanticError::FunctionAsValue { span, .. } => {
+                Diagnostic::error(message, span).with_label("function used as a value")
+            }
+            SemanticError::ArgumentCount { span, .. } => Diagnostic::error(message, span),
+            SemanticError::ConflictingLinkage { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("linkage differs from the previous declaration")
+                    .with_note(format!("previous declaration is at {previous}"))
+            }
+            SemanticError::ConflictingKind { span, previous, .. } => {
+                Diagnostic::error(message, span)
+                    .with_label("redeclared here")
+                    .with_note(format!("previous declaration is at {previous}"))
+            }
+            SemanticError::InvalidStorageClass { span, .. } => Diagnostic::error(message, span),
+            SemanticError::NonConstantInitializer { span, .. } => {
+                Diagnostic::error(message, span).with_label("not a constant")
+            }
+            SemanticError::ExternInitializer { span, .. } => Diagnostic::error(message, span)
+                .with_label("initializer here")
+                .with_help("initialize the variable where it is defined"),
+        }
+    }
+}
+
+impl fmt::Display for SemanticError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SemanticError::UnexpectedNode { node, .. } => {
+                write!(f, "unexpected AST node {node}")
+            }
+            SemanticError::UndeclaredIdentifier { name, .. } => {
+                write!(f, "use of undeclared identifier `{name}`")
+            }
+            SemanticError::DuplicateDeclaration { name, .. } => {
+                write!(f, "redeclaration of `{name}`")
+            }
+            SemanticError::InvalidLvalue { .. } => {
+                write!(f, "expression is not assignable")
+            }
+            SemanticError::JumpOutsideLoop {
+                statement: "break", ..
+            } => write!(f, "`break` statement not within a loop or switch"),
+            SemanticError::JumpOutsideLoop { statement, .. } => {
+                write!(f, "`{statement}` statement not within a loop")
+            }
+            SemanticError::CaseOutsideSwitch { statement, .. } => {
+                write!(f, "`{statement}` label not within a switch statement")
+            }
+            SemanticError::DuplicateCase {
+                value: Some(value), ..
+            } => write!(f, "duplicate case value `{value}`"),
+            SemanticError::DuplicateCase { value: None, .. } => {
+                write!(f, "multiple `default` labels in one switch")
+            }
+            SemanticError::NonConstantCase { .. } => {
+                write!(f, "case label is not an integer constant expression")
+            }
+            SemanticError::DuplicateLabel { name, .. } => {
+                write!(f, "redefinition of label `{name}`")
+            }
+            SemanticError::UndefinedLabel { name, .. } => {
+                write!(f, "use of undeclared label `{name}`")
+            }
+            SemanticError::ConflictingDeclaration { name, .. } => {
+                write!(f, "conflicting declarations of `{name}`")
+            }
+            SemanticError::DuplicateDefinition { name, .. } => {
+                write!(f, "redefinition of `{name}`")
+            }
+            SemanticError::NestedFunction { name, .. } => {
+                write!(f, "function `{name}` defined inside another function")
+            }
+            SemanticError::NotAFunction { name, .. } => {
+                write!(f, "called object `{name}` is not a function")
+            }
+            SemanticError::FunctionAsValue { name, .. } => {
+                write!(f, "`{name}` is a function, not a variable")
+            }
+            SemanticError::ArgumentCount {
+                name,
+                expected,
+                found,
+                variadic,
+                ..
+            } => write!(
+                f,
+                "function `{name}` takes {}{expected} argument{} but {found} {} given",
+                if *variadic { "at least " } else { "" },
+                if *expected == 1 { "" } else { "s" },
+                if *found == 1 { "was" } else { "were" }
+            ),
+            SemanticError::ConflictingLinkage {
+                name, is_static, ..
+            } => {
+                if *is_static {
+                    write!(
+                        f,
+                        "`static` declaration of `{name}` follows a non-`static` one"
+                    )
+                } else {
+                    write!(
+                        f,
+                        "non-`static` declaration of `{name}` follows a `static` one"
+                    )
+                }
+            }
+            SemanticError::ConflictingKind { name, .. } => {
+                write!(f, "`{name}` redeclared as a different kind of symbol")
+            }
+            SemanticError::InvalidStorageClass {
+                storage, context, ..
+            } => write!(f, "storage class `{storage}` is not allowed {context}"),
+            SemanticError::NonConstantInitializer { name, .. } => {
+                write!(
+                    f,
+                    "initializer of static variable `{name}` is not a constant"
+                )
+            }
+            SemanticError::ExternInitializer { name, .. } => {
+                write!(f, "`extern` variable `{name}` has an initializer")
+            }
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use crate::parser::parser::Node;
+use crate::lexer::span::Span;
+use crate::parser::error::SemanticError;
+use crate::parser::parser::{Node, Operations};
+use crate::parser::types::{Constant, Symbols, Type};
 use std::collections::VecDeque;
-use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU32, Ordering};
 
 #[derive(Debug, Clone)]
 pub enum IntermediateOperations {
@@ -12,11 +15,56 @@ pub enum IntermediateOperations {
     Var,
     Complement,
     Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    LeftShift,
+    RightShift,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    Copy,
+    /// Conversions between integer types of different sizes, from `lhs`
+    /// to `rhs` in a `Binary`. Conversions between types of the same size
+    /// are a `Copy`.
+    SignExtend,
+    ZeroExtend,
+    Truncate,
+    Jump,
+    JumpIfZero,
+    JumpIfNotZero,
+    /// Jumps through the table named by `dst` in a `Ternary`: `lhs` is
+    /// the index and `rhs` the sequence of target labels.
+    JumpTable,
+    Label,
+    /// Calls the function named by `lhs` in a `Ternary` with the
+    /// arguments in `rhs`, storing the result in `dst`.
+    Call,
+    /// Like `Call`, for a function taking variable arguments.
+    VariadicCall,
+    /// Stores the next parameter of the function in `node`. These come
+    /// first in a function body, in order.
+    Param,
+    /// A variable with static storage duration: a `Binary` defines the
+    /// one named by `lhs` with the initial value in `rhs`, a `Unary`
+    /// declares the one named by `node`, defined in another file.
+    StaticVariable,
+    /// Gives the function or variable defined by `node` internal linkage.
+    Static,
 }
 
 #[derive(Debug, Clone)]
 pub enum IntermediateNode {
-    Int(i32),
+    Int(Constant),
     Str(String),
     Unary {
         op: IntermediateOperations,
@@ -27,26 +75,46 @@ pub enum IntermediateNode {
         lhs: Box<IntermediateNode>,
         rhs: Box<IntermediateNode>,
     },
+    /// Instruction with two sources and a destination.
+    Ternary {
+        op: IntermediateOperations,
+        lhs: Box<IntermediateNode>,
+        rhs: Box<IntermediateNode>,
+        dst: Box<IntermediateNode>,
+    },
     Sequence(VecDeque<Box<IntermediateNode>>),
 }
 
-static mut TEMPORARY_COUNT: u32 = 0u32;
+static TEMPORARY_COUNT: AtomicU32 = AtomicU32::new(0);
+static LABEL_COUNT: AtomicU32 = AtomicU32::new(0);
 
 fn get_current_temporary() -> String {
-    unsafe { format!("temp.{TEMPORARY_COUNT}") }
+    format!("temp.{}", TEMPORARY_COUNT.load(Ordering::Relaxed))
 }
 
 fn get_next_temporary() -> String {
-    unsafe {
-        TEMPORARY_COUNT += 1;
-    }
+    TEMPORARY_COUNT.fetch_add(1, Ordering::Relaxed);
     get_current_temporary()
 }
 
+/// Unique name for a local variable. It is numbered alongside the
+/// temporaries, so the two can never clash.
+pub fn get_unique_name(name: &str) -> String {
+    format!(
+        "{name}.{}",
+        TEMPORARY_COUNT.fetch_add(1, Ordering::Relaxed) + 1
+    )
+}
+
+/// Unique label name, e.g. `and_false.3`.
+pub fn get_next_label(name: &str) -> String {
+    format!("{name}.{}", LABEL_COUNT.fetch_add(1, Ordering::Relaxed))
+}
+
 fn get_constant_or_var(src: Box<IntermediateNode>) -> Box<IntermediateNode> {
     match &*src {
         IntermediateNode::Unary {
-            op: IntermediateOperations::Constant,
+            op: IntermediateOperations::Constant | IntermediateOperations::Var,
             node: _,
         } => src,
         _ => Box::new(IntermediateNode::Unary {
@@ -56,22 +124,381 @@ fn get_constant_or_var(src: Box<IntermediateNode>) -> Box<IntermediateNode> {
     }
 }
 
-fn get_next_node() -> Box<IntermediateNode> {
+/// A new temporary of type `ty`.
+fn get_next_node(ty: Type, symbols: &mut Symbols) -> Box<IntermediateNode> {
+    let name = get_next_temporary();
+    symbols.insert(name.clone(), ty);
     Box::new(IntermediateNode::Unary {
         op: IntermediateOperations::Var,
-        node: Box::new(IntermediateNode::Str(get_next_temporary())),
+        node: Box::new(IntermediateNode::Str(name)),
+    })
+}
+
+/// Type of a constant or variable operand.
+pub fn value_type(node: &IntermediateNode, symbols: &Symbols) -> Option<Type> {
+    match node {
+        IntermediateNode::Unary {
+            op: IntermediateOperations::Constant,
+            node,
+        } => match **node {
+            IntermediateNode::Int(constant) => Some(constant.ty),
+            _ => None,
+        },
+        IntermediateNode::Unary {
+            op: IntermediateOperations::Var,
+            node,
+        } => match &**node {
+            IntermediateNode::Str(name) => symbols.get(name).copied(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn operand_type(node: &IntermediateNode, symbols: &Symbols) -> Result<Type, SemanticError> {
+    value_type(node, symbols).ok_or_else(|| SemanticError::UnexpectedNode {
+        node: format!("operand {:?} without a type", node),
+        span: None,
+    })
+}
+
+fn constant(value: Constant) -> Box<IntermediateNode> {
+    Box::new(IntermediateNode::Unary {
+        op: IntermediateOperations::Constant,
+        node: Box::new(IntermediateNode::Int(value)),
+    })
+}
+
+fn label(op: IntermediateOperations, name: &str) -> Box<IntermediateNode> {
+    Box::new(IntermediateNode::Unary {
+        op,
+        node: Box::new(IntermediateNode::Str(name.to_string())),
+    })
+}
+
+fn jump(
+    op: IntermediateOperations,
+    condition: Box<IntermediateNode>,
+    target: &str,
+) -> Box<IntermediateNode> {
+    Box::new(IntermediateNode::Binary {
+        op,
+        lhs: condition,
+        rhs: Box::new(IntermediateNode::Str(target.to_string())),
+    })
+}
+
+fn copy(src: Box<IntermediateNode>, dst: Box<IntermediateNode>) -> Box<IntermediateNode> {
+    Box::new(IntermediateNode::Binary {
+        op: IntermediateOperations::Copy,
+        lhs: src,
+        rhs: dst,
     })
 }
 
+/// Lowers `&&` and `||`, evaluating the right operand only when the left
+/// one does not decide the result.
+fn create_logical(
+    op: &Operations,
+    lhs: &Node,
+    rhs: &Node,
+    sequence: &mut VecDeque<Box<IntermediateNode>>,
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    let (jump_op, short_circuit, name) = match op {
+        Operations::And => (IntermediateOperations::JumpIfZero, 0, "and"),
+        _ => (IntermediateOperations::JumpIfNotZero, 1, "or"),
+    };
+    let short_label = get_next_label(&format!("{name}_short"));
+    let end_label = get_next_label(&format!("{name}_end"));
+    let src1 = get_constant_or_var(create_functions(lhs, sequence, symbols)?);
+    sequence.push_back(jump(jump_op.clone(), src1, &short_label));
+    let src2 = get_constant_or_var(create_functions(rhs, sequence, symbols)?);
+    sequence.push_back(jump(jump_op, src2, &short_label));
+    let dst = get_next_node(Type::Int, symbols);
+    let result = |value| constant(Constant::new(value, Type::Int));
+    sequence.push_back(copy(result(1 - short_circuit), dst.clone()));
+    sequence.push_back(label(IntermediateOperations::Jump, &end_label));
+    sequence.push_back(label(IntermediateOperations::Label, &short_label));
+    sequence.push_back(copy(result(short_circuit), dst.clone()));
+    sequence.push_back(label(IntermediateOperations::Label, &end_label));
+    Ok(dst)
+}
+
+/// Lowers `if` statements and `?:`, which differ only in whether the
+/// branches produce a value.
+fn create_conditional(
+    op: &Operations,
+    condition: &Node,
+    then: &Node,
+    otherwise: Option<&Node>,
+    sequence: &mut VecDeque<Box<IntermediateNode>>,
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    let name = match op {
+        Operations::If => "if",
+        _ => "conditional",
+    };
+    let else_label = get_next_label(&format!("{name}_else"));
+    let end_label = get_next_label(&format!("{name}_end"));
+    let src = get_constant_or_var(create_functions(condition, sequence, symbols)?);
+    let Some(otherwise) = otherwise else {
+        sequence.push_back(jump(IntermediateOperations::JumpIfZero, src, &end_label));
+        create_functions(then, sequence, symbols)?;
+        sequence.push_back(label(IntermediateOperations::Label, &end_label));
+        return Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())));
+    };
+    sequence.push_back(jump(IntermediateOperations::JumpIfZero, src, &else_label));
+    let src1 = get_constant_or_var(create_functions(then, sequence, symbols)?);
+    // Type checking gave both branches of `?:` the same type.
+    let dst = match op {
+        Operations::If => None,
+        _ => Some(get_next_node(operand_type(&src1, symbols)?, symbols)),
+    };
+    if let Some(dst) = &dst {
+        sequence.push_back(copy(src1, dst.clone()));
+    }
+    sequence.push_back(label(IntermediateOperations::Jump, &end_label));
+    sequence.push_back(label(IntermediateOperations::Label, &else_label));
+    let src2 = get_constant_or_var(create_functions(otherwise, sequence, symbols)?);
+    if let Some(dst) = &dst {
+        sequence.push_back(copy(src2, dst.clone()));
+    }
+    sequence.push_back(label(IntermediateOperations::Label, &end_label));
+    Ok(dst.unwrap_or_else(|| Box::new(IntermediateNode::Sequence(VecDeque::new()))))
+}
+
+/// Lowers the three loop forms. `continue` jumps to `continue_<label>`
+/// and `break` to `break_<label>`.
+fn create_loop(
+    ast: &Node,
+    sequence: &mut VecDeque<Box<IntermediateNode>>,
+    symbols: &mut Symbols,
+) -> Result<(), SemanticError> {
+    let Node::Loop {
+        op,
+        init,
+        condition,
+        post,
+        body,
+        label: name,
+        ..
+    } = ast
+    else {
+        return Err(SemanticError::UnexpectedNode {
+            node: format!("loop {:?}", ast),
+            span: None,
+        });
+    };
+    let start_label = format!("start_{name}");
+    let continue_label = format!("continue_{name}");
+    let break_label = format!("break_{name}");
+    if let Some(init) = init {
+        create_functions(init, sequence, symbols)?;
+    }
+    if let Operations::DoWhile = op {
+        sequence.push_back(label(IntermediateOperations::Label, &start_label));
+        create_functions(body, sequence, symbols)?;
+        sequence.push_back(label(IntermediateOperations::Label, &continue_label));
+        if let Some(condition) = condition {
+            let src = get_constant_or_var(create_functions(condition, sequence, symbols)?);
+            sequence.push_back(jump(
+                IntermediateOperations::JumpIfNotZero,
+                src,
+                &start_label,
+            ));
+        }
+    } else {
+        // A `while` loop starts again from its `continue` label, a `for`
+        // loop from before its condition, after the post expression.
+        let top_label = match op {
+            Operations::While => &continue_label,
+            _ => &start_label,
+        };
+        sequence.push_back(label(IntermediateOperations::Label, top_label));
+        if let Some(condition) = condition {
+            let src = get_constant_or_var(create_functions(condition, sequence, symbols)?);
+            sequence.push_back(jump(IntermediateOperations::JumpIfZero, src, &break_label));
+        }
+        create_functions(body, sequence, symbols)?;
+        if top_label != &continue_label {
+            sequence.push_back(label(IntermediateOperations::Label, &continue_label));
+        }
+        if let Some(post) = post {
+            create_functions(post, sequence, symbols)?;
+        }
+        sequence.push_back(label(IntermediateOperations::Jump, top_label));
+    }
+    sequence.push_back(label(IntermediateOperations::Label, &break_label));
+    Ok(())
+}
+
+/// A switch with at least this many cases may use a jump table.
+const JUMP_TABLE_MIN_CASES: usize = 4;
+/// A jump table may have at most this many entries per case; the rest
+/// lead to `default`.
+const JUMP_TABLE_MAX_ENTRIES_PER_CASE: i64 = 3;
+
+/// Lowers a switch to a jump table when its cases are dense enough, and
+/// to a chain of comparisons otherwise.
+fn create_switch(
+    condition: &Node,
+    body: &Node,
+    name: &str,
+    cases: &[(i64, String)],
+    default: Option<&str>,
+    sequence: &mut VecDeque<Box<IntermediateNode>>,
+    symbols: &mut Symbols,
+) -> Result<(), SemanticError> {
+    let break_label = format!("break_{name}");
+    let fallback = default.unwrap_or(&break_label);
+    let src = get_constant_or_var(create_functions(condition, sequence, symbols)?);
+    // Case values have the type of the condition, and are ordered as
+    // values of that type.
+    let ty = operand_type(&src, symbols)?;
+    let key = |value: i64| match ty.is_signed() {
+        true => value as i128,
+        false => value as u64 as i128,
+    };
+    let min = cases
+        .iter()
+        .map(|case| case.0)
+        .min_by_key(|value| key(*value));
+    let max = cases
+        .iter()
+        .map(|case| case.0)
+        .max_by_key(|value| key(*value));
+    let (min, max) = (min.unwrap_or(0), max.unwrap_or(0));
+    let entries = key(max) - key(min) + 1;
+    if cases.len() >= JUMP_TABLE_MIN_CASES
+        && entries <= JUMP_TABLE_MAX_ENTRIES_PER_CASE as i128 * cases.len() as i128
+    {
+        for (op, bound) in [
+            (IntermediateOperations::LessThan, min),
+            (IntermediateOperations::GreaterThan, max),
+        ] {
+            let dst = get_next_node(Type::Int, symbols);
+            sequence.push_back(Box::new(IntermediateNode::Ternary {
+                op,
+                lhs: src.clone(),
+                rhs: constant(Constant::new(bound, ty)),
+                dst: dst.clone(),
+            }));
+            sequence.push_back(jump(IntermediateOperations::JumpIfNotZero, dst, fallback));
+        }
+        let index = get_next_node(ty, symbols);
+        sequence.push_back(Box::new(IntermediateNode::Ternary {
+            op: IntermediateOperations::Subtract,
+            lhs: src,
+            rhs: constant(Constant::new(min, ty)),
+            dst: index.clone(),
+        }));
+        let mut targets = vec![fallback.to_string(); entries as usize];
+        for (value, label) in cases {
+            targets[(key(*value) - key(min)) as usize] = label.clone();
+        }
+        sequence.push_back(Box::new(IntermediateNode::Ternary {
+            op: IntermediateOperations::JumpTable,
+            lhs: index,
+            rhs: Box::new(IntermediateNode::Sequence(
+                targets
+                    .into_iter()
+                    .map(|target| Box::new(IntermediateNode::Str(target)))
+                    .collect(),
+            )),
+            dst: Box::new(IntermediateNode::Str(format!("table_{name}"))),
+        }));
+    } else {
+        for (value, label) in cases {
+            let dst = get_next_node(Type::Int, symbols);
+            sequence.push_back(Box::new(IntermediateNode::Ternary {
+                op: IntermediateOperations::Equal,
+                lhs: src.clone(),
+                rhs: constant(Constant::new(*value, ty)),
+                dst: dst.clone(),
+            }));
+            sequence.push_back(jump(IntermediateOperations::JumpIfNotZero, dst, label));
+        }
+        sequence.push_back(label(IntermediateOperations::Jump, fallback));
+    }
+    create_functions(body, sequence, symbols)?;
+    sequence.push_back(label(IntermediateOperations::Label, &break_label));
+    Ok(())
+}
+
+/// TACKY instruction for a binary operator, or for the operation done by
+/// a compound assignment.
+fn binary_operation(op: &Operations) -> Option<IntermediateOperations> {
+    let op = match op {
+        Operations::Add | Operations::AddAssign => IntermediateOperations::Add,
+        Operations::Subtract | Operations::SubtractAssign => IntermediateOperations::Subtract,
+        Operations::Multiply | Operations::MultiplyAssign => IntermediateOperations::Multiply,
+        Operations::Divide | Operations::DivideAssign => IntermediateOperations::Divide,
+        Operations::Remainder | Operations::RemainderAssign => IntermediateOperations::Remainder,
+        Operations::BitwiseAnd | Operations::BitwiseAndAssign => IntermediateOperations::BitwiseAnd,
+        Operations::BitwiseOr | Operations::BitwiseOrAssign => IntermediateOperations::BitwiseOr,
+        Operations::BitwiseXor | Operations::BitwiseXorAssign => IntermediateOperations::BitwiseXor,
+        Operations::LeftShift | Operations::LeftShiftAssign => IntermediateOperations::LeftShift,
+        Operations::RightShift | Operations::RightShiftAssign => IntermediateOperations::RightShift,
+        Operations::Equal => IntermediateOperations::Equal,
+        Operations::NotEqual => IntermediateOperations::NotEqual,
+        Operations::LessThan => IntermediateOperations::LessThan,
+        Operations::LessOrEqual => IntermediateOperations::LessOrEqual,
+        Operations::GreaterThan => IntermediateOperations::GreaterThan,
+        Operations::GreaterOrEqual => IntermediateOperations::GreaterOrEqual,
+        _ => return None,
+    };
+    Some(op)
+}
+
+pub fn is_compound_assignment(op: &Operations) -> bool {
+    matches!(
+        op,
+        Operations::AddAssign
+            | Operations::SubtractAssign
+            | Operations::MultiplyAssign
+            | Operations::DivideAssign
+            | Operations::RemainderAssign
+            | Operations::BitwiseAndAssign
+            | Operations::BitwiseOrAssign
+            | Operations::BitwiseXorAssign
+            | Operations::LeftShiftAssign
+            | Operations::RightShiftAssign
+    )
+}
+
+/// The variable assigned to by an assignment.
+fn create_lvalue(
+    ast: &Node,
+    span: &Span,
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    match ast {
+        Node::Unary {
+            op: Operations::Var,
+            node,
+            ..
+        } => Ok(Box::new(IntermediateNode::Unary {
+            op: IntermediateOperations::Var,
+            node: create_intermediate(node, symbols)?,
+        })),
+        _ => Err(SemanticError::UnexpectedNode {
+            node: "assignment to a non-variable".to_string(),
+            span: Some(span.clone()),
+        }),
+    }
+}
+
 fn create_functions(
-    ast: &Box<Node>,
+    ast: &Node,
     sequence: &mut VecDeque<Box<IntermediateNode>>,
-) -> std::io::Result<Box<IntermediateNode>> {
-    match &**ast {
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    match ast {
         Node::Int(val) => Ok(Box::new(IntermediateNode::Int(*val))),
-        Node::Unary { op, node } => match op {
+        Node::Unary { op, node, span } => match op {
             crate::parser::parser::Operations::Return => {
-                let src = create_functions(node, sequence)?;
+                let src = create_functions(node, sequence, symbols)?;
                 let src_node = get_constant_or_var(src);
                 sequence.push_back(Box::new(IntermediateNode::Unary {
                     op: IntermediateOperations::Return,
@@ -81,12 +508,72 @@ fn create_functions(
             }
             crate::parser::parser::Operations::Constant => Ok(Box::new(IntermediateNode::Unary {
                 op: IntermediateOperations::Constant,
-                node: create_intermediate(node)?,
+                node: create_intermediate(node, symbols)?,
             })),
+            crate::parser::parser::Operations::Var => Ok(Box::new(IntermediateNode::Unary {
+                op: IntermediateOperations::Var,
+                node: create_intermediate(node, symbols)?,
+            })),
+            // Locals need no code until they are assigned.
+            crate::parser::parser::Operations::Declaration(_) => {
+                Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())))
+            }
+            crate::parser::parser::Operations::Expression => {
+                create_functions(node, sequence, symbols)
+            }
+            crate::parser::parser::Operations::Break
+            | crate::parser::parser::Operations::Continue
+            | crate::parser::parser::Operations::Goto => {
+                let IntermediateNode::Str(name) = *create_intermediate(node, symbols)? else {
+                    return Err(SemanticError::UnexpectedNode {
+                        node: format!("{:?} target", op),
+                        span: Some(span.clone()),
+                    });
+                };
+                let target = match op {
+                    crate::parser::parser::Operations::Break => format!("break_{name}"),
+                    crate::parser::parser::Operations::Continue => format!("continue_{name}"),
+                    _ => name,
+                };
+                sequence.push_back(label(IntermediateOperations::Jump, &target));
+                Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())))
+            }
+            crate::parser::parser::Operations::Cast(ty) => {
+                let src = get_constant_or_var(create_functions(node, sequence, symbols)?);
+                // Constants are converted right away.
+                if let IntermediateNode::Unary {
+                    op: IntermediateOperations::Constant,
+                    node,
+                } = &*src
+                {
+                    if let IntermediateNode::Int(value) = **node {
+                        return Ok(constant(Constant::new(value.value, *ty)));
+                    }
+                }
+                let from = operand_type(&src, symbols)?;
+                let op = if from == *ty {
+                    return Ok(src);
+                } else if from.size() == ty.size() {
+                    IntermediateOperations::Copy
+                } else if from.size() > ty.size() {
+                    IntermediateOperations::Truncate
+                } else if from.is_signed() {
+                    IntermediateOperations::SignExtend
+                } else {
+                    IntermediateOperations::ZeroExtend
+                };
+                let dst = get_next_node(*ty, symbols);
+                sequence.push_back(Box::new(IntermediateNode::Binary {
+                    op,
+                    lhs: src,
+                    rhs: dst.clone(),
+                }));
+                Ok(dst)
+            }
             crate::parser::parser::Operations::Negate => {
-                let src = create_functions(node, sequence)?;
+                let src = create_functions(node, sequence, symbols)?;
                 let src_node = get_constant_or_var(src);
-                let dst = get_next_node();
+                let dst = get_next_node(operand_type(&src_node, symbols)?, symbols);
                 sequence.push_back(Box::new(IntermediateNode::Binary {
                     op: IntermediateOperations::Negate,
                     lhs: src_node,
@@ -94,10 +581,21 @@ fn create_functions(
                 }));
                 Ok(dst)
             }
+            crate::parser::parser::Operations::Not => {
+                let src = create_functions(node, sequence, symbols)?;
+                let src_node = get_constant_or_var(src);
+                let dst = get_next_node(Type::Int, symbols);
+                sequence.push_back(Box::new(IntermediateNode::Binary {
+                    op: IntermediateOperations::Not,
+                    lhs: src_node,
+                    rhs: dst.clone(),
+                }));
+                Ok(dst)
+            }
             crate::parser::parser::Operations::Complement => {
-                let src = create_functions(node, sequence)?;
+                let src = create_functions(node, sequence, symbols)?;
                 let src_node = get_constant_or_var(src);
-                let dst = get_next_node();
+                let dst = get_next_node(operand_type(&src_node, symbols)?, symbols);
                 sequence.push_back(Box::new(IntermediateNode::Binary {
                     op: IntermediateOperations::Complement,
                     lhs: src_node,
@@ -105,58 +603,430 @@ fn create_functions(
                 }));
                 Ok(dst)
             }
-            _ => {
-                let error = format!("Unexpected unary AST node {:?}", op);
-                Err(Error::new(ErrorKind::InvalidInput, error))
-            }
+            _ => Err(SemanticError::UnexpectedNode {
+                node: format!("unary {:?}", op),
+                span: Some(span.clone()),
+            }),
         },
-        _ => {
-            let error = format!("Unexpected AST node");
-            Err(Error::new(ErrorKind::InvalidInput, error))
+        Node::Str(val) => Err(SemanticError::UnexpectedNode {
+            node: format!("`{val}`"),
+            span: None,
+        }),
+        Node::Ternary {
+            op,
+            condition,
+            then,
+            otherwise,
+            ..
+        } => create_conditional(op, condition, then, otherwise.as_deref(), sequence, symbols),
+        Node::Loop { .. } => {
+            create_loop(ast, sequence, symbols)?;
+            Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())))
+        }
+        // Only definitions need code, and they are at the top level.
+        Node::Function { .. } => Ok(Box::new(IntermediateNode::Sequence(VecDeque::new()))),
+        Node::Call {
+            name,
+            args,
+            variadic,
+            ..
+        } => {
+            let mut srcs = VecDeque::new();
+            for arg in args {
+                srcs.push_back(get_constant_or_var(create_functions(
+                    arg, sequence, symbols,
+                )?));
+            }
+            let Some(&ty) = symbols.get(name) else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("call of `{name}` without a return type"),
+                    span: None,
+                });
+            };
+            let dst = get_next_node(ty, symbols);
+            sequence.push_back(Box::new(IntermediateNode::Ternary {
+                op: if *variadic {
+                    IntermediateOperations::VariadicCall
+                } else {
+                    IntermediateOperations::Call
+                },
+                lhs: Box::new(IntermediateNode::Str(name.clone())),
+                rhs: Box::new(IntermediateNode::Sequence(srcs)),
+                dst: dst.clone(),
+            }));
+            Ok(dst)
+        }
+        Node::Switch {
+            condition,
+            body,
+            label,
+            cases,
+            default,
+            ..
+        } => {
+            create_switch(
+                condition,
+                body,
+                label,
+                cases,
+                default.as_deref(),
+                sequence,
+                symbols,
+            )?;
+            Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())))
+        }
+        Node::Labeled {
+            label: name, node, ..
+        } => {
+            sequence.push_back(label(IntermediateOperations::Label, name));
+            create_functions(node, sequence, symbols)
+        }
+        Node::Sequence(items) => {
+            for item in items {
+                create_functions(item, sequence, symbols)?;
+            }
+            Ok(Box::new(IntermediateNode::Sequence(VecDeque::new())))
+        }
+        Node::Binary {
+            op: op @ (Operations::And | Operations::Or),
+            lhs,
+            rhs,
+            ..
+        } => create_logical(op, lhs, rhs, sequence, symbols),
+        Node::Binary {
+            op: Operations::Declaration(_),
+            lhs,
+            rhs,
+            ..
+        } => {
+            let src = get_constant_or_var(create_functions(rhs, sequence, symbols)?);
+            let dst = create_functions(lhs, sequence, symbols)?;
+            sequence.push_back(copy(src, dst.clone()));
+            Ok(dst)
+        }
+        Node::Binary {
+            op: Operations::Assign,
+            lhs,
+            rhs,
+            span,
+        } => {
+            let dst = create_lvalue(lhs, span, symbols)?;
+            let src = get_constant_or_var(create_functions(rhs, sequence, symbols)?);
+            sequence.push_back(copy(src, dst.clone()));
+            Ok(dst)
+        }
+        Node::Binary { op, lhs, rhs, span } if is_compound_assignment(op) => {
+            let dst = create_lvalue(lhs, span, symbols)?;
+            let src = get_constant_or_var(create_functions(rhs, sequence, symbols)?);
+            sequence.push_back(Box::new(IntermediateNode::Ternary {
+                op: binary_operation(op).unwrap(),
+                lhs: dst.clone(),
+                rhs: src,
+                dst: dst.clone(),
+            }));
+            Ok(dst)
+        }
+        Node::Binary { op, lhs, rhs, span } => {
+            let Some(op) = binary_operation(op) else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("binary {:?}", op),
+                    span: Some(span.clone()),
+                });
+            };
+            let src1 = get_constant_or_var(create_functions(lhs, sequence, symbols)?);
+            let src2 = get_constant_or_var(create_functions(rhs, sequence, symbols)?);
+            let ty = match op {
+                IntermediateOperations::Equal
+                | IntermediateOperations::NotEqual
+                | IntermediateOperations::LessThan
+                | IntermediateOperations::LessOrEqual
+                | IntermediateOperations::GreaterThan
+                | IntermediateOperations::GreaterOrEqual => Type::Int,
+                _ => operand_type(&src1, symbols)?,
+            };
+            let dst = get_next_node(ty, symbols);
+            sequence.push_back(Box::new(IntermediateNode::Ternary {
+                op,
+                lhs: src1,
+                rhs: src2,
+                dst: dst.clone(),
+            }));
+            Ok(dst)
         }
     }
 }
 
-pub fn create_intermediate(ast: &Box<Node>) -> std::io::Result<Box<IntermediateNode>> {
-    match &**ast {
+pub fn create_intermediate(
+    ast: &Node,
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    match ast {
         Node::Int(val) => Ok(Box::new(IntermediateNode::Int(*val))),
         Node::Str(val) => Ok(Box::new(IntermediateNode::Str(val.clone()))),
-        Node::Unary { op, node } => match op {
+        // The top level: function definitions and variables with static
+        // storage duration. Other declarations need no code.
+        Node::Sequence(items) => {
+            let mut functions = VecDeque::new();
+            for item in items {
+                if let Node::Function { body: None, .. } = **item {
+                    continue;
+                }
+                functions.push_back(create_intermediate(item, symbols)?);
+            }
+            Ok(Box::new(IntermediateNode::Sequence(functions)))
+        }
+        Node::Function {
+            name,
+            return_type,
+            params,
+            body: Some(body),
+            span,
+            ..
+        } => {
+            let mut sequence: VecDeque<Box<IntermediateNode>> = VecDeque::new();
+            for param in params {
+                let Node::Unary {
+                    op: Operations::Declaration(_),
+                    node: var,
+                    ..
+                } = &**param
+                else {
+                    return Err(SemanticError::UnexpectedNode {
+                        node: format!("parameter {:?}", param),
+                        span: Some(span.clone()),
+                    });
+                };
+                let param = create_functions(var, &mut sequence, symbols)?;
+                sequence.push_back(Box::new(IntermediateNode::Unary {
+                    op: IntermediateOperations::Param,
+                    node: param,
+                }));
+            }
+            create_functions(body, &mut sequence, symbols)?;
+            // Falling off the end of a function returns 0, which is
+            // what `main` must do.
+            sequence.push_back(Box::new(IntermediateNode::Unary {
+                op: IntermediateOperations::Return,
+                node: constant(Constant::new(0, *return_type)),
+            }));
+            Ok(Box::new(IntermediateNode::Binary {
+                op: IntermediateOperations::Function,
+                lhs: Box::new(IntermediateNode::Str(name.clone())),
+                rhs: Box::new(IntermediateNode::Sequence(sequence)),
+            }))
+        }
+        Node::Function { span, .. } | Node::Call { span, .. } => {
+            Err(SemanticError::UnexpectedNode {
+                node: format!("{:?} at the top level", ast),
+                span: Some(span.clone()),
+            })
+        }
+        Node::Switch { span, .. } => Err(SemanticError::UnexpectedNode {
+            node: "switch outside a function".to_string(),
+            span: Some(span.clone()),
+        }),
+        Node::Ternary { op, span, .. }
+        | Node::Loop { op, span, .. }
+        | Node::Labeled { op, span, .. } => Err(SemanticError::UnexpectedNode {
+            node: format!("{:?} outside a function", op),
+            span: Some(span.clone()),
+        }),
+        Node::Unary { op, node, span } => match op {
             crate::parser::parser::Operations::Program => Ok(Box::new(IntermediateNode::Unary {
                 op: IntermediateOperations::Program,
-                node: create_intermediate(node)?,
+                node: create_intermediate(node, symbols)?,
             })),
             crate::parser::parser::Operations::Constant => Ok(Box::new(IntermediateNode::Unary {
                 op: IntermediateOperations::Constant,
-                node: create_intermediate(node)?,
+                node: create_intermediate(node, symbols)?,
             })),
-            _ => {
-                let error = format!("Unexpected unary AST node {:?}", op);
-                Err(Error::new(ErrorKind::InvalidInput, error))
-            }
+            crate::parser::parser::Operations::Static => Ok(Box::new(IntermediateNode::Unary {
+                op: IntermediateOperations::Static,
+                node: create_intermediate(node, symbols)?,
+            })),
+            crate::parser::parser::Operations::Extern => match &**node {
+                Node::Unary {
+                    op: Operations::Declaration(_),
+                    node: var,
+                    ..
+                } => Ok(Box::new(IntermediateNode::Unary {
+                    op: IntermediateOperations::StaticVariable,
+                    node: static_name(var, span, symbols)?,
+                })),
+                _ => Err(SemanticError::UnexpectedNode {
+                    node: format!("extern {:?}", node),
+                    span: Some(span.clone()),
+                }),
+            },
+            _ => Err(SemanticError::UnexpectedNode {
+                node: format!("unary {:?}", op),
+                span: Some(span.clone()),
+            }),
         },
-        Node::Binary { op, lhs, rhs } => match op {
-            crate::parser::parser::Operations::Function => {
-                let mut sequence: VecDeque<Box<IntermediateNode>> = VecDeque::new();
-                create_functions(rhs, &mut sequence)?;
-                Ok(Box::new(IntermediateNode::Binary {
-                    op: IntermediateOperations::Function,
-                    lhs: create_intermediate(lhs)?,
-                    rhs: Box::new(IntermediateNode::Sequence(sequence)),
-                }))
-            }
-            _ => {
-                let error = format!("Unexpected binary AST node {:?}", op);
-                Err(Error::new(ErrorKind::InvalidInput, error))
-            }
+        // Semantic analysis leaves only constant initializers here.
+        Node::Binary {
+            op: Operations::Declaration(_),
+            lhs,
+            rhs,
+            span,
+        } => match &**rhs {
+            Node::Unary {
+                op: Operations::Constant,
+                node: value,
+                ..
+            } => Ok(Box::new(IntermediateNode::Binary {
+                op: IntermediateOperations::StaticVariable,
+                lhs: static_name(lhs, span, symbols)?,
+                rhs: create_intermediate(value, symbols)?,
+            })),
+            _ => Err(SemanticError::UnexpectedNode {
+                node: format!("static initializer {:?}", rhs),
+                span: Some(span.clone()),
+            }),
         },
+        Node::Binary { op, span, .. } => Err(SemanticError::UnexpectedNode {
+            node: format!("binary {:?}", op),
+            span: Some(span.clone()),
+        }),
     }
 }
 
-pub fn intermediate(ast: &Box<Node>, debug_mode: bool) -> std::io::Result<Box<IntermediateNode>> {
-    let res = create_intermediate(ast)?;
+/// Name of a variable with static storage duration, from its `Var` node.
+fn static_name(
+    var: &Node,
+    span: &Span,
+    symbols: &mut Symbols,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    match var {
+        Node::Unary {
+            op: Operations::Var,
+            node,
+            ..
+        } => create_intermediate(node, symbols),
+        _ => Err(SemanticError::UnexpectedNode {
+            node: format!("static variable {:?}", var),
+            span: Some(span.clone()),
+        }),
+    }
+}
+
+/// Lowers the program to TACKY, adding the types of the temporaries it
+/// creates to `symbols`.
+pub fn intermediate(
+    ast: &Node,
+    symbols: &mut Symbols,
+    debug_mode: bool,
+) -> Result<Box<IntermediateNode>, SemanticError> {
+    let res = create_intermediate(ast, symbols)?;
     if debug_mode {
         println!("{:?}", res)
     };
     Ok(res)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::lex;
+    use crate::parser::{parser::parse, semantic::analyze};
+
+    /// How a switch was lowered: the targets of its jump table, if it got
+    /// one, and the number of comparisons against case values.
+    #[derive(Debug, Default)]
+    struct Lowering {
+        table: Option<Vec<String>>,
+        comparisons: usize,
+    }
+
+    fn find_switch(node: &IntermediateNode, lowering: &mut Lowering) {
+        match node {
+            IntermediateNode::Ternary {
+                op: IntermediateOperations::JumpTable,
+                rhs,
+                ..
+            } => {
+                let IntermediateNode::Sequence(targets) = &**rhs else {
+                    panic!("unexpected jump table {rhs:?}");
+                };
+                let targets = targets.iter().map(|target| match &**target {
+                    IntermediateNode::Str(label) => label.clone(),
+                    target => panic!("unexpected target {target:?}"),
+                });
+                lowering.table = Some(targets.collect());
+            }
+            IntermediateNode::Ternary {
+                op: IntermediateOperations::Equal,
+                ..
+            } => lowering.comparisons += 1,
+            IntermediateNode::Unary { node, .. } => find_switch(node, lowering),
+            IntermediateNode::Binary { lhs, rhs, .. } => {
+                find_switch(lhs, lowering);
+                find_switch(rhs, lowering);
+            }
+            IntermediateNode::Sequence(nodes) => {
+                nodes.iter().for_each(|node| find_switch(node, lowering))
+            }
+            _ => {}
+        }
+    }
+
+    /// Lowers a switch on `condition`, of type `ty`, with the given case
+    /// values.
+    fn lower_switch(ty: &str, condition: &str, cases: &[&str]) -> Lowering {
+        let cases: String = cases
+            .iter()
+            .map(|value| format!("case {value}: ;\n"))
+            .collect();
+        let source = format!(
+            "int main(void) {{ {ty} x = {condition};\nswitch (x) {{\n{cases}}}\nreturn 0; }}"
+        );
+        let ast = parse(&mut lex(&source, "test.c", false).unwrap(), false).unwrap();
+        let (ast, mut symbols) = analyze(*ast, false).unwrap();
+        let tacky = intermediate(&ast, &mut symbols, false).unwrap();
+        let mut lowering = Lowering::default();
+        find_switch(&tacky, &mut lowering);
+        lowering
+    }
+
+    #[test]
+    fn dense_switch_uses_a_jump_table() {
+        let lowering = lower_switch("int", "2", &["0", "1", "2", "3", "4"]);
+        assert_eq!(lowering.comparisons, 0);
+        let table = lowering.table.unwrap();
+        assert_eq!(table.len(), 5);
+        assert!(table.iter().all(|label| !label.starts_with("break_")));
+    }
+
+    #[test]
+    fn gaps_in_a_jump_table_lead_to_the_end_of_the_switch() {
+        let table = lower_switch("int", "2", &["-1", "0", "2", "5"])
+            .table
+            .unwrap();
+        assert_eq!(table.len(), 7);
+        let missing: Vec<_> = (0..table.len())
+            .filter(|index| table[*index].starts_with("break_"))
+            .collect();
+        assert_eq!(missing, [2, 4, 5]);
+    }
+
+    #[test]
+    fn sparse_switch_compares_each_case() {
+        let lowering = lower_switch("int", "2", &["1", "100", "1000", "10000"]);
+        assert!(lowering.table.is_none());
+        assert_eq!(lowering.comparisons, 4);
+        // Too few cases for a table, however dense.
+        let lowering = lower_switch("int", "2", &["1", "2", "3"]);
+        assert!(lowering.table.is_none());
+        assert_eq!(lowering.comparisons, 3);
+    }
+
+    #[test]
+    fn case_values_are_ordered_by_the_condition_type() {
+        let cases = ["-1", "0", "1", "2"];
+        assert_eq!(lower_switch("long", "2", &cases).table.unwrap().len(), 4);
+        // As unsigned values, -1 is far from the others.
+        let lowering = lower_switch("unsigned long", "2ul", &cases);
+        assert!(lowering.table.is_none());
+        assert_eq!(lowering.comparisons, 4);
+    }
+}
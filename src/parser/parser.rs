@@ -1,146 +1,1127 @@
 use crate::lexer::lexer::{Token, TokenValue};
+use crate::lexer::literal::{Encoding, Literal};
+use crate::lexer::span::Span;
+use crate::parser::error::ParseError;
+use crate::parser::types::{Constant, Type};
+use std::cell::Cell;
 use std::collections::VecDeque;
-use std::io::{Error, ErrorKind};
 
 #[derive(Debug)]
 pub enum Operations {
     Program,
-    Function,
     //Statement,
     //Expression,
     Return,
+    If,
+    While,
+    DoWhile,
+    For,
+    Break,
+    Continue,
+    Switch,
+    Case,
+    Default,
+    Goto,
+    Label,
+    /// Declaration of a variable or parameter of the given type.
+    Declaration(Type),
+    /// Storage classes, wrapping the declaration they apply to. After
+    /// semantic analysis, only file scope definitions with internal
+    /// linkage are wrapped in `Static`, and `Extern` wraps declarations
+    /// of variables defined elsewhere.
+    Static,
+    Extern,
+    Expression,
+    Var,
     Constant,
+    /// Conversion of the operand to the given type, written in the source
+    /// or added by type checking.
+    Cast(Type),
     Unary,
     Complement,
     Negate,
+    Not,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    LeftShift,
+    RightShift,
+    Equal,
+    NotEqual,
+    LessThan,
+    LessOrEqual,
+    GreaterThan,
+    GreaterOrEqual,
+    And,
+    Or,
+    Conditional,
+    Assign,
+    AddAssign,
+    SubtractAssign,
+    MultiplyAssign,
+    DivideAssign,
+    RemainderAssign,
+    BitwiseAndAssign,
+    BitwiseOrAssign,
+    BitwiseXorAssign,
+    LeftShiftAssign,
+    RightShiftAssign,
 }
 
 #[derive(Debug)]
 pub enum Node {
-    Int(i32),
+    Int(Constant),
     Str(String),
     Unary {
         op: Operations,
         node: Box<Node>,
+        span: Span,
     },
     Binary {
         op: Operations,
         lhs: Box<Node>,
         rhs: Box<Node>,
+        span: Span,
     },
+    /// `if` statements, where `otherwise` is the `else` branch, and the
+    /// `?:` operator.
+    Ternary {
+        op: Operations,
+        condition: Box<Node>,
+        then: Box<Node>,
+        otherwise: Option<Box<Node>>,
+        span: Span,
+    },
+    /// `while`, `do`-`while` and `for` loops. Only `for` has `init` and
+    /// `post`, and its `condition` may be missing. `label` stays empty
+    /// until loop labeling names the loop for its `break`s and
+    /// `continue`s.
+    Loop {
+        op: Operations,
+        init: Option<Box<Node>>,
+        condition: Option<Box<Node>>,
+        post: Option<Box<Node>>,
+        body: Box<Node>,
+        label: String,
+        span: Span,
+    },
+    /// `switch` statements. Labeling fills in `label`, and `cases` and
+    /// `default` with the values and labels of the `case` and `default`
+    /// statements of this switch.
+    Switch {
+        condition: Box<Node>,
+        body: Box<Node>,
+        label: String,
+        cases: Vec<(i64, String)>,
+        default: Option<String>,
+        span: Span,
+    },
+    /// A labeled statement: `case` with its `value`, `default`, or an
+    /// ordinary label. `label` is the target of jumps to the statement;
+    /// for ordinary labels, it is the name in the source until labeling
+    /// makes it unique.
+    Labeled {
+        op: Operations,
+        value: Option<Box<Node>>,
+        label: String,
+        node: Box<Node>,
+        span: Span,
+    },
+    /// A function declaration, with its `body` if it is a definition.
+    /// `params` are declarations without initializers, whose names are
+    /// empty if a declaration leaves them out; a `variadic` function takes
+    /// any number of arguments after them.
+    Function {
+        name: String,
+        return_type: Type,
+        params: VecDeque<Box<Node>>,
+        variadic: bool,
+        body: Option<Box<Node>>,
+        span: Span,
+    },
+    /// A function call. Semantic analysis sets `variadic` from the
+    /// declaration of the function.
+    Call {
+        name: String,
+        args: VecDeque<Box<Node>>,
+        variadic: bool,
+        span: Span,
+    },
+    /// Block items in order: declarations and statements. Each sequence
+    /// is its own scope.
+    Sequence(VecDeque<Box<Node>>),
 }
 
-fn check_token(token: &Option<TokenValue>, token_type: Token) -> std::io::Result<()> {
-    if token.is_none() {
-        let error = format!("Missing token, expected {:?}", token_type);
-        return Err(Error::new(ErrorKind::InvalidInput, error));
-    } else if token.as_ref().unwrap().token != token_type {
-        let error = format!(
-            "Bad token, found {:?}, but expected {:?}",
-            token.as_ref().unwrap().token,
-            token_type
-        );
-        return Err(Error::new(ErrorKind::InvalidInput, error));
+impl Node {
+    pub fn span(&self) -> Option<&Span> {
+        match self {
+            Node::Unary { span, .. }
+            | Node::Binary { span, .. }
+            | Node::Ternary { span, .. }
+            | Node::Loop { span, .. }
+            | Node::Switch { span, .. }
+            | Node::Labeled { span, .. }
+            | Node::Function { span, .. }
+            | Node::Call { span, .. } => Some(span),
+            _ => None,
+        }
+    }
+}
+
+/// Deepest nesting of expressions and statements accepted. Later passes
+/// recurse once per level, and the compiler thread's stack is sized for
+/// this many. C17 5.2.4.1 only asks for 127 nested blocks and 63 nested
+/// parentheses, but each operator of a chain like `a + b + c` is a level
+/// too, as the chain is a left-deep tree. The limit leaves room for long
+/// generated expressions while the stack needed at the limit stays below
+/// 1 GiB in debug builds.
+pub const MAX_NESTING_DEPTH: u32 = 25000;
+
+thread_local! {
+    static NESTING_DEPTH: Cell<u32> = const { Cell::new(0) };
+}
+
+/// Goes one nesting level deeper, failing at `span` past
+/// `MAX_NESTING_DEPTH`.
+fn enter_nesting(span: &Span) -> Result<(), ParseError> {
+    let depth = NESTING_DEPTH.get();
+    NESTING_DEPTH.set(depth + 1);
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(ParseError::NestingTooDeep { span: span.clone() });
     }
     Ok(())
 }
 
-fn identifier(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
-    let token = tokens.pop_front();
-    check_token(&token, Token::Identifier)?;
-    Ok(Box::new(Node::Str(token.unwrap().data.unwrap())))
+fn leave_nesting(levels: u32) {
+    NESTING_DEPTH.set(NESTING_DEPTH.get() - levels);
+}
+
+/// Runs `parse` one nesting level deeper.
+fn nested(
+    tokens: &mut VecDeque<TokenValue>,
+    parse: fn(&mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError>,
+) -> Result<Box<Node>, ParseError> {
+    enter_nesting(&peek(tokens)?.span)?;
+    let node = parse(tokens);
+    leave_nesting(1);
+    node
 }
 
-fn int(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
+fn check_token(token: &Option<TokenValue>, token_type: Token) -> Result<(), ParseError> {
+    match token {
+        None => Err(ParseError::UnexpectedEnd),
+        Some(token) if token.token != token_type => Err(ParseError::UnexpectedToken {
+            expected: token_type,
+            found: token.token,
+            span: token.span.clone(),
+        }),
+        Some(_) => Ok(()),
+    }
+}
+
+fn expect(tokens: &mut VecDeque<TokenValue>, token_type: Token) -> Result<TokenValue, ParseError> {
     let token = tokens.pop_front();
-    check_token(&token, Token::Constant)?;
-    let string = token.unwrap().data.unwrap();
-    let int = string.parse::<i32>();
-    if int.is_err() {
-        return Err(Error::new(ErrorKind::InvalidInput, "Bad integer"));
+    check_token(&token, token_type)?;
+    Ok(token.unwrap())
+}
+
+fn peek(tokens: &VecDeque<TokenValue>) -> Result<&TokenValue, ParseError> {
+    tokens.front().ok_or(ParseError::UnexpectedEnd)
+}
+
+fn identifier_name(tokens: &mut VecDeque<TokenValue>) -> Result<String, ParseError> {
+    let next = peek(tokens)?;
+    if next.token.is_keyword() {
+        return Err(ParseError::KeywordAsIdentifier {
+            keyword: next.token,
+            span: next.span.clone(),
+        });
     }
-    Ok(Box::new(Node::Int(int.unwrap())))
+    let token = expect(tokens, Token::Identifier)?;
+    Ok(token.data.unwrap())
+}
+
+fn identifier(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    Ok(Box::new(Node::Str(identifier_name(tokens)?)))
 }
 
-fn unop(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Operations> {
-    let token = tokens
-        .pop_front()
-        .ok_or(Error::new(ErrorKind::InvalidInput, "Missing unop operator"))?;
+fn int(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let token = tokens.pop_front().ok_or(ParseError::UnexpectedEnd)?;
+    match token.literal {
+        Some(Literal::Integer { value, kind }) => Ok(Box::new(Node::Int(Constant::new(
+            value as i64,
+            kind.into(),
+        )))),
+        Some(Literal::Character { value, encoding }) => {
+            let ty = match encoding {
+                Encoding::Plain | Encoding::Wide => Type::Int,
+                Encoding::Utf8 => Type::UnsignedChar,
+                Encoding::Utf16 => Type::UnsignedShort,
+                Encoding::Utf32 => Type::UnsignedInt,
+            };
+            Ok(Box::new(Node::Int(Constant::new(value, ty))))
+        }
+        Some(Literal::Float { kind, .. }) => Err(ParseError::UnsupportedType {
+            type_name: kind.to_string(),
+            span: token.span,
+        }),
+        Some(Literal::String { bytes, encoding }) => Err(ParseError::UnsupportedType {
+            type_name: format!(
+                "{}[{}]",
+                encoding.element_type(),
+                bytes.len() / encoding.unit_size() + 1
+            ),
+            span: token.span,
+        }),
+        None => Err(ParseError::UnexpectedToken {
+            expected: Token::Constant,
+            found: token.token,
+            span: token.span,
+        }),
+    }
+}
+
+fn unop(tokens: &mut VecDeque<TokenValue>) -> Result<(Operations, Span), ParseError> {
+    let token = tokens.pop_front().ok_or(ParseError::UnexpectedEnd)?;
     match token.token {
-        Token::Hyphen => Ok(Operations::Negate),
-        Token::Tilde => Ok(Operations::Complement),
-        _ => Err(Error::new(ErrorKind::InvalidInput, "Bad unop")),
+        Token::Hyphen => Ok((Operations::Negate, token.span)),
+        Token::Tilde => Ok((Operations::Complement, token.span)),
+        Token::Exclamation => Ok((Operations::Not, token.span)),
+        _ => Err(ParseError::ExpectedUnaryOperator {
+            found: token.token,
+            span: token.span,
+        }),
+    }
+}
+
+const ASSIGNMENT_PRECEDENCE: u8 = 1;
+
+/// Binary operator for a token and its precedence; higher binds tighter.
+fn binary_operator(token: Token) -> Option<(Operations, u8)> {
+    match token {
+        Token::Asterisk => Some((Operations::Multiply, 50)),
+        Token::Slash => Some((Operations::Divide, 50)),
+        Token::Percent => Some((Operations::Remainder, 50)),
+        Token::Plus => Some((Operations::Add, 45)),
+        Token::Hyphen => Some((Operations::Subtract, 45)),
+        Token::TwoLessThans => Some((Operations::LeftShift, 40)),
+        Token::TwoGreaterThans => Some((Operations::RightShift, 40)),
+        Token::LessThan => Some((Operations::LessThan, 35)),
+        Token::LessThanEqual => Some((Operations::LessOrEqual, 35)),
+        Token::GreaterThan => Some((Operations::GreaterThan, 35)),
+        Token::GreaterThanEqual => Some((Operations::GreaterOrEqual, 35)),
+        Token::TwoEquals => Some((Operations::Equal, 30)),
+        Token::ExclamationEqual => Some((Operations::NotEqual, 30)),
+        Token::Ampersand => Some((Operations::BitwiseAnd, 25)),
+        Token::Caret => Some((Operations::BitwiseXor, 20)),
+        Token::Pipe => Some((Operations::BitwiseOr, 15)),
+        Token::TwoAmpersands => Some((Operations::And, 10)),
+        Token::TwoPipes => Some((Operations::Or, 5)),
+        Token::QuestionMark => Some((Operations::Conditional, 3)),
+        Token::Equal => Some((Operations::Assign, ASSIGNMENT_PRECEDENCE)),
+        Token::PlusEqual => Some((Operations::AddAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::HyphenEqual => Some((Operations::SubtractAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::AsteriskEqual => Some((Operations::MultiplyAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::SlashEqual => Some((Operations::DivideAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::PercentEqual => Some((Operations::RemainderAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::AmpersandEqual => Some((Operations::BitwiseAndAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::PipeEqual => Some((Operations::BitwiseOrAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::CaretEqual => Some((Operations::BitwiseXorAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::TwoLessThansEqual => Some((Operations::LeftShiftAssign, ASSIGNMENT_PRECEDENCE)),
+        Token::TwoGreaterThansEqual => Some((Operations::RightShiftAssign, ASSIGNMENT_PRECEDENCE)),
+        _ => None,
+    }
+}
+
+/// Parses an expression by precedence climbing: operators binding at
+/// least as tightly as `min_precedence` are consumed here, and the right
+/// operand only takes tighter ones, making them left associative. The
+/// assignment operators and `?:` are right associative instead.
+fn exp(tokens: &mut VecDeque<TokenValue>, min_precedence: u8) -> Result<Box<Node>, ParseError> {
+    let mut lhs = factor(tokens)?;
+    let mut levels = 0;
+    while let Some((op, precedence)) = binary_operator(peek(tokens)?.token) {
+        if precedence < min_precedence {
+            break;
+        }
+        // Each operator nests the expression parsed so far one level
+        // deeper, even though the loop does not recurse.
+        enter_nesting(&peek(tokens)?.span)?;
+        levels += 1;
+        tokens.pop_front();
+        if let Operations::Conditional = op {
+            // The middle operand is parsed as if parenthesized, and the
+            // last one makes `?:` right associative.
+            let then = exp(tokens, 0)?;
+            expect(tokens, Token::Colon)?;
+            let otherwise = exp(tokens, precedence)?;
+            let span = lhs.span().unwrap().to(otherwise.span().unwrap());
+            lhs = Box::new(Node::Ternary {
+                op,
+                condition: lhs,
+                then,
+                otherwise: Some(otherwise),
+                span,
+            });
+            continue;
+        }
+        let rhs = if precedence == ASSIGNMENT_PRECEDENCE {
+            exp(tokens, precedence)?
+        } else {
+            exp(tokens, precedence + 1)?
+        };
+        let span = lhs.span().unwrap().to(rhs.span().unwrap());
+        lhs = Box::new(Node::Binary { op, lhs, rhs, span });
     }
+    leave_nesting(levels);
+    Ok(lhs)
+}
+
+fn factor(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    nested(tokens, parse_factor)
 }
 
-fn exp(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
-    let next = tokens
-        .front()
-        .ok_or(Error::new(ErrorKind::InvalidInput, "Missing exp token"))?;
+fn parse_factor(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let next = peek(tokens)?;
     match next.token {
-        Token::Constant => Ok(Box::new(Node::Unary {
-            op: Operations::Constant,
-            node: int(tokens)?,
-        })),
-        Token::Hyphen | Token::Tilde => {
-            let op = unop(tokens)?;
-            let exp = exp(tokens)?;
-            Ok(Box::new(Node::Unary { op: op, node: exp }))
+        Token::Constant | Token::FloatConstant | Token::CharLiteral | Token::StringLiteral => {
+            let span = next.span.clone();
+            Ok(Box::new(Node::Unary {
+                op: Operations::Constant,
+                node: int(tokens)?,
+                span,
+            }))
+        }
+        Token::Hyphen | Token::Tilde | Token::Exclamation => {
+            let (op, span) = unop(tokens)?;
+            let exp = factor(tokens)?;
+            let span = span.to(exp.span().unwrap_or(&span));
+            Ok(Box::new(Node::Unary {
+                op,
+                node: exp,
+                span,
+            }))
+        }
+        Token::Identifier
+            if tokens
+                .get(1)
+                .is_some_and(|next| next.token == Token::OpenParenthesis) =>
+        {
+            let start = next.span.clone();
+            let name = identifier_name(tokens)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let mut args = VecDeque::new();
+            if peek(tokens)?.token != Token::CloseParenthesis {
+                loop {
+                    args.push_back(exp(tokens, 0)?);
+                    if peek(tokens)?.token != Token::Comma {
+                        break;
+                    }
+                    expect(tokens, Token::Comma)?;
+                }
+            }
+            let end = expect(tokens, Token::CloseParenthesis)?;
+            Ok(Box::new(Node::Call {
+                name,
+                args,
+                variadic: false,
+                span: start.to(&end.span),
+            }))
+        }
+        Token::Identifier => {
+            let span = next.span.clone();
+            Ok(Box::new(Node::Unary {
+                op: Operations::Var,
+                node: identifier(tokens)?,
+                span,
+            }))
+        }
+        Token::OpenParenthesis
+            if tokens
+                .get(1)
+                .is_some_and(|next| is_type_specifier(next.token)) =>
+        {
+            let start = expect(tokens, Token::OpenParenthesis)?;
+            let ty = type_name(tokens)?;
+            expect(tokens, Token::CloseParenthesis)?;
+            let exp = factor(tokens)?;
+            let span = start.span.to(exp.span().unwrap_or(&start.span));
+            Ok(Box::new(Node::Unary {
+                op: Operations::Cast(ty),
+                node: exp,
+                span,
+            }))
         }
         Token::OpenParenthesis => {
-            check_token(&tokens.pop_front(), Token::OpenParenthesis)?;
-            let res = exp(tokens);
-            check_token(&tokens.pop_front(), Token::CloseParenthesis)?;
-            res
+            expect(tokens, Token::OpenParenthesis)?;
+            let res = exp(tokens, 0)?;
+            expect(tokens, Token::CloseParenthesis)?;
+            Ok(res)
         }
-        _ => Err(Error::new(ErrorKind::InvalidInput, "Unknown expression")),
+        _ => Err(ParseError::ExpectedExpression {
+            found: next.token,
+            span: next.span.clone(),
+        }),
     }
 }
 
-fn statement(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
-    check_token(&tokens.pop_front(), Token::ReturnKeyword)?;
-    let e = exp(tokens)?;
-    check_token(&tokens.pop_front(), Token::Semicolon)?;
-    Ok(Box::new(Node::Unary {
-        op: Operations::Return,
-        node: e,
-    }))
+/// An expression, unless the next token is `end`, which is left in place.
+fn optional_exp(
+    tokens: &mut VecDeque<TokenValue>,
+    end: Token,
+) -> Result<Option<Box<Node>>, ParseError> {
+    if peek(tokens)?.token == end {
+        return Ok(None);
+    }
+    Ok(Some(exp(tokens, 0)?))
+}
+
+fn statement(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    nested(tokens, parse_statement)
+}
+
+fn parse_statement(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let next = peek(tokens)?;
+    match next.token {
+        Token::ReturnKeyword => {
+            let start = expect(tokens, Token::ReturnKeyword)?;
+            let e = exp(tokens, 0)?;
+            let end = expect(tokens, Token::Semicolon)?;
+            Ok(Box::new(Node::Unary {
+                op: Operations::Return,
+                node: e,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::IfKeyword => {
+            let start = expect(tokens, Token::IfKeyword)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let condition = exp(tokens, 0)?;
+            let end = expect(tokens, Token::CloseParenthesis)?;
+            let then = statement(tokens)?;
+            // An `else` belongs to the innermost `if` that can take it.
+            let otherwise = if peek(tokens)?.token == Token::ElseKeyword {
+                expect(tokens, Token::ElseKeyword)?;
+                Some(statement(tokens)?)
+            } else {
+                None
+            };
+            Ok(Box::new(Node::Ternary {
+                op: Operations::If,
+                condition,
+                then,
+                otherwise,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::WhileKeyword => {
+            let start = expect(tokens, Token::WhileKeyword)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let condition = exp(tokens, 0)?;
+            let end = expect(tokens, Token::CloseParenthesis)?;
+            Ok(Box::new(Node::Loop {
+                op: Operations::While,
+                init: None,
+                condition: Some(condition),
+                post: None,
+                body: statement(tokens)?,
+                label: String::new(),
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::DoKeyword => {
+            let start = expect(tokens, Token::DoKeyword)?;
+            let body = statement(tokens)?;
+            expect(tokens, Token::WhileKeyword)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let condition = exp(tokens, 0)?;
+            expect(tokens, Token::CloseParenthesis)?;
+            let end = expect(tokens, Token::Semicolon)?;
+            Ok(Box::new(Node::Loop {
+                op: Operations::DoWhile,
+                init: None,
+                condition: Some(condition),
+                post: None,
+                body,
+                label: String::new(),
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::ForKeyword => {
+            let start = expect(tokens, Token::ForKeyword)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let init = match peek(tokens)?.token {
+                token if starts_declaration(token) => Some(declaration(tokens)?),
+                _ => {
+                    let init = optional_exp(tokens, Token::Semicolon)?;
+                    expect(tokens, Token::Semicolon)?;
+                    init
+                }
+            };
+            let condition = optional_exp(tokens, Token::Semicolon)?;
+            expect(tokens, Token::Semicolon)?;
+            let post = optional_exp(tokens, Token::CloseParenthesis)?;
+            let end = expect(tokens, Token::CloseParenthesis)?;
+            Ok(Box::new(Node::Loop {
+                op: Operations::For,
+                init,
+                condition,
+                post,
+                body: statement(tokens)?,
+                label: String::new(),
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::SwitchKeyword => {
+            let start = expect(tokens, Token::SwitchKeyword)?;
+            expect(tokens, Token::OpenParenthesis)?;
+            let condition = exp(tokens, 0)?;
+            let end = expect(tokens, Token::CloseParenthesis)?;
+            Ok(Box::new(Node::Switch {
+                condition,
+                body: statement(tokens)?,
+                label: String::new(),
+                cases: Vec::new(),
+                default: None,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::CaseKeyword | Token::DefaultKeyword => {
+            let start = tokens.pop_front().unwrap();
+            let (op, value) = match start.token {
+                Token::CaseKeyword => (Operations::Case, Some(exp(tokens, 0)?)),
+                _ => (Operations::Default, None),
+            };
+            let end = expect(tokens, Token::Colon)?;
+            Ok(Box::new(Node::Labeled {
+                op,
+                value,
+                label: String::new(),
+                node: statement(tokens)?,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::Identifier if tokens.get(1).is_some_and(|next| next.token == Token::Colon) => {
+            let start = expect(tokens, Token::Identifier)?;
+            let end = expect(tokens, Token::Colon)?;
+            Ok(Box::new(Node::Labeled {
+                op: Operations::Label,
+                value: None,
+                label: start.data.unwrap(),
+                node: statement(tokens)?,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::GotoKeyword => {
+            let start = expect(tokens, Token::GotoKeyword)?;
+            let target = identifier(tokens)?;
+            let end = expect(tokens, Token::Semicolon)?;
+            Ok(Box::new(Node::Unary {
+                op: Operations::Goto,
+                node: target,
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::BreakKeyword | Token::ContinueKeyword => {
+            let start = tokens.pop_front().unwrap();
+            let end = expect(tokens, Token::Semicolon)?;
+            let op = match start.token {
+                Token::BreakKeyword => Operations::Break,
+                _ => Operations::Continue,
+            };
+            Ok(Box::new(Node::Unary {
+                op,
+                node: Box::new(Node::Str(String::new())),
+                span: start.span.to(&end.span),
+            }))
+        }
+        Token::OpenBrace => {
+            expect(tokens, Token::OpenBrace)?;
+            let items = block(tokens)?;
+            expect(tokens, Token::CloseBrace)?;
+            Ok(items)
+        }
+        Token::Semicolon => {
+            // The null statement does nothing, like an empty block.
+            expect(tokens, Token::Semicolon)?;
+            Ok(Box::new(Node::Sequence(VecDeque::new())))
+        }
+        _ => {
+            let e = exp(tokens, 0)?;
+            let end = expect(tokens, Token::Semicolon)?;
+            let span = e.span().unwrap().to(&end.span);
+            Ok(Box::new(Node::Unary {
+                op: Operations::Expression,
+                node: e,
+                span,
+            }))
+        }
+    }
+}
+
+fn is_type_specifier(token: Token) -> bool {
+    matches!(
+        token,
+        Token::CharKeyword
+            | Token::ShortKeyword
+            | Token::IntKeyword
+            | Token::LongKeyword
+            | Token::SignedKeyword
+            | Token::UnsignedKeyword
+    )
 }
 
-fn function(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
-    check_token(&tokens.pop_front(), Token::IntKeyword)?;
-    let i = identifier(tokens)?;
-    check_token(&tokens.pop_front(), Token::OpenParenthesis)?;
-    check_token(&tokens.pop_front(), Token::VoidKeyword)?;
-    check_token(&tokens.pop_front(), Token::CloseParenthesis)?;
-    check_token(&tokens.pop_front(), Token::OpenBrace)?;
-    let s = statement(tokens)?;
-    check_token(&tokens.pop_front(), Token::CloseBrace)?;
-    Ok(Box::new(Node::Binary {
-        op: Operations::Function,
-        lhs: i,
-        rhs: s,
+fn starts_declaration(token: Token) -> bool {
+    is_type_specifier(token) || matches!(token, Token::StaticKeyword | Token::ExternKeyword)
+}
+
+/// Integer type named by a list of type specifiers, in any order, such as
+/// `unsigned long int`. `span` covers the specifiers.
+fn integer_type(specifiers: &[Token], span: Span) -> Result<Type, ParseError> {
+    let count = |token| specifiers.iter().filter(|x| **x == token).count();
+    let (char, short, int, long) = (
+        count(Token::CharKeyword),
+        count(Token::ShortKeyword),
+        count(Token::IntKeyword),
+        count(Token::LongKeyword),
+    );
+    let (signed, unsigned) = (count(Token::SignedKeyword), count(Token::UnsignedKeyword));
+    if char > 1
+        || short > 1
+        || int > 1
+        || long > 2
+        || signed + unsigned > 1
+        || (char == 1 && short + int + long > 0)
+        || (short == 1 && long > 0)
+    {
+        return Err(ParseError::InvalidTypeSpecifiers { span });
+    }
+    let unsigned = unsigned == 1;
+    Ok(match (char, short, long) {
+        (1, _, _) if signed == 1 => Type::SignedChar,
+        (1, _, _) if unsigned => Type::UnsignedChar,
+        (1, _, _) => Type::Char,
+        (_, 1, _) if unsigned => Type::UnsignedShort,
+        (_, 1, _) => Type::Short,
+        (_, _, 2) if unsigned => Type::UnsignedLongLong,
+        (_, _, 2) => Type::LongLong,
+        (_, _, 1) if unsigned => Type::UnsignedLong,
+        (_, _, 1) => Type::Long,
+        _ if unsigned => Type::UnsignedInt,
+        _ => Type::Int,
+    })
+}
+
+/// Specifiers at the start of a declaration, in any order: type specifiers
+/// naming an integer type and at most one storage class. Returns the
+/// type, the storage class and where the specifiers start.
+fn specifiers(
+    tokens: &mut VecDeque<TokenValue>,
+) -> Result<(Type, Option<Operations>, Span), ParseError> {
+    let start = peek(tokens)?.span.clone();
+    let mut end = start.clone();
+    let mut storage = None;
+    let mut types = Vec::new();
+    loop {
+        let next = peek(tokens)?;
+        let class = match next.token {
+            token if is_type_specifier(token) => {
+                types.push(token);
+                end = tokens.pop_front().unwrap().span;
+                continue;
+            }
+            Token::StaticKeyword => Operations::Static,
+            Token::ExternKeyword => Operations::Extern,
+            _ => break,
+        };
+        if storage.is_some() {
+            return Err(ParseError::MultipleStorageClasses {
+                span: next.span.clone(),
+            });
+        }
+        end = tokens.pop_front().unwrap().span;
+        storage = Some(class);
+    }
+    if types.is_empty() {
+        expect(tokens, Token::IntKeyword)?;
+    }
+    Ok((integer_type(&types, start.to(&end))?, storage, start))
+}
+
+/// Type specifiers without a storage class, as in a cast or a parameter.
+fn type_name(tokens: &mut VecDeque<TokenValue>) -> Result<Type, ParseError> {
+    let start = peek(tokens)?.span.clone();
+    let mut end = start.clone();
+    let mut types = Vec::new();
+    while is_type_specifier(peek(tokens)?.token) {
+        let token = tokens.pop_front().unwrap();
+        types.push(token.token);
+        end = token.span;
+    }
+    if types.is_empty() {
+        expect(tokens, Token::IntKeyword)?;
+    }
+    integer_type(&types, start.to(&end))
+}
+
+/// `int name;`, `int name = exp;`, or a function declaration or
+/// definition, with any integer type and an optional storage class.
+fn declaration(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let (ty, storage, start) = specifiers(tokens)?;
+    let name_span = peek(tokens)?.span.clone();
+    let name = identifier_name(tokens)?;
+    let declaration = if peek(tokens)?.token == Token::OpenParenthesis {
+        function(tokens, &start, ty, name)?
+    } else {
+        let name = Box::new(Node::Unary {
+            op: Operations::Var,
+            node: Box::new(Node::Str(name)),
+            span: name_span,
+        });
+        if peek(tokens)?.token == Token::Equal {
+            expect(tokens, Token::Equal)?;
+            let init = exp(tokens, 0)?;
+            let end = expect(tokens, Token::Semicolon)?;
+            Box::new(Node::Binary {
+                op: Operations::Declaration(ty),
+                lhs: name,
+                rhs: init,
+                span: start.to(&end.span),
+            })
+        } else {
+            let end = expect(tokens, Token::Semicolon)?;
+            Box::new(Node::Unary {
+                op: Operations::Declaration(ty),
+                node: name,
+                span: start.to(&end.span),
+            })
+        }
+    };
+    Ok(match storage {
+        Some(op) => {
+            let span = declaration.span().unwrap().clone();
+            Box::new(Node::Unary {
+                op,
+                node: declaration,
+                span,
+            })
+        }
+        None => declaration,
+    })
+}
+
+/// Block items up to, but not including, the closing brace.
+fn block(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let mut items = VecDeque::new();
+    loop {
+        let next = peek(tokens)?;
+        match next.token {
+            Token::CloseBrace | Token::EndOfFile => break,
+            token if starts_declaration(token) => items.push_back(declaration(tokens)?),
+            _ => items.push_back(statement(tokens)?),
+        }
+    }
+    Ok(Box::new(Node::Sequence(items)))
+}
+
+/// Parameter list of a function, including the parentheses, and whether
+/// it ends with `...`. An empty list means no parameters, as for `(void)`.
+fn params(tokens: &mut VecDeque<TokenValue>) -> Result<(VecDeque<Box<Node>>, bool), ParseError> {
+    expect(tokens, Token::OpenParenthesis)?;
+    let mut params = VecDeque::new();
+    let mut variadic = false;
+    match peek(tokens)?.token {
+        Token::VoidKeyword => {
+            expect(tokens, Token::VoidKeyword)?;
+        }
+        Token::CloseParenthesis => {}
+        _ => loop {
+            let start = peek(tokens)?.span.clone();
+            let ty = type_name(tokens)?;
+            let next = peek(tokens)?;
+            let span = next.span.clone();
+            // Declarations may leave the name out, as in `int f(int);`. The
+            // name is then empty, and `span` is where it would be.
+            let (name, declaration_span) = match next.token {
+                Token::Comma | Token::CloseParenthesis => (String::new(), start),
+                _ => (identifier_name(tokens)?, start.to(&span)),
+            };
+            let var = Box::new(Node::Unary {
+                op: Operations::Var,
+                node: Box::new(Node::Str(name)),
+                span,
+            });
+            params.push_back(Box::new(Node::Unary {
+                op: Operations::Declaration(ty),
+                node: var,
+                span: declaration_span,
+            }));
+            if peek(tokens)?.token != Token::Comma {
+                break;
+            }
+            expect(tokens, Token::Comma)?;
+            if peek(tokens)?.token == Token::Ellipsis {
+                expect(tokens, Token::Ellipsis)?;
+                variadic = true;
+                break;
+            }
+        },
+    }
+    expect(tokens, Token::CloseParenthesis)?;
+    Ok((params, variadic))
+}
+
+/// Where the name of a parameter declared without one would be.
+fn unnamed_param(param: &Node) -> Option<&Span> {
+    match param {
+        Node::Unary {
+            op: Operations::Declaration(_),
+            node,
+            ..
+        } => match &**node {
+            Node::Unary {
+                op: Operations::Var,
+                node,
+                span,
+            } if matches!(&**node, Node::Str(name) if name.is_empty()) => Some(span),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The rest of a function declaration or definition, after its return
+/// type and name.
+fn function(
+    tokens: &mut VecDeque<TokenValue>,
+    start: &Span,
+    return_type: Type,
+    name: String,
+) -> Result<Box<Node>, ParseError> {
+    let (params, variadic) = params(tokens)?;
+    if peek(tokens)?.token == Token::Semicolon {
+        let end = expect(tokens, Token::Semicolon)?;
+        return Ok(Box::new(Node::Function {
+            name,
+            return_type,
+            params,
+            variadic,
+            body: None,
+            span: start.to(&end.span),
+        }));
+    }
+    if let Some(span) = params.iter().find_map(|param| unnamed_param(param)) {
+        return Err(ParseError::MissingParameterName { span: span.clone() });
+    }
+    expect(tokens, Token::OpenBrace)?;
+    let body = block(tokens)?;
+    let end = expect(tokens, Token::CloseBrace)?;
+    Ok(Box::new(Node::Function {
+        name,
+        return_type,
+        params,
+        variadic,
+        body: Some(body),
+        span: start.to(&end.span),
     }))
 }
 
-fn program(tokens: &mut VecDeque<TokenValue>) -> std::io::Result<Box<Node>> {
-    let f = function(tokens)?;
-    if !tokens.is_empty() {
-        return Err(Error::new(
-            ErrorKind::InvalidInput,
-            "invalid top level identifer",
-        ));
+fn program(tokens: &mut VecDeque<TokenValue>) -> Result<Box<Node>, ParseError> {
+    let mut declarations = VecDeque::new();
+    loop {
+        let next = peek(tokens)?;
+        match next.token {
+            Token::EndOfFile => break,
+            token if starts_declaration(token) => declarations.push_back(declaration(tokens)?),
+            _ => {
+                return Err(ParseError::TrailingInput {
+                    found: next.token,
+                    span: next.span.clone(),
+                })
+            }
+        }
     }
+    let span = match (declarations.front(), declarations.back()) {
+        (Some(first), Some(last)) => first.span().unwrap().to(last.span().unwrap()),
+        _ => peek(tokens)?.span.clone(),
+    };
     Ok(Box::new(Node::Unary {
         op: Operations::Program,
-        node: f,
+        node: Box::new(Node::Sequence(declarations)),
+        span,
     }))
 }
 
-pub fn parse(tokens: &mut VecDeque<TokenValue>, debug_mode: bool) -> std::io::Result<Box<Node>> {
+pub fn parse(tokens: &mut VecDeque<TokenValue>, debug_mode: bool) -> Result<Box<Node>, ParseError> {
+    NESTING_DEPTH.set(0);
     let p = program(tokens)?;
     if debug_mode {
         println!("{:?}", p)
     };
     Ok(p)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::lex;
+
+    fn parse_source(source: &str) -> Result<Box<Node>, ParseError> {
+        parse(&mut lex(source, "test.c", false).unwrap(), false)
+    }
+
+    /// Names and types of the parameters of each function in `source`.
+    fn param_names(source: &str) -> Vec<Vec<(String, Type)>> {
+        let Node::Unary { node, .. } = *parse_source(source).unwrap() else {
+            panic!("expected a program");
+        };
+        let Node::Sequence(items) = *node else {
+            panic!("expected a sequence of declarations");
+        };
+        items
+            .iter()
+            .map(|item| match &**item {
+                Node::Function { params, .. } => params
+                    .iter()
+                    .map(|param| match &**param {
+                        Node::Unary {
+                            op: Operations::Declaration(ty),
+                            node,
+                            ..
+                        } => match &**node {
+                            Node::Unary { node, .. } => match &**node {
+                                Node::Str(name) => (name.clone(), *ty),
+                                node => panic!("unexpected name {node:?}"),
+                            },
+                            node => panic!("unexpected parameter {node:?}"),
+                        },
+                        node => panic!("unexpected parameter {node:?}"),
+                    })
+                    .collect(),
+                node => panic!("unexpected item {node:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn declarations_may_omit_parameter_names() {
+        assert_eq!(
+            param_names("int putchar(int);\nint f(long, int b, unsigned);\nint g(void);"),
+            [
+                vec![(String::new(), Type::Int)],
+                vec![
+                    (String::new(), Type::Long),
+                    ("b".to_string(), Type::Int),
+                    (String::new(), Type::UnsignedInt),
+                ],
+                vec![],
+            ]
+        );
+    }
+
+    #[test]
+    fn definitions_need_parameter_names() {
+        assert_eq!(
+            param_names("int f(int a, long b) { return a; }"),
+            [vec![
+                ("a".to_string(), Type::Int),
+                ("b".to_string(), Type::Long)
+            ]]
+        );
+        let error = parse_source("int f(int a, int) { return a; }").unwrap_err();
+        let ParseError::MissingParameterName { span } = error else {
+            panic!("unexpected error {error:?}");
+        };
+        assert_eq!((span.line, span.column), (1, 17));
+    }
+
+    /// The expression `text` as an s-expression, e.g. `(Add 1 (Multiply 2 3))`.
+    fn sexp(text: &str) -> String {
+        fn show(node: &Node) -> String {
+            match node {
+                Node::Int(constant) => constant.value.to_string(),
+                Node::Str(name) => name.clone(),
+                Node::Unary {
+                    op: Operations::Var | Operations::Constant,
+                    node,
+                    ..
+                } => show(node),
+                Node::Unary { op, node, .. } => format!("({op:?} {})", show(node)),
+                Node::Binary { op, lhs, rhs, .. } => {
+                    format!("({op:?} {} {})", show(lhs), show(rhs))
+                }
+                Node::Ternary {
+                    condition,
+                    then,
+                    otherwise: Some(otherwise),
+                    ..
+                } => format!("(? {} {} {})", show(condition), show(then), show(otherwise)),
+                node => panic!("unexpected node {node:?}"),
+            }
+        }
+        let source = format!("int main(void) {{ return {text}; }}");
+        let Node::Unary { node, .. } = *parse_source(&source).unwrap() else {
+            panic!("expected a program");
+        };
+        let Node::Sequence(items) = *node else {
+            panic!("expected a sequence of declarations");
+        };
+        let Node::Function {
+            body: Some(body), ..
+        } = &*items[0]
+        else {
+            panic!("expected a function definition");
+        };
+        let Node::Sequence(body) = &**body else {
+            panic!("expected a block");
+        };
+        let Node::Unary {
+            op: Operations::Return,
+            node,
+            ..
+        } = &*body[0]
+        else {
+            panic!("expected a return statement");
+        };
+        show(node)
+    }
+
+    #[test]
+    fn binary_operator_precedence() {
+        assert_eq!(sexp("1 + 2 * 3"), "(Add 1 (Multiply 2 3))");
+        assert_eq!(sexp("1 * 2 + 3"), "(Add (Multiply 1 2) 3)");
+        assert_eq!(sexp("1 << 2 + 3"), "(LeftShift 1 (Add 2 3))");
+        assert_eq!(
+            sexp("1 < 2 == 3 > 4"),
+            "(Equal (LessThan 1 2) (GreaterThan 3 4))"
+        );
+        assert_eq!(
+            sexp("1 | 2 ^ 3 & 4"),
+            "(BitwiseOr 1 (BitwiseXor 2 (BitwiseAnd 3 4)))"
+        );
+        assert_eq!(sexp("1 || 2 && 3 | 4"), "(Or 1 (And 2 (BitwiseOr 3 4)))");
+        assert_eq!(sexp("-1 * ~2"), "(Multiply (Negate 1) (Complement 2))");
+        assert_eq!(sexp("(1 + 2) * 3"), "(Multiply (Add 1 2) 3)");
+    }
+
+    #[test]
+    fn binary_operators_associate_left() {
+        assert_eq!(sexp("1 - 2 - 3"), "(Subtract (Subtract 1 2) 3)");
+        assert_eq!(sexp("8 / 4 % 3"), "(Remainder (Divide 8 4) 3)");
+        assert_eq!(sexp("1 << 2 >> 3"), "(RightShift (LeftShift 1 2) 3)");
+        assert_eq!(sexp("1 && 2 && 3"), "(And (And 1 2) 3)");
+    }
+
+    #[test]
+    fn assignment_and_conditional_associate_right() {
+        assert_eq!(sexp("a = b = 1"), "(Assign a (Assign b 1))");
+        assert_eq!(sexp("a += b -= 1"), "(AddAssign a (SubtractAssign b 1))");
+        assert_eq!(sexp("a ? 1 : b ? 2 : 3"), "(? a 1 (? b 2 3))");
+        assert_eq!(sexp("a = 1 || 2 ? 3 : 4"), "(Assign a (? (Or 1 2) 3 4))");
+    }
+}
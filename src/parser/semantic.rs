@@ -0,0 +1,1766 @@
+use crate::lexer::span::Span;
+use crate::parser::error::SemanticError;
+use crate::parser::intermediate::{get_next_label, get_unique_name, is_compound_assignment};
+use crate::parser::parser::{Node, Operations};
+use crate::parser::types::{Constant, Symbols, Type};
+use std::collections::{HashMap, VecDeque};
+
+/// Types of the parameters of a function, whether it takes more
+/// arguments after them (`...`), and its return type.
+#[derive(Clone, PartialEq)]
+struct Signature {
+    params: Vec<Type>,
+    variadic: bool,
+    returns: Type,
+}
+
+/// A declared identifier: its unique name, where it was declared, the
+/// signature if it is a function, and whether it has linkage, i.e.
+/// refers to a function or file scope variable.
+struct Symbol {
+    name: String,
+    span: Span,
+    signature: Option<Signature>,
+    linkage: bool,
+}
+
+/// A function declared somewhere in the program. All declarations of a
+/// name refer to the same function, in whichever scope they appear.
+struct Function {
+    signature: Signature,
+    span: Span,
+    defined: bool,
+    global: bool,
+}
+
+/// What a variable with static storage duration starts out holding.
+#[derive(Clone, Copy, PartialEq)]
+enum InitialValue {
+    /// Already converted to the type of the variable.
+    Constant(Constant),
+    /// Declared at file scope without an initializer: zero, unless
+    /// another declaration gives it a value.
+    Tentative,
+    /// Only declared `extern`, so defined in another file.
+    External,
+}
+
+/// A variable with static storage duration: a file scope variable, or a
+/// `static` local under its unique name.
+struct StaticVariable {
+    span: Span,
+    ty: Type,
+    init: InitialValue,
+    global: bool,
+}
+
+/// Identifiers visible at the current point, innermost scope last, and
+/// every function and static variable declared so far. `order` lists the
+/// static variables in the order of their first declaration, and `types`
+/// has the type of every variable and the return type of every function.
+struct Scopes {
+    stack: Vec<HashMap<String, Symbol>>,
+    functions: HashMap<String, Function>,
+    variables: HashMap<String, StaticVariable>,
+    order: Vec<String>,
+    types: Symbols,
+}
+
+impl Scopes {
+    /// Declares a local variable without linkage, under a new unique name.
+    fn declare(&mut self, name: &str, ty: Type, span: &Span) -> Result<String, SemanticError> {
+        let scope = self.stack.last_mut().unwrap();
+        if let Some(previous) = scope.get(name) {
+            return Err(SemanticError::DuplicateDeclaration {
+                name: name.to_string(),
+                span: span.clone(),
+                previous: previous.span.clone(),
+            });
+        }
+        let unique = get_unique_name(name);
+        self.types.insert(unique.clone(), ty);
+        scope.insert(
+            name.to_string(),
+            Symbol {
+                name: unique.clone(),
+                span: span.clone(),
+                signature: None,
+                linkage: false,
+            },
+        );
+        Ok(unique)
+    }
+
+    /// Declares a `static` local, which lives as long as the program.
+    fn declare_static(
+        &mut self,
+        name: &str,
+        ty: Type,
+        value: Constant,
+        span: &Span,
+    ) -> Result<(), SemanticError> {
+        let unique = self.declare(name, ty, span)?;
+        self.order.push(unique.clone());
+        self.variables.insert(
+            unique,
+            StaticVariable {
+                span: span.clone(),
+                ty,
+                init: InitialValue::Constant(value),
+                global: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Declares a variable with linkage: one at file scope, or an
+    /// `extern` local. It keeps its name so that it can be linked to, and
+    /// its linkage is that of the first declaration unless `storage` is
+    /// `static`, which makes it internal.
+    fn declare_linked(
+        &mut self,
+        name: &str,
+        ty: Type,
+        storage: Option<&Operations>,
+        init: InitialValue,
+        span: &Span,
+    ) -> Result<(), SemanticError> {
+        let scope = self.stack.last_mut().unwrap();
+        if let Some(previous) = scope.get(name) {
+            if !previous.linkage {
+                return Err(SemanticError::DuplicateDeclaration {
+                    name: name.to_string(),
+                    span: span.clone(),
+                    previous: previous.span.clone(),
+                });
+            }
+        }
+        if let Some(previous) = self.functions.get(name) {
+            return Err(SemanticError::ConflictingKind {
+                name: name.to_string(),
+                span: span.clone(),
+                previous: previous.span.clone(),
+            });
+        }
+        let is_static = matches!(storage, Some(Operations::Static));
+        match self.variables.get_mut(name) {
+            Some(previous) => {
+                if previous.ty != ty {
+                    return Err(SemanticError::ConflictingDeclaration {
+                        name: name.to_string(),
+                        span: span.clone(),
+                        previous: previous.span.clone(),
+                    });
+                }
+                let extern_follows = matches!(storage, Some(Operations::Extern));
+                if !extern_follows && previous.global == is_static {
+                    return Err(SemanticError::ConflictingLinkage {
+                        name: name.to_string(),
+                        is_static,
+                        span: span.clone(),
+                        previous: previous.span.clone(),
+                    });
+                }
+                match (previous.init, init) {
+                    (InitialValue::Constant(_), InitialValue::Constant(_)) => {
+                        return Err(SemanticError::DuplicateDefinition {
+                            name: name.to_string(),
+                            span: span.clone(),
+                            previous: previous.span.clone(),
+                        })
+                    }
+                    (_, InitialValue::Constant(_))
+                    | (InitialValue::External, InitialValue::Tentative) => {
+                        previous.init = init;
+                        previous.span = span.clone();
+                    }
+                    _ => {}
+                }
+            }
+            None => {
+                self.order.push(name.to_string());
+                self.variables.insert(
+                    name.to_string(),
+                    StaticVariable {
+                        span: span.clone(),
+                        ty,
+                        init,
+                        global: !is_static,
+                    },
+                );
+                self.types.insert(name.to_string(), ty);
+            }
+        }
+        scope.insert(
+            name.to_string(),
+            Symbol {
+                name: name.to_string(),
+                span: span.clone(),
+                signature: None,
+                linkage: true,
+            },
+        );
+        Ok(())
+    }
+
+    /// Declares a function, which keeps its name so that it can be linked
+    /// to. Definitions are only allowed at file scope, and like
+    /// variables, functions have the linkage of their first declaration
+    /// unless declared `static`.
+    fn declare_function(
+        &mut self,
+        name: &str,
+        signature: Signature,
+        storage: Option<&Operations>,
+        defined: bool,
+        span: &Span,
+    ) -> Result<(), SemanticError> {
+        if defined && self.stack.len() > 1 {
+            return Err(SemanticError::NestedFunction {
+                name: name.to_string(),
+                span: span.clone(),
+            });
+        }
+        let is_static = matches!(storage, Some(Operations::Static));
+        if is_static && self.stack.len() > 1 {
+            return Err(SemanticError::InvalidStorageClass {
+                storage: "static",
+                context: "on a function declared in a block",
+                span: span.clone(),
+            });
+        }
+        if let Some(previous) = self.variables.get(name) {
+            return Err(SemanticError::ConflictingKind {
+                name: name.to_string(),
+                span: span.clone(),
+                previous: previous.span.clone(),
+            });
+        }
+        let scope = self.stack.last_mut().unwrap();
+        if let Some(
+            previous @ Symbol {
+                signature: None, ..
+            },
+        ) = scope.get(name)
+        {
+            return Err(SemanticError::DuplicateDeclaration {
+                name: name.to_string(),
+                span: span.clone(),
+                previous: previous.span.clone(),
+            });
+        }
+        if let Some(previous) = self.functions.get_mut(name) {
+            if previous.signature != signature {
+                return Err(SemanticError::ConflictingDeclaration {
+                    name: name.to_string(),
+                    span: span.clone(),
+                    previous: previous.span.clone(),
+                });
+            }
+            if is_static && previous.global {
+                return Err(SemanticError::ConflictingLinkage {
+                    name: name.to_string(),
+                    is_static,
+                    span: span.clone(),
+                    previous: previous.span.clone(),
+                });
+            }
+            if previous.defined && defined {
+                return Err(SemanticError::DuplicateDefinition {
+                    name: name.to_string(),
+                    span: span.clone(),
+                    previous: previous.span.clone(),
+                });
+            }
+            if defined {
+                previous.defined = true;
+                previous.span = span.clone();
+            }
+        } else {
+            self.types.insert(name.to_string(), signature.returns);
+            self.functions.insert(
+                name.to_string(),
+                Function {
+                    signature: signature.clone(),
+                    span: span.clone(),
+                    defined,
+                    global: !is_static,
+                },
+            );
+        }
+        scope.insert(
+            name.to_string(),
+            Symbol {
+                name: name.to_string(),
+                span: span.clone(),
+                signature: Some(signature),
+                linkage: true,
+            },
+        );
+        Ok(())
+    }
+
+    fn lookup(&self, name: &str, span: &Span) -> Result<&Symbol, SemanticError> {
+        self.stack
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
+            .ok_or_else(|| SemanticError::UndeclaredIdentifier {
+                name: name.to_string(),
+                span: span.clone(),
+            })
+    }
+
+    fn lookup_variable(&self, name: &str, span: &Span) -> Result<String, SemanticError> {
+        match self.lookup(name, span)? {
+            Symbol {
+                signature: None,
+                name,
+                ..
+            } => Ok(name.clone()),
+            _ => Err(SemanticError::FunctionAsValue {
+                name: name.to_string(),
+                span: span.clone(),
+            }),
+        }
+    }
+
+    /// Checks that a call names a function and passes it `args`
+    /// arguments, and returns whether the function is variadic.
+    fn check_call(&self, name: &str, args: usize, span: &Span) -> Result<bool, SemanticError> {
+        match &self.lookup(name, span)?.signature {
+            Some(Signature {
+                params, variadic, ..
+            }) if params.len() == args || (*variadic && params.len() < args) => Ok(*variadic),
+            Some(Signature {
+                params, variadic, ..
+            }) => Err(SemanticError::ArgumentCount {
+                name: name.to_string(),
+                expected: params.len(),
+                found: args,
+                variadic: *variadic,
+                span: span.clone(),
+            }),
+            None => Err(SemanticError::NotAFunction {
+                name: name.to_string(),
+                span: span.clone(),
+            }),
+        }
+    }
+}
+
+/// Name held by a `Var` node.
+fn var_name(node: Node, span: &Span) -> Result<String, SemanticError> {
+    match node {
+        Node::Str(name) => Ok(name),
+        node => Err(SemanticError::UnexpectedNode {
+            node: format!("variable {:?}", node),
+            span: Some(span.clone()),
+        }),
+    }
+}
+
+/// Declares a parameter in the innermost scope. Parameters without a name
+/// only occur in declarations, and are left undeclared.
+fn declare_param(ast: Node, scopes: &mut Scopes) -> Result<Box<Node>, SemanticError> {
+    let Node::Unary {
+        op: Operations::Declaration(ty),
+        node: var,
+        span,
+    } = ast
+    else {
+        return Err(SemanticError::UnexpectedNode {
+            node: format!("parameter {:?}", ast),
+            span: ast.span().cloned(),
+        });
+    };
+    match *var {
+        Node::Unary {
+            op: Operations::Var,
+            node,
+            span: name_span,
+        } => {
+            let name = match var_name(*node, &name_span)? {
+                name if name.is_empty() => name,
+                name => scopes.declare(&name, ty, &name_span)?,
+            };
+            Ok(Box::new(Node::Unary {
+                op: Operations::Declaration(ty),
+                node: Box::new(Node::Unary {
+                    op: Operations::Var,
+                    node: Box::new(Node::Str(name)),
+                    span: name_span,
+                }),
+                span,
+            }))
+        }
+        node => Err(SemanticError::UnexpectedNode {
+            node: format!("declaration of {:?}", node),
+            span: Some(span),
+        }),
+    }
+}
+
+fn resolve_optional(
+    node: Option<Box<Node>>,
+    scopes: &mut Scopes,
+) -> Result<Option<Box<Node>>, SemanticError> {
+    node.map(|node| resolve(*node, scopes)).transpose()
+}
+
+fn resolve_items(
+    items: VecDeque<Box<Node>>,
+    scopes: &mut Scopes,
+) -> Result<VecDeque<Box<Node>>, SemanticError> {
+    items
+        .into_iter()
+        .map(|item| resolve(*item, scopes))
+        .collect()
+}
+
+/// Nothing, in place of a declaration that needs no code where it is.
+fn empty() -> Box<Node> {
+    Box::new(Node::Sequence(VecDeque::new()))
+}
+
+/// Value a variable of type `ty` with static storage duration is
+/// initialized with, which must be known at compile time.
+fn static_initializer(
+    name: &str,
+    ty: Type,
+    init: &Node,
+    span: &Span,
+) -> Result<Constant, SemanticError> {
+    match constant_value(init) {
+        Some(value) => Ok(Constant::new(value.value, ty)),
+        None => Err(SemanticError::NonConstantInitializer {
+            name: name.to_string(),
+            span: init.span().unwrap_or(span).clone(),
+        }),
+    }
+}
+
+/// Resolves a function or variable declaration with an optional storage
+/// class. Variables with static storage duration are recorded in
+/// `scopes` and defined at the top level after analysis, so their
+/// declarations are left out.
+fn resolve_declaration(
+    ast: Node,
+    storage: Option<Operations>,
+    scopes: &mut Scopes,
+) -> Result<Box<Node>, SemanticError> {
+    let file_scope = scopes.stack.len() == 1;
+    let (ty, var, init, span) = match ast {
+        // Parameters are in the same scope as the outermost block of the
+        // body.
+        Node::Function {
+            name,
+            return_type,
+            params,
+            variadic,
+            body,
+            span,
+        } => {
+            let signature = Signature {
+                params: params
+                    .iter()
+                    .filter_map(|param| match **param {
+                        Node::Unary {
+                            op: Operations::Declaration(ty),
+                            ..
+                        } => Some(ty),
+                        _ => None,
+                    })
+                    .collect(),
+                variadic,
+                returns: return_type,
+            };
+            scopes.declare_function(&name, signature, storage.as_ref(), body.is_some(), &span)?;
+            scopes.stack.push(HashMap::new());
+            let params = params
+                .into_iter()
+                .map(|param| declare_param(*param, scopes))
+                .collect::<Result<_, _>>()?;
+            let body = match body.map(|body| *body) {
+                Some(Node::Sequence(items)) => {
+                    Some(Box::new(Node::Sequence(resolve_items(items, scopes)?)))
+                }
+                Some(body) => {
+                    return Err(SemanticError::UnexpectedNode {
+                        node: format!("function body {:?}", body),
+                        span: Some(span),
+                    })
+                }
+                None => None,
+            };
+            scopes.stack.pop();
+            return Ok(Box::new(Node::Function {
+                name,
+                return_type,
+                params,
+                variadic,
+                body,
+                span,
+            }));
+        }
+        Node::Unary {
+            op: Operations::Declaration(ty),
+            node,
+            span,
+        } => (ty, node, None, span),
+        Node::Binary {
+            op: Operations::Declaration(ty),
+            lhs,
+            rhs,
+            span,
+        } => (ty, lhs, Some(rhs), span),
+        node => {
+            return Err(SemanticError::UnexpectedNode {
+                node: format!("declaration {:?}", node),
+                span: node.span().cloned(),
+            })
+        }
+    };
+    let Node::Unary {
+        op: Operations::Var,
+        node: name,
+        span: name_span,
+    } = *var
+    else {
+        return Err(SemanticError::UnexpectedNode {
+            node: format!("declaration of {:?}", var),
+            span: Some(span),
+        });
+    };
+    let name = var_name(*name, &name_span)?;
+    match (file_scope, &storage, init) {
+        (true, _, Some(init)) => {
+            let value = static_initializer(&name, ty, &init, &span)?;
+            let init = InitialValue::Constant(value);
+            scopes.declare_linked(&name, ty, storage.as_ref(), init, &span)?;
+        }
+        (true, Some(Operations::Extern), None) => {
+            let init = InitialValue::External;
+            scopes.declare_linked(&name, ty, storage.as_ref(), init, &span)?;
+        }
+        (true, _, None) => {
+            let init = InitialValue::Tentative;
+            scopes.declare_linked(&name, ty, storage.as_ref(), init, &span)?;
+        }
+        (false, Some(Operations::Extern), Some(init)) => {
+            return Err(SemanticError::ExternInitializer {
+                name,
+                span: init.span().unwrap_or(&span).clone(),
+            })
+        }
+        (false, Some(Operations::Extern), None) => {
+            let init = InitialValue::External;
+            scopes.declare_linked(&name, ty, storage.as_ref(), init, &span)?;
+        }
+        (false, Some(_), init) => {
+            let value = match init {
+                Some(init) => static_initializer(&name, ty, &init, &span)?,
+                None => Constant::new(0, ty),
+            };
+            scopes.declare_static(&name, ty, value, &span)?;
+        }
+        // The variable is in scope from the end of its declarator, so the
+        // initializer already refers to it.
+        (false, None, init) => {
+            let var = Box::new(Node::Unary {
+                op: Operations::Var,
+                node: Box::new(Node::Str(scopes.declare(&name, ty, &name_span)?)),
+                span: name_span,
+            });
+            return Ok(Box::new(match init {
+                Some(init) => Node::Binary {
+                    op: Operations::Declaration(ty),
+                    lhs: var,
+                    rhs: resolve(*init, scopes)?,
+                    span,
+                },
+                None => Node::Unary {
+                    op: Operations::Declaration(ty),
+                    node: var,
+                    span,
+                },
+            }));
+        }
+    }
+    Ok(empty())
+}
+
+fn resolve(ast: Node, scopes: &mut Scopes) -> Result<Box<Node>, SemanticError> {
+    let node = match ast {
+        Node::Int(_) | Node::Str(_) => ast,
+        Node::Sequence(items) => {
+            scopes.stack.push(HashMap::new());
+            let items = resolve_items(items, scopes)?;
+            scopes.stack.pop();
+            Node::Sequence(items)
+        }
+        ast @ (Node::Function { .. }
+        | Node::Unary {
+            op: Operations::Declaration(_),
+            ..
+        }
+        | Node::Binary {
+            op: Operations::Declaration(_),
+            ..
+        }) => return resolve_declaration(ast, None, scopes),
+        Node::Unary {
+            op: storage @ (Operations::Static | Operations::Extern),
+            node,
+            ..
+        } => return resolve_declaration(*node, Some(storage), scopes),
+        Node::Call {
+            name, args, span, ..
+        } => {
+            let variadic = scopes.check_call(&name, args.len(), &span)?;
+            Node::Call {
+                name,
+                variadic,
+                args: args
+                    .into_iter()
+                    .map(|arg| resolve(*arg, scopes))
+                    .collect::<Result<_, _>>()?,
+                span,
+            }
+        }
+        Node::Unary {
+            op: Operations::Var,
+            node,
+            span,
+        } => {
+            let name = scopes.lookup_variable(&var_name(*node, &span)?, &span)?;
+            Node::Unary {
+                op: Operations::Var,
+                node: Box::new(Node::Str(name)),
+                span,
+            }
+        }
+        Node::Binary { op, lhs, rhs, span }
+            if matches!(op, Operations::Assign) || is_compound_assignment(&op) =>
+        {
+            if !matches!(
+                *lhs,
+                Node::Unary {
+                    op: Operations::Var,
+                    ..
+                }
+            ) {
+                return Err(SemanticError::InvalidLvalue {
+                    span: lhs.span().unwrap_or(&span).clone(),
+                });
+            }
+            Node::Binary {
+                op,
+                lhs: resolve(*lhs, scopes)?,
+                rhs: resolve(*rhs, scopes)?,
+                span,
+            }
+        }
+        Node::Unary { op, node, span } => Node::Unary {
+            op,
+            node: resolve(*node, scopes)?,
+            span,
+        },
+        Node::Binary { op, lhs, rhs, span } => Node::Binary {
+            op,
+            lhs: resolve(*lhs, scopes)?,
+            rhs: resolve(*rhs, scopes)?,
+            span,
+        },
+        // A `for` loop is a scope of its own, holding the variable its
+        // header declares.
+        Node::Loop {
+            op,
+            init,
+            condition,
+            post,
+            body,
+            label,
+            span,
+        } => {
+            if let Some(Node::Unary {
+                op: storage @ (Operations::Static | Operations::Extern),
+                span,
+                ..
+            }) = init.as_deref()
+            {
+                return Err(SemanticError::InvalidStorageClass {
+                    storage: match storage {
+                        Operations::Static => "static",
+                        _ => "extern",
+                    },
+                    context: "in a `for` loop initializer",
+                    span: span.clone(),
+                });
+            }
+            scopes.stack.push(HashMap::new());
+            let node = Node::Loop {
+                op,
+                init: resolve_optional(init, scopes)?,
+                condition: resolve_optional(condition, scopes)?,
+                post: resolve_optional(post, scopes)?,
+                body: resolve(*body, scopes)?,
+                label,
+                span,
+            };
+            scopes.stack.pop();
+            node
+        }
+        Node::Switch {
+            condition,
+            body,
+            label,
+            cases,
+            default,
+            span,
+        } => Node::Switch {
+            condition: resolve(*condition, scopes)?,
+            body: resolve(*body, scopes)?,
+            label,
+            cases,
+            default,
+            span,
+        },
+        Node::Labeled {
+            op,
+            value,
+            label,
+            node,
+            span,
+        } => Node::Labeled {
+            op,
+            value: resolve_optional(value, scopes)?,
+            label,
+            node: resolve(*node, scopes)?,
+            span,
+        },
+        Node::Ternary {
+            op,
+            condition,
+            then,
+            otherwise,
+            span,
+        } => Node::Ternary {
+            op,
+            condition: resolve(*condition, scopes)?,
+            then: resolve(*then, scopes)?,
+            otherwise: resolve_optional(otherwise, scopes)?,
+            span,
+        },
+    };
+    Ok(Box::new(node))
+}
+
+/// `node`, of type `from`, converted to `to`.
+fn convert(node: Box<Node>, from: Type, to: Type) -> Box<Node> {
+    if from == to {
+        return node;
+    }
+    let span = node.span().unwrap().clone();
+    Box::new(Node::Unary {
+        op: Operations::Cast(to),
+        node,
+        span,
+    })
+}
+
+fn is_comparison(op: &Operations) -> bool {
+    matches!(
+        op,
+        Operations::Equal
+            | Operations::NotEqual
+            | Operations::LessThan
+            | Operations::LessOrEqual
+            | Operations::GreaterThan
+            | Operations::GreaterOrEqual
+    )
+}
+
+/// Operator applied by a compound assignment.
+fn compound_operation(op: &Operations) -> Option<Operations> {
+    let op = match op {
+        Operations::AddAssign => Operations::Add,
+        Operations::SubtractAssign => Operations::Subtract,
+        Operations::MultiplyAssign => Operations::Multiply,
+        Operations::DivideAssign => Operations::Divide,
+        Operations::RemainderAssign => Operations::Remainder,
+        Operations::BitwiseAndAssign => Operations::BitwiseAnd,
+        Operations::BitwiseOrAssign => Operations::BitwiseOr,
+        Operations::BitwiseXorAssign => Operations::BitwiseXor,
+        Operations::LeftShiftAssign => Operations::LeftShift,
+        Operations::RightShiftAssign => Operations::RightShift,
+        _ => return None,
+    };
+    Some(op)
+}
+
+/// Checks an expression and makes its conversions explicit, so that both
+/// operands of a binary operator have the same type and every value is
+/// converted to the type it is stored in or passed as. Returns the
+/// expression and its type.
+fn check_expression(ast: Node, scopes: &Scopes) -> Result<(Box<Node>, Type), SemanticError> {
+    let (node, ty) = match ast {
+        Node::Unary {
+            op: Operations::Constant,
+            node,
+            span,
+        } => {
+            let Node::Int(constant) = *node else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("constant {:?}", node),
+                    span: Some(span),
+                });
+            };
+            let node = Box::new(Node::Int(constant));
+            let op = Operations::Constant;
+            (Node::Unary { op, node, span }, constant.ty)
+        }
+        Node::Unary {
+            op: Operations::Var,
+            node,
+            span,
+        } => {
+            let ty = match &*node {
+                Node::Str(name) => scopes.types.get(name).copied(),
+                _ => None,
+            };
+            let Some(ty) = ty else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("variable {:?} without a type", node),
+                    span: Some(span),
+                });
+            };
+            let op = Operations::Var;
+            (Node::Unary { op, node, span }, ty)
+        }
+        Node::Unary {
+            op: Operations::Cast(ty),
+            node,
+            span,
+        } => {
+            let (node, _) = check_expression(*node, scopes)?;
+            let op = Operations::Cast(ty);
+            (Node::Unary { op, node, span }, ty)
+        }
+        Node::Unary {
+            op: Operations::Not,
+            node,
+            span,
+        } => {
+            let (node, _) = check_expression(*node, scopes)?;
+            let op = Operations::Not;
+            (Node::Unary { op, node, span }, Type::Int)
+        }
+        // `-` and `~` work on the promoted operand.
+        Node::Unary { op, node, span } => {
+            let (node, ty) = check_expression(*node, scopes)?;
+            let node = convert(node, ty, ty.promote());
+            (Node::Unary { op, node, span }, ty.promote())
+        }
+        Node::Binary {
+            op: op @ (Operations::And | Operations::Or),
+            lhs,
+            rhs,
+            span,
+        } => {
+            let (lhs, _) = check_expression(*lhs, scopes)?;
+            let (rhs, _) = check_expression(*rhs, scopes)?;
+            (Node::Binary { op, lhs, rhs, span }, Type::Int)
+        }
+        Node::Binary {
+            op: Operations::Assign,
+            lhs,
+            rhs,
+            span,
+        } => {
+            let (lhs, ty) = check_expression(*lhs, scopes)?;
+            let (rhs, from) = check_expression(*rhs, scopes)?;
+            let rhs = convert(rhs, from, ty);
+            let op = Operations::Assign;
+            (Node::Binary { op, lhs, rhs, span }, ty)
+        }
+        Node::Binary { op, lhs, rhs, span } if is_compound_assignment(&op) => {
+            let (lhs, ty) = check_expression(*lhs, scopes)?;
+            let (rhs, from) = check_expression(*rhs, scopes)?;
+            let operation = compound_operation(&op).unwrap();
+            let common = match operation {
+                Operations::LeftShift | Operations::RightShift => ty.promote(),
+                _ => ty.common(from),
+            };
+            if common == ty {
+                let rhs = convert(rhs, from, ty);
+                (Node::Binary { op, lhs, rhs, span }, ty)
+            } else {
+                // The operation is done in the common type, so `a op= b`
+                // becomes `a = (T)((C)a op (C)b)`.
+                let Node::Unary {
+                    node: ref name,
+                    span: ref var_span,
+                    ..
+                } = *lhs
+                else {
+                    return Err(SemanticError::InvalidLvalue { span });
+                };
+                let value = Box::new(Node::Unary {
+                    op: Operations::Var,
+                    node: Box::new(match &**name {
+                        Node::Str(name) => Node::Str(name.clone()),
+                        _ => return Err(SemanticError::InvalidLvalue { span }),
+                    }),
+                    span: var_span.clone(),
+                });
+                let value = Box::new(Node::Binary {
+                    op: operation,
+                    lhs: convert(value, ty, common),
+                    rhs: convert(rhs, from, common),
+                    span: span.clone(),
+                });
+                let rhs = convert(value, common, ty);
+                let op = Operations::Assign;
+                (Node::Binary { op, lhs, rhs, span }, ty)
+            }
+        }
+        // The right operand of a shift only gives the count, and the
+        // result has the type of the promoted left operand.
+        Node::Binary {
+            op: op @ (Operations::LeftShift | Operations::RightShift),
+            lhs,
+            rhs,
+            span,
+        } => {
+            let (lhs, left) = check_expression(*lhs, scopes)?;
+            let (rhs, right) = check_expression(*rhs, scopes)?;
+            let ty = left.promote();
+            let (lhs, rhs) = (convert(lhs, left, ty), convert(rhs, right, ty));
+            (Node::Binary { op, lhs, rhs, span }, ty)
+        }
+        Node::Binary { op, lhs, rhs, span } => {
+            let (lhs, left) = check_expression(*lhs, scopes)?;
+            let (rhs, right) = check_expression(*rhs, scopes)?;
+            let common = left.common(right);
+            let ty = if is_comparison(&op) {
+                Type::Int
+            } else {
+                common
+            };
+            let (lhs, rhs) = (convert(lhs, left, common), convert(rhs, right, common));
+            (Node::Binary { op, lhs, rhs, span }, ty)
+        }
+        Node::Ternary {
+            op,
+            condition,
+            then,
+            otherwise: Some(otherwise),
+            span,
+        } => {
+            let (condition, _) = check_expression(*condition, scopes)?;
+            let (then, a) = check_expression(*then, scopes)?;
+            let (otherwise, b) = check_expression(*otherwise, scopes)?;
+            let ty = a.common(b);
+            let node = Node::Ternary {
+                op,
+                condition,
+                then: convert(then, a, ty),
+                otherwise: Some(convert(otherwise, b, ty)),
+                span,
+            };
+            (node, ty)
+        }
+        // Arguments are converted to the types of the parameters, and the
+        // extra arguments of a variadic function are promoted.
+        Node::Call {
+            name,
+            args,
+            variadic,
+            span,
+        } => {
+            let Some(function) = scopes.functions.get(&name) else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("call of undeclared `{name}`"),
+                    span: Some(span),
+                });
+            };
+            let signature = &function.signature;
+            let args = args
+                .into_iter()
+                .enumerate()
+                .map(|(i, arg)| {
+                    let (arg, ty) = check_expression(*arg, scopes)?;
+                    let param = signature.params.get(i).copied();
+                    Ok(convert(arg, ty, param.unwrap_or(ty.promote())))
+                })
+                .collect::<Result<_, _>>()?;
+            let node = Node::Call {
+                name,
+                args,
+                variadic,
+                span,
+            };
+            (node, signature.returns)
+        }
+        node => {
+            return Err(SemanticError::UnexpectedNode {
+                node: format!("expression {:?}", node),
+                span: node.span().cloned(),
+            })
+        }
+    };
+    Ok((Box::new(node), ty))
+}
+
+/// Return type of the function being checked, and the promoted type of
+/// the condition of the innermost switch around the current statement.
+struct Enclosing {
+    return_type: Type,
+    switch: Option<Type>,
+}
+
+fn check_optional(
+    node: Option<Box<Node>>,
+    scopes: &Scopes,
+    enclosing: &mut Enclosing,
+) -> Result<Option<Box<Node>>, SemanticError> {
+    node.map(|node| typecheck(*node, scopes, enclosing))
+        .transpose()
+}
+
+/// Checks the types in a statement, or in an expression where one is
+/// allowed, and makes every conversion explicit with `Cast` nodes.
+fn typecheck(
+    ast: Node,
+    scopes: &Scopes,
+    enclosing: &mut Enclosing,
+) -> Result<Box<Node>, SemanticError> {
+    let node = match ast {
+        Node::Unary {
+            op: Operations::Program,
+            node,
+            span,
+        } => Node::Unary {
+            op: Operations::Program,
+            node: typecheck(*node, scopes, enclosing)?,
+            span,
+        },
+        Node::Sequence(items) => Node::Sequence(
+            items
+                .into_iter()
+                .map(|item| typecheck(*item, scopes, enclosing))
+                .collect::<Result<_, _>>()?,
+        ),
+        Node::Function {
+            name,
+            return_type,
+            params,
+            variadic,
+            body: Some(body),
+            span,
+        } => {
+            enclosing.return_type = return_type;
+            Node::Function {
+                name,
+                return_type,
+                params,
+                variadic,
+                body: Some(typecheck(*body, scopes, enclosing)?),
+                span,
+            }
+        }
+        Node::Unary {
+            op: Operations::Return,
+            node,
+            span,
+        } => {
+            let (node, ty) = check_expression(*node, scopes)?;
+            Node::Unary {
+                op: Operations::Return,
+                node: convert(node, ty, enclosing.return_type),
+                span,
+            }
+        }
+        Node::Unary {
+            op: Operations::Expression,
+            node,
+            span,
+        } => Node::Unary {
+            op: Operations::Expression,
+            node: check_expression(*node, scopes)?.0,
+            span,
+        },
+        Node::Binary {
+            op: Operations::Declaration(ty),
+            lhs,
+            rhs,
+            span,
+        } => {
+            let (rhs, from) = check_expression(*rhs, scopes)?;
+            Node::Binary {
+                op: Operations::Declaration(ty),
+                lhs,
+                rhs: convert(rhs, from, ty),
+                span,
+            }
+        }
+        ast @ (Node::Function { body: None, .. }
+        | Node::Unary {
+            op:
+                Operations::Declaration(_) | Operations::Break | Operations::Continue | Operations::Goto,
+            ..
+        }) => ast,
+        Node::Ternary {
+            op: Operations::If,
+            condition,
+            then,
+            otherwise,
+            span,
+        } => Node::Ternary {
+            op: Operations::If,
+            condition: check_expression(*condition, scopes)?.0,
+            then: typecheck(*then, scopes, enclosing)?,
+            otherwise: check_optional(otherwise, scopes, enclosing)?,
+            span,
+        },
+        Node::Loop {
+            op,
+            init,
+            condition,
+            post,
+            body,
+            label,
+            span,
+        } => Node::Loop {
+            op,
+            init: check_optional(init, scopes, enclosing)?,
+            condition: check_optional(condition, scopes, enclosing)?,
+            post: check_optional(post, scopes, enclosing)?,
+            body: typecheck(*body, scopes, enclosing)?,
+            label,
+            span,
+        },
+        Node::Switch {
+            condition,
+            body,
+            label,
+            cases,
+            default,
+            span,
+        } => {
+            let (condition, ty) = check_expression(*condition, scopes)?;
+            let outer = enclosing.switch.replace(ty.promote());
+            let body = typecheck(*body, scopes, enclosing)?;
+            enclosing.switch = outer;
+            Node::Switch {
+                condition: convert(condition, ty, ty.promote()),
+                body,
+                label,
+                cases,
+                default,
+                span,
+            }
+        }
+        // Case values are converted to the type of the condition of their
+        // switch. Labeling reports those that are not constant.
+        Node::Labeled {
+            op,
+            value,
+            label,
+            node,
+            span,
+        } => {
+            let value = match (value, enclosing.switch) {
+                (Some(value), Some(ty)) => match constant_value(&value) {
+                    Some(constant) => {
+                        let span = value.span().unwrap().clone();
+                        Some(Box::new(Node::Unary {
+                            op: Operations::Constant,
+                            node: Box::new(Node::Int(Constant::new(constant.value, ty))),
+                            span,
+                        }))
+                    }
+                    None => Some(value),
+                },
+                (value, _) => value,
+            };
+            Node::Labeled {
+                op,
+                value,
+                label,
+                node: typecheck(*node, scopes, enclosing)?,
+                span,
+            }
+        }
+        ast => return Ok(check_expression(ast, scopes)?.0),
+    };
+    Ok(Box::new(node))
+}
+
+/// Cases found so far in the body of a switch, with their locations.
+#[derive(Default)]
+struct SwitchCases {
+    cases: Vec<(i64, String, Span)>,
+    default: Option<(String, Span)>,
+}
+
+/// Where `break` and `continue` jump to at the current point, the
+/// switch that `case` and `default` belong to, and the unique names of
+/// the labels of the current function.
+#[derive(Default)]
+struct Targets {
+    break_label: Option<String>,
+    continue_label: Option<String>,
+    switch: Option<SwitchCases>,
+    labels: HashMap<String, (String, Span)>,
+}
+
+/// Gives each ordinary label in a function body a unique name, so it
+/// cannot clash with the compiler's labels or those of other functions.
+/// Labels are collected before `goto`s are resolved, since a `goto` may
+/// come first.
+fn collect_labels(
+    ast: &mut Node,
+    labels: &mut HashMap<String, (String, Span)>,
+) -> Result<(), SemanticError> {
+    match ast {
+        Node::Labeled {
+            op: Operations::Label,
+            label,
+            node,
+            span,
+            ..
+        } => {
+            if let Some((_, previous)) = labels.get(label.as_str()) {
+                return Err(SemanticError::DuplicateLabel {
+                    name: label.clone(),
+                    span: span.clone(),
+                    previous: previous.clone(),
+                });
+            }
+            let unique = get_next_label(label);
+            labels.insert(label.clone(), (unique.clone(), span.clone()));
+            *label = unique;
+            collect_labels(node, labels)
+        }
+        Node::Labeled { node: body, .. } | Node::Loop { body, .. } | Node::Switch { body, .. } => {
+            collect_labels(body, labels)
+        }
+        Node::Ternary {
+            then, otherwise, ..
+        } => {
+            collect_labels(then, labels)?;
+            match otherwise {
+                Some(otherwise) => collect_labels(otherwise, labels),
+                None => Ok(()),
+            }
+        }
+        Node::Sequence(items) => items
+            .iter_mut()
+            .try_for_each(|item| collect_labels(item, labels)),
+        // Expressions and the other statements hold no labels.
+        _ => Ok(()),
+    }
+}
+
+/// Value of an integer constant expression, with its type, or `None` if
+/// `node` is not one.
+fn constant_value(node: &Node) -> Option<Constant> {
+    match node {
+        Node::Unary {
+            op: Operations::Constant,
+            node,
+            ..
+        } => match **node {
+            Node::Int(constant) => Some(constant),
+            _ => None,
+        },
+        Node::Unary { op, node, .. } => {
+            let operand = constant_value(node)?;
+            let ty = operand.ty.promote();
+            match op {
+                Operations::Cast(ty) => Some(Constant::new(operand.value, *ty)),
+                Operations::Negate => Some(Constant::new(operand.value.wrapping_neg(), ty)),
+                Operations::Complement => Some(Constant::new(!operand.value, ty)),
+                Operations::Not => Some(Constant::new((operand.value == 0) as i64, Type::Int)),
+                _ => None,
+            }
+        }
+        Node::Binary { op, lhs, rhs, .. } => {
+            let a = constant_value(lhs)?;
+            // The right operand of `&&` and `||` need not be constant when
+            // it is not evaluated.
+            match op {
+                Operations::And if a.value == 0 => return Some(Constant::new(0, Type::Int)),
+                Operations::Or if a.value != 0 => return Some(Constant::new(1, Type::Int)),
+                _ => {}
+            }
+            let b = constant_value(rhs)?;
+            if let Operations::LeftShift | Operations::RightShift = op {
+                let ty = a.ty.promote();
+                let count = u32::try_from(b.value)
+                    .ok()
+                    .filter(|count| (*count as i64) < 8 * ty.size())?;
+                let value = match op {
+                    Operations::LeftShift => a.value << count,
+                    _ if ty.is_signed() => a.value >> count,
+                    _ => ((a.value as u64) >> count) as i64,
+                };
+                return Some(Constant::new(value, ty));
+            }
+            let ty = a.ty.common(b.ty);
+            let (a, b) = (ty.convert(a.value), ty.convert(b.value));
+            let ordering = if ty.is_signed() {
+                a.cmp(&b)
+            } else {
+                (a as u64).cmp(&(b as u64))
+            };
+            let (value, ty) = match op {
+                Operations::Add => (a.wrapping_add(b), ty),
+                Operations::Subtract => (a.wrapping_sub(b), ty),
+                Operations::Multiply => (a.wrapping_mul(b), ty),
+                Operations::Divide if ty.is_signed() => (a.checked_div(b)?, ty),
+                Operations::Divide => ((a as u64).checked_div(b as u64)? as i64, ty),
+                Operations::Remainder if ty.is_signed() => (a.checked_rem(b)?, ty),
+                Operations::Remainder => ((a as u64).checked_rem(b as u64)? as i64, ty),
+                Operations::BitwiseAnd => (a & b, ty),
+                Operations::BitwiseOr => (a | b, ty),
+                Operations::BitwiseXor => (a ^ b, ty),
+                Operations::Equal => (ordering.is_eq() as i64, Type::Int),
+                Operations::NotEqual => (ordering.is_ne() as i64, Type::Int),
+                Operations::LessThan => (ordering.is_lt() as i64, Type::Int),
+                Operations::LessOrEqual => (ordering.is_le() as i64, Type::Int),
+                Operations::GreaterThan => (ordering.is_gt() as i64, Type::Int),
+                Operations::GreaterOrEqual => (ordering.is_ge() as i64, Type::Int),
+                Operations::And | Operations::Or => ((b != 0) as i64, Type::Int),
+                _ => return None,
+            };
+            Some(Constant::new(value, ty))
+        }
+        // The branch that is not taken need not be constant, in which case
+        // the result keeps the type of the other one.
+        Node::Ternary {
+            op: Operations::Conditional,
+            condition,
+            then,
+            otherwise: Some(otherwise),
+            ..
+        } => {
+            let (taken, other) = match constant_value(condition)?.value {
+                0 => (otherwise, then),
+                _ => (then, otherwise),
+            };
+            let value = constant_value(taken)?;
+            let ty = match constant_value(other) {
+                Some(other) => value.ty.common(other.ty),
+                None => value.ty,
+            };
+            Some(Constant::new(value.value, ty))
+        }
+        _ => None,
+    }
+}
+
+/// Records a `case` or `default` label in the switch around it.
+fn add_case(
+    op: &Operations,
+    value: Option<&Node>,
+    label: &str,
+    span: &Span,
+    targets: &mut Targets,
+) -> Result<(), SemanticError> {
+    let Some(switch) = &mut targets.switch else {
+        return Err(SemanticError::CaseOutsideSwitch {
+            statement: match op {
+                Operations::Case => "case",
+                _ => "default",
+            },
+            span: span.clone(),
+        });
+    };
+    let Some(value) = value else {
+        if let Some((_, previous)) = &switch.default {
+            return Err(SemanticError::DuplicateCase {
+                value: None,
+                span: span.clone(),
+                previous: previous.clone(),
+            });
+        }
+        switch.default = Some((label.to_string(), span.clone()));
+        return Ok(());
+    };
+    let Some(constant) = constant_value(value) else {
+        return Err(SemanticError::NonConstantCase {
+            span: value.span().unwrap_or(span).clone(),
+        });
+    };
+    let value = constant.value;
+    if let Some((_, _, previous)) = switch.cases.iter().find(|case| case.0 == value) {
+        return Err(SemanticError::DuplicateCase {
+            value: Some(value),
+            span: span.clone(),
+            previous: previous.clone(),
+        });
+    }
+    switch.cases.push((value, label.to_string(), span.clone()));
+    Ok(())
+}
+
+/// Names each loop and switch, points every `break` and `continue` at the
+/// statement it leaves, collects the cases of each switch, and points
+/// every `goto` at its label.
+fn label_statements(ast: &mut Node, targets: &mut Targets) -> Result<(), SemanticError> {
+    match ast {
+        Node::Int(_) | Node::Str(_) => Ok(()),
+        Node::Loop { body, label, .. } => {
+            *label = get_next_label("loop");
+            let break_label = targets.break_label.replace(label.clone());
+            let continue_label = targets.continue_label.replace(label.clone());
+            label_statements(body, targets)?;
+            targets.break_label = break_label;
+            targets.continue_label = continue_label;
+            Ok(())
+        }
+        Node::Switch {
+            body,
+            label,
+            cases,
+            default,
+            ..
+        } => {
+            *label = get_next_label("switch");
+            let break_label = targets.break_label.replace(label.clone());
+            let outer = targets.switch.replace(SwitchCases::default());
+            label_statements(body, targets)?;
+            targets.break_label = break_label;
+            let switch = std::mem::replace(&mut targets.switch, outer).unwrap();
+            *cases = switch
+                .cases
+                .into_iter()
+                .map(|(value, label, _)| (value, label))
+                .collect();
+            *default = switch.default.map(|(label, _)| label);
+            Ok(())
+        }
+        Node::Labeled {
+            op,
+            value,
+            label,
+            node,
+            span,
+        } => {
+            if let Operations::Case | Operations::Default = op {
+                *label = match op {
+                    Operations::Case => get_next_label("case"),
+                    _ => get_next_label("default"),
+                };
+                add_case(op, value.as_deref(), label, span, targets)?;
+            }
+            label_statements(node, targets)
+        }
+        Node::Unary {
+            op: op @ (Operations::Break | Operations::Continue),
+            node,
+            span,
+        } => {
+            let (target, statement) = match op {
+                Operations::Break => (&targets.break_label, "break"),
+                _ => (&targets.continue_label, "continue"),
+            };
+            let Some(label) = target else {
+                return Err(SemanticError::JumpOutsideLoop {
+                    statement,
+                    span: span.clone(),
+                });
+            };
+            **node = Node::Str(label.clone());
+            Ok(())
+        }
+        Node::Unary {
+            op: Operations::Goto,
+            node,
+            span,
+        } => {
+            let Node::Str(name) = &mut **node else {
+                return Err(SemanticError::UnexpectedNode {
+                    node: format!("goto {:?}", node),
+                    span: Some(span.clone()),
+                });
+            };
+            let Some((label, _)) = targets.labels.get(name.as_str()) else {
+                return Err(SemanticError::UndefinedLabel {
+                    name: name.clone(),
+                    span: span.clone(),
+                });
+            };
+            *name = label.clone();
+            Ok(())
+        }
+        Node::Unary { node, .. } => label_statements(node, targets),
+        Node::Function {
+            body: Some(body), ..
+        } => {
+            targets.labels.clear();
+            collect_labels(body, &mut targets.labels)?;
+            label_statements(body, targets)
+        }
+        Node::Function { body: None, .. } | Node::Call { .. } => Ok(()),
+        Node::Binary { lhs, rhs, .. } => {
+            label_statements(lhs, targets)?;
+            label_statements(rhs, targets)
+        }
+        Node::Ternary {
+            then, otherwise, ..
+        } => {
+            label_statements(then, targets)?;
+            match otherwise {
+                Some(otherwise) => label_statements(otherwise, targets),
+                None => Ok(()),
+            }
+        }
+        Node::Sequence(items) => items
+            .iter_mut()
+            .try_for_each(|item| label_statements(item, targets)),
+    }
+}
+
+/// Wraps a file scope definition in `Static` if it has internal linkage.
+fn with_linkage(node: Box<Node>, global: bool) -> Box<Node> {
+    if global {
+        return node;
+    }
+    let span = node.span().unwrap().clone();
+    Box::new(Node::Unary {
+        op: Operations::Static,
+        node,
+        span,
+    })
+}
+
+/// Lays out the program for the later stages: the function definitions,
+/// then every variable with static storage duration, defined with its
+/// initial value or declared `Extern` if it is defined in another file.
+fn definitions(ast: Box<Node>, scopes: &Scopes) -> Result<Box<Node>, SemanticError> {
+    let Node::Unary {
+        op: Operations::Program,
+        node,
+        span,
+    } = *ast
+    else {
+        return Err(SemanticError::UnexpectedNode {
+            node: format!("program {:?}", ast),
+            span: ast.span().cloned(),
+        });
+    };
+    let Node::Sequence(items) = *node else {
+        return Err(SemanticError::UnexpectedNode {
+            node: format!("top level {:?}", node),
+            span: Some(span),
+        });
+    };
+    let mut definitions = VecDeque::new();
+    for item in items {
+        if let Node::Function {
+            name,
+            body: Some(_),
+            ..
+        } = &*item
+        {
+            let global = scopes.functions[name].global;
+            definitions.push_back(with_linkage(item, global));
+        }
+    }
+    for name in &scopes.order {
+        let variable = &scopes.variables[name];
+        let span = variable.span.clone();
+        let var = Box::new(Node::Unary {
+            op: Operations::Var,
+            node: Box::new(Node::Str(name.clone())),
+            span: span.clone(),
+        });
+        let value = match variable.init {
+            InitialValue::Constant(value) => value,
+            InitialValue::Tentative => Constant::new(0, variable.ty),
+            InitialValue::External => {
+                definitions.push_back(Box::new(Node::Unary {
+                    op: Operations::Extern,
+                    node: Box::new(Node::Unary {
+                        op: Operations::Declaration(variable.ty),
+                        node: var,
+                        span: span.clone(),
+                    }),
+                    span,
+                }));
+                continue;
+            }
+        };
+        let definition = Box::new(Node::Binary {
+            op: Operations::Declaration(variable.ty),
+            lhs: var,
+            rhs: Box::new(Node::Unary {
+                op: Operations::Constant,
+                node: Box::new(Node::Int(value)),
+                span: span.clone(),
+            }),
+            span,
+        });
+        definitions.push_back(with_linkage(definition, variable.global));
+    }
+    Ok(Box::new(Node::Unary {
+        op: Operations::Program,
+        node: Box::new(Node::Sequence(definitions)),
+        span,
+    }))
+}
+
+/// Checks the program, gives every local variable and label a unique
+/// name and labels loops and switches, so later stages need not know
+/// about scopes, makes type conversions explicit, and lists the variables
+/// with static storage duration after the functions. Returns the program
+/// with the types of its variables and functions.
+pub fn analyze(ast: Node, debug_mode: bool) -> Result<(Box<Node>, Symbols), SemanticError> {
+    let mut scopes = Scopes {
+        stack: Vec::new(),
+        functions: HashMap::new(),
+        variables: HashMap::new(),
+        order: Vec::new(),
+        types: Symbols::new(),
+    };
+    let res = resolve(ast, &mut scopes)?;
+    let mut enclosing = Enclosing {
+        return_type: Type::Int,
+        switch: None,
+    };
+    let mut res = typecheck(*res, &scopes, &mut enclosing)?;
+    label_statements(&mut res, &mut Targets::default())?;
+    let res = definitions(res, &scopes)?;
+    if debug_mode {
+        println!("{:?}", res)
+    };
+    Ok((res, scopes.types))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::lex;
+    use crate::parser::parser::parse;
+
+    fn check(source: &str) -> Result<Symbols, SemanticError> {
+        let ast = parse(&mut lex(source, "test.c", false).unwrap(), false).unwrap();
+        analyze(*ast, false).map(|(_, symbols)| symbols)
+    }
+
+    /// The message and line of the error in the body of `main`.
+    fn error(body: &str) -> (String, u32) {
+        let source = format!("int main(void) {{\n{body}\n}}");
+        let error = check(&source).expect_err(body);
+        let line = error.diagnostic().span.map_or(0, |span| span.line);
+        (error.to_string(), line)
+    }
+
+    fn message(body: &str) -> String {
+        error(body).0
+    }
+
+    #[test]
+    fn inner_declarations_shadow_outer_ones() {
+        let symbols =
+            check("int main(void) { int a = 1; { int a = 2; { a = 3; } } return a; }").unwrap();
+        let locals = symbols.keys().filter(|name| name.starts_with("a.")).count();
+        assert_eq!(locals, 2);
+    }
+
+    #[test]
+    fn declarations_end_with_their_block() {
+        assert_eq!(
+            error("{ int b = 1; }\nreturn b;"),
+            ("use of undeclared identifier `b`".to_string(), 3)
+        );
+        assert_eq!(
+            message("for (int i = 0; i < 3; i = i + 1) ;\nreturn i;"),
+            "use of undeclared identifier `i`"
+        );
+    }
+
+    #[test]
+    fn redeclaration_in_one_scope() {
+        assert_eq!(
+            error("int a;\n{ int a; }\nint a;"),
+            ("redeclaration of `a`".to_string(), 4)
+        );
+    }
+
+    #[test]
+    fn assignment_needs_a_variable() {
+        assert_eq!(message("int a; 1 = a;"), "expression is not assignable");
+        assert_eq!(
+            message("int a; (a + 1) += 2;"),
+            "expression is not assignable"
+        );
+    }
+
+    #[test]
+    fn label_diagnostics() {
+        assert_eq!(
+            error("a: ;\n{ a: ; }"),
+            ("redefinition of label `a`".to_string(), 3)
+        );
+        assert_eq!(
+            error("goto b;\nreturn 0;"),
+            ("use of undeclared label `b`".to_string(), 2)
+        );
+        // Labels belong to a function, and have their own name space.
+        assert!(check(
+            "int f(void) { goto x; x: return 0; }\nint main(void) { int x; goto x; x: return 0; }"
+        )
+        .is_ok());
+        assert_eq!(
+            check("int f(void) { x: return 0; }\nint main(void) { goto x; }")
+                .unwrap_err()
+                .to_string(),
+            "use of undeclared label `x`"
+        );
+    }
+
+    #[test]
+    fn case_diagnostics() {
+        assert_eq!(
+            error("case 1: ;"),
+            ("`case` label not within a switch statement".to_string(), 2)
+        );
+        assert_eq!(
+            message("while (1) { default: ; }"),
+            "`default` label not within a switch statement"
+        );
+        assert_eq!(
+            error("switch (1) {\ncase 2: ;\ncase 1 + 1: ;\n}"),
+            ("duplicate case value `2`".to_string(), 4)
+        );
+        assert_eq!(
+            message("switch (1) { default: ; case 1: default: ; }"),
+            "multiple `default` labels in one switch"
+        );
+        assert_eq!(
+            message("int a = 1; switch (a) { case a: ; }"),
+            "case label is not an integer constant expression"
+        );
+        // Cases of an inner switch are its own.
+        assert!(
+            check("int main(void) { switch (1) { case 1: switch (2) { case 1: ; } } }").is_ok()
+        );
+    }
+
+    #[test]
+    fn jump_diagnostics() {
+        assert_eq!(
+            message("break;"),
+            "`break` statement not within a loop or switch"
+        );
+        assert_eq!(
+            message("switch (1) { case 1: continue; }"),
+            "`continue` statement not within a loop"
+        );
+        assert!(check("int main(void) { while (1) switch (1) { case 1: continue; } }").is_ok());
+    }
+}
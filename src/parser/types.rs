@@ -0,0 +1,155 @@
+use crate::lexer::literal::IntegerKind;
+use std::collections::HashMap;
+use std::fmt;
+
+/// An integer type, with the sizes of the LP64 data model. Plain `char`
+/// is signed, as on x86-64, but is a distinct type from `signed char`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Char,
+    SignedChar,
+    UnsignedChar,
+    Short,
+    UnsignedShort,
+    Int,
+    UnsignedInt,
+    Long,
+    UnsignedLong,
+    LongLong,
+    UnsignedLongLong,
+}
+
+impl Type {
+    /// Size in bytes, which is also the alignment.
+    pub fn size(&self) -> i64 {
+        match self {
+            Type::Char | Type::SignedChar | Type::UnsignedChar => 1,
+            Type::Short | Type::UnsignedShort => 2,
+            Type::Int | Type::UnsignedInt => 4,
+            Type::Long | Type::UnsignedLong | Type::LongLong | Type::UnsignedLongLong => 8,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(
+            self,
+            Type::Char | Type::SignedChar | Type::Short | Type::Int | Type::Long | Type::LongLong
+        )
+    }
+
+    /// Integer conversion rank: types of the same rank differ only in
+    /// signedness.
+    fn rank(&self) -> u8 {
+        match self {
+            Type::Char | Type::SignedChar | Type::UnsignedChar => 1,
+            Type::Short | Type::UnsignedShort => 2,
+            Type::Int | Type::UnsignedInt => 3,
+            Type::Long | Type::UnsignedLong => 4,
+            Type::LongLong | Type::UnsignedLongLong => 5,
+        }
+    }
+
+    /// Unsigned type of the same rank.
+    fn to_unsigned(self) -> Type {
+        match self {
+            Type::Char | Type::SignedChar => Type::UnsignedChar,
+            Type::Short => Type::UnsignedShort,
+            Type::Int => Type::UnsignedInt,
+            Type::Long => Type::UnsignedLong,
+            Type::LongLong => Type::UnsignedLongLong,
+            _ => self,
+        }
+    }
+
+    /// Integer promotions: every type narrower than `int` fits in `int`.
+    pub fn promote(self) -> Type {
+        match self.rank() {
+            1 | 2 => Type::Int,
+            _ => self,
+        }
+    }
+
+    /// Type both operands of an arithmetic operator are converted to by
+    /// the usual arithmetic conversions.
+    pub fn common(self, other: Type) -> Type {
+        let (a, b) = (self.promote(), other.promote());
+        if a == b {
+            return a;
+        }
+        if a.is_signed() == b.is_signed() {
+            return if a.rank() > b.rank() { a } else { b };
+        }
+        let (signed, unsigned) = if a.is_signed() { (a, b) } else { (b, a) };
+        if unsigned.rank() >= signed.rank() {
+            unsigned
+        } else if signed.size() > unsigned.size() {
+            signed
+        } else {
+            signed.to_unsigned()
+        }
+    }
+
+    /// Converts `value` to this type: keeps the low bytes that fit, then
+    /// sign- or zero-extends them back to 64 bits.
+    pub fn convert(&self, value: i64) -> i64 {
+        let bits = 64 - 8 * self.size() as u32;
+        if self.is_signed() {
+            value.wrapping_shl(bits).wrapping_shr(bits)
+        } else {
+            ((value as u64).wrapping_shl(bits).wrapping_shr(bits)) as i64
+        }
+    }
+}
+
+impl From<IntegerKind> for Type {
+    fn from(kind: IntegerKind) -> Type {
+        match kind {
+            IntegerKind::Int => Type::Int,
+            IntegerKind::UnsignedInt => Type::UnsignedInt,
+            IntegerKind::Long => Type::Long,
+            IntegerKind::UnsignedLong => Type::UnsignedLong,
+            IntegerKind::LongLong => Type::LongLong,
+            IntegerKind::UnsignedLongLong => Type::UnsignedLongLong,
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Char => write!(f, "char"),
+            Type::SignedChar => write!(f, "signed char"),
+            Type::UnsignedChar => write!(f, "unsigned char"),
+            Type::Short => write!(f, "short"),
+            Type::UnsignedShort => write!(f, "unsigned short"),
+            Type::Int => write!(f, "int"),
+            Type::UnsignedInt => write!(f, "unsigned int"),
+            Type::Long => write!(f, "long"),
+            Type::UnsignedLong => write!(f, "unsigned long"),
+            Type::LongLong => write!(f, "long long"),
+            Type::UnsignedLongLong => write!(f, "unsigned long long"),
+        }
+    }
+}
+
+/// An integer constant. `value` is already converted to `ty`, so it
+/// holds the bits of the constant sign- or zero-extended to 64 bits.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Constant {
+    pub value: i64,
+    pub ty: Type,
+}
+
+impl Constant {
+    pub fn new(value: i64, ty: Type) -> Constant {
+        Constant {
+            value: ty.convert(value),
+            ty,
+        }
+    }
+}
+
+/// Type of every variable, under its unique name, and of every
+/// temporary, as well as the return type of every function. Semantic
+/// analysis fills it in, and TACKY generation adds the temporaries.
+pub type Symbols = HashMap<String, Type>;
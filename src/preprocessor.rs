@@ -0,0 +1,5 @@
+pub mod error;
+pub mod expression;
+#[allow(clippy::module_inception)]
+pub mod preprocessor;
+pub mod token;
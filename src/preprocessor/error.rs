@@ -0,0 +1,200 @@
+use crate::diagnostics::diagnostic::Diagnostic;
+use crate::lexer::span::Span;
+use std::fmt;
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    UnterminatedComment {
+        span: Span,
+    },
+    InvalidDirective {
+        name: String,
+        span: Span,
+    },
+    ExpectedMacroName {
+        directive: String,
+        span: Span,
+    },
+    InvalidMacroParameters {
+        span: Span,
+    },
+    /// `#` in a function-like macro not followed by a parameter.
+    StringizeWithoutParameter {
+        span: Span,
+    },
+    /// `##` at the start or end of a replacement list.
+    PasteAtEdge {
+        span: Span,
+    },
+    InvalidPaste {
+        left: String,
+        right: String,
+        span: Span,
+    },
+    UnterminatedArguments {
+        name: String,
+        span: Span,
+    },
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    InvalidInclude {
+        span: Span,
+    },
+    IncludeNotFound {
+        name: String,
+        span: Span,
+    },
+    IncludeTooDeep {
+        span: Span,
+    },
+    Io {
+        path: String,
+        error: std::io::Error,
+        span: Span,
+    },
+    /// `#else`, `#elif` or `#endif` without matching `#if`.
+    UnmatchedConditional {
+        directive: String,
+        span: Span,
+    },
+    /// `#else` or `#elif` after `#else`.
+    ConditionalAfterElse {
+        directive: String,
+        span: Span,
+    },
+    UnterminatedConditional {
+        directive: String,
+        span: Span,
+    },
+    InvalidExpression {
+        message: String,
+        span: Span,
+    },
+    DivisionByZero {
+        span: Span,
+    },
+    InvalidLineDirective {
+        span: Span,
+    },
+    ErrorDirective {
+        message: String,
+        span: Span,
+    },
+}
+
+impl PreprocessError {
+    pub fn span(&self) -> &Span {
+        match self {
+            PreprocessError::UnterminatedComment { span }
+            | PreprocessError::InvalidDirective { span, .. }
+            | PreprocessError::ExpectedMacroName { span, .. }
+            | PreprocessError::InvalidMacroParameters { span }
+            | PreprocessError::StringizeWithoutParameter { span }
+            | PreprocessError::PasteAtEdge { span }
+            | PreprocessError::InvalidPaste { span, .. }
+            | PreprocessError::UnterminatedArguments { span, .. }
+            | PreprocessError::ArgumentCount { span, .. }
+            | PreprocessError::InvalidInclude { span }
+            | PreprocessError::IncludeNotFound { span, .. }
+            | PreprocessError::IncludeTooDeep { span }
+            | PreprocessError::Io { span, .. }
+            | PreprocessError::UnmatchedConditional { span, .. }
+            | PreprocessError::ConditionalAfterElse { span, .. }
+            | PreprocessError::UnterminatedConditional { span, .. }
+            | PreprocessError::InvalidExpression { span, .. }
+            | PreprocessError::DivisionByZero { span }
+            | PreprocessError::InvalidLineDirective { span }
+            | PreprocessError::ErrorDirective { span, .. } => span,
+        }
+    }
+
+    pub fn diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::error(self.to_string(), self.span());
+        match self {
+            PreprocessError::UnterminatedComment { .. } => {
+                diagnostic.with_label("comment starts here")
+            }
+            PreprocessError::IncludeNotFound { .. } => {
+                diagnostic.with_help("add the directory containing it with `-I <dir>`")
+            }
+            PreprocessError::UnterminatedConditional { .. } => {
+                diagnostic.with_label("not closed by `#endif`")
+            }
+            _ => diagnostic,
+        }
+    }
+}
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreprocessError::UnterminatedComment { .. } => write!(f, "unterminated comment"),
+            PreprocessError::InvalidDirective { name, .. } => {
+                write!(f, "invalid preprocessing directive `#{name}`")
+            }
+            PreprocessError::ExpectedMacroName { directive, .. } => {
+                write!(f, "macro name missing in `#{directive}`")
+            }
+            PreprocessError::InvalidMacroParameters { .. } => {
+                write!(f, "invalid macro parameter list")
+            }
+            PreprocessError::StringizeWithoutParameter { .. } => {
+                write!(f, "`#` is not followed by a macro parameter")
+            }
+            PreprocessError::PasteAtEdge { .. } => {
+                write!(f, "`##` cannot appear at either end of a macro expansion")
+            }
+            PreprocessError::InvalidPaste { left, right, .. } => write!(
+                f,
+                "pasting `{left}` and `{right}` does not give a valid preprocessing token"
+            ),
+            PreprocessError::UnterminatedArguments { name, .. } => {
+                write!(f, "unterminated argument list invoking macro `{name}`")
+            }
+            PreprocessError::ArgumentCount {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "macro `{name}` requires {expected} argument{plural}, but {found} given"
+                )
+            }
+            PreprocessError::InvalidInclude { .. } => {
+                write!(f, "`#include` expects \"FILENAME\" or <FILENAME>")
+            }
+            PreprocessError::IncludeNotFound { name, .. } => write!(f, "`{name}` file not found"),
+            PreprocessError::IncludeTooDeep { .. } => write!(f, "`#include` nested too deeply"),
+            PreprocessError::Io { path, error, .. } => write!(f, "`{path}`: {error}"),
+            PreprocessError::UnmatchedConditional { directive, .. } => {
+                write!(f, "`#{directive}` without `#if`")
+            }
+            PreprocessError::ConditionalAfterElse { directive, .. } => {
+                write!(f, "`#{directive}` after `#else`")
+            }
+            PreprocessError::UnterminatedConditional { directive, .. } => {
+                write!(f, "unterminated `#{directive}`")
+            }
+            PreprocessError::InvalidExpression { message, .. } => {
+                write!(f, "invalid preprocessor expression: {message}")
+            }
+            PreprocessError::DivisionByZero { .. } => {
+                write!(f, "division by zero in preprocessor expression")
+            }
+            PreprocessError::InvalidLineDirective { .. } => {
+                write!(
+                    f,
+                    "`#line` expects a positive line number and optional file name"
+                )
+            }
+            PreprocessError::ErrorDirective { message, .. } => write!(f, "#error {message}"),
+        }
+    }
+}
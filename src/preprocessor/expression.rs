@@ -0,0 +1,281 @@
+use super::error::PreprocessError;
+use super::token::{PpKind, PpToken};
+use crate::lexer::literal::{self, Literal};
+use crate::lexer::span::Span;
+
+/// Value of a preprocessor expression, which has type `intmax_t` or
+/// `uintmax_t` (C17 6.10.1p4).
+#[derive(Clone, Copy)]
+struct Value {
+    bits: u64,
+    unsigned: bool,
+}
+
+impl Value {
+    fn signed(value: i64) -> Value {
+        Value {
+            bits: value as u64,
+            unsigned: false,
+        }
+    }
+
+    fn boolean(value: bool) -> Value {
+        Value::signed(value as i64)
+    }
+
+    fn is_true(&self) -> bool {
+        self.bits != 0
+    }
+}
+
+fn precedence(operator: &str) -> Option<u8> {
+    match operator {
+        "*" | "/" | "%" => Some(10),
+        "+" | "-" => Some(9),
+        "<<" | ">>" => Some(8),
+        "<" | ">" | "<=" | ">=" => Some(7),
+        "==" | "!=" => Some(6),
+        "&" => Some(5),
+        "^" => Some(4),
+        "|" => Some(3),
+        "&&" => Some(2),
+        "||" => Some(1),
+        _ => None,
+    }
+}
+
+struct Evaluator<'a> {
+    tokens: &'a [PpToken],
+    position: usize,
+    /// Location of the directive, used when the expression ends early.
+    span: &'a Span,
+}
+
+impl<'a> Evaluator<'a> {
+    fn peek(&self) -> Option<&'a PpToken> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_operator(&self) -> Option<&'a str> {
+        self.peek()
+            .filter(|token| token.kind == PpKind::Punctuator)
+            .map(|token| &*token.text)
+    }
+
+    fn error(&self, message: String) -> PreprocessError {
+        let span = self.peek().map_or(self.span, |token| &token.span);
+        PreprocessError::InvalidExpression {
+            message,
+            span: span.clone(),
+        }
+    }
+
+    fn expect(&mut self, operator: &str) -> Result<(), PreprocessError> {
+        if self.peek_operator() != Some(operator) {
+            let found = self.peek().map_or("end of line".to_string(), |token| {
+                format!("`{}`", token.text)
+            });
+            return Err(self.error(format!("expected `{operator}`, found {found}")));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn primary(&mut self, evaluate: bool) -> Result<Value, PreprocessError> {
+        let Some(token) = self.peek() else {
+            return Err(self.error("expected value, found end of line".to_string()));
+        };
+        self.position += 1;
+        let invalid = |message: String| PreprocessError::InvalidExpression {
+            message,
+            span: token.span.clone(),
+        };
+        match token.kind {
+            PpKind::Number if literal::is_floating(&token.text) => Err(invalid(
+                "floating constant in preprocessor expression".to_string(),
+            )),
+            PpKind::Number => match literal::integer(&token.text) {
+                Ok(Literal::Integer { value, kind }) => Ok(Value {
+                    bits: value,
+                    unsigned: kind.is_unsigned(),
+                }),
+                Ok(_) => unreachable!(),
+                Err(error) => Err(invalid(error.to_string())),
+            },
+            PpKind::CharLiteral => {
+                let quote = token.text.find('\'').unwrap();
+                let encoding = literal::Encoding::from_prefix(&token.text[..quote]).unwrap();
+                let body = &token.text[quote + 1..token.text.len() - 1];
+                match literal::character(body, encoding) {
+                    Ok(Literal::Character { value, .. }) => Ok(Value::signed(value)),
+                    Ok(_) => unreachable!(),
+                    Err(error) => Err(invalid(error.to_string())),
+                }
+            }
+            // Identifiers left after macro expansion evaluate to 0.
+            PpKind::Identifier => Ok(Value::signed(0)),
+            PpKind::Punctuator if &*token.text == "(" => {
+                let value = self.comma(evaluate)?;
+                self.expect(")")?;
+                Ok(value)
+            }
+            _ => Err(invalid(format!(
+                "token `{}` is not valid in preprocessor expressions",
+                token.text
+            ))),
+        }
+    }
+
+    fn unary(&mut self, evaluate: bool) -> Result<Value, PreprocessError> {
+        let operator = match self.peek_operator() {
+            Some(operator @ ("+" | "-" | "~" | "!")) => operator,
+            _ => return self.primary(evaluate),
+        };
+        self.position += 1;
+        let value = self.unary(evaluate)?;
+        Ok(match operator {
+            "+" => value,
+            "-" => Value {
+                bits: value.bits.wrapping_neg(),
+                ..value
+            },
+            "~" => Value {
+                bits: !value.bits,
+                ..value
+            },
+            _ => Value::boolean(!value.is_true()),
+        })
+    }
+
+    fn apply(
+        &self,
+        operator: &PpToken,
+        left: Value,
+        right: Value,
+        evaluate: bool,
+    ) -> Result<Value, PreprocessError> {
+        let unsigned = left.unsigned || right.unsigned;
+        let (a, b) = (left.bits, right.bits);
+        let (sa, sb) = (a as i64, b as i64);
+        let arithmetic = |bits: u64| Value { bits, unsigned };
+        let less = if unsigned { a < b } else { sa < sb };
+        let equal = a == b;
+        let value = match &*operator.text {
+            "*" => arithmetic(a.wrapping_mul(b)),
+            "/" | "%" if b == 0 => {
+                if evaluate {
+                    return Err(PreprocessError::DivisionByZero {
+                        span: operator.span.clone(),
+                    });
+                }
+                arithmetic(0)
+            }
+            "/" if unsigned => arithmetic(a / b),
+            "/" => arithmetic(sa.wrapping_div(sb) as u64),
+            "%" if unsigned => arithmetic(a % b),
+            "%" => arithmetic(sa.wrapping_rem(sb) as u64),
+            "+" => arithmetic(a.wrapping_add(b)),
+            "-" => arithmetic(a.wrapping_sub(b)),
+            "<<" => Value {
+                bits: a.checked_shl(b as u32).filter(|_| b < 64).unwrap_or(0),
+                ..left
+            },
+            ">>" if left.unsigned => Value {
+                bits: a.checked_shr(b as u32).filter(|_| b < 64).unwrap_or(0),
+                ..left
+            },
+            ">>" => Value {
+                bits: (sa >> b.min(63)) as u64,
+                ..left
+            },
+            "<" => Value::boolean(less),
+            ">" => Value::boolean(!less && !equal),
+            "<=" => Value::boolean(less || equal),
+            ">=" => Value::boolean(!less),
+            "==" => Value::boolean(equal),
+            "!=" => Value::boolean(!equal),
+            "&" => arithmetic(a & b),
+            "^" => arithmetic(a ^ b),
+            "|" => arithmetic(a | b),
+            "&&" => Value::boolean(left.is_true() && right.is_true()),
+            "||" => Value::boolean(left.is_true() || right.is_true()),
+            _ => unreachable!(),
+        };
+        Ok(value)
+    }
+
+    /// Precedence climbing over the binary operators.
+    fn binary(&mut self, min_precedence: u8, evaluate: bool) -> Result<Value, PreprocessError> {
+        let mut left = self.unary(evaluate)?;
+        while let Some(operator) = self.peek() {
+            let Some(precedence) = self.peek_operator().and_then(precedence) else {
+                break;
+            };
+            if precedence < min_precedence {
+                break;
+            }
+            self.position += 1;
+            // The right operand of `&&` and `||` is only evaluated if it
+            // decides the result.
+            let evaluate_right = match &*operator.text {
+                "&&" => evaluate && left.is_true(),
+                "||" => evaluate && !left.is_true(),
+                _ => evaluate,
+            };
+            let right = self.binary(precedence + 1, evaluate_right)?;
+            left = self.apply(operator, left, right, evaluate)?;
+        }
+        Ok(left)
+    }
+
+    fn conditional(&mut self, evaluate: bool) -> Result<Value, PreprocessError> {
+        let condition = self.binary(1, evaluate)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.position += 1;
+        let then = self.comma(evaluate && condition.is_true())?;
+        self.expect(":")?;
+        let otherwise = self.conditional(evaluate && !condition.is_true())?;
+        let unsigned = then.unsigned || otherwise.unsigned;
+        let bits = if condition.is_true() {
+            then.bits
+        } else {
+            otherwise.bits
+        };
+        Ok(Value { bits, unsigned })
+    }
+
+    fn comma(&mut self, evaluate: bool) -> Result<Value, PreprocessError> {
+        let mut value = self.conditional(evaluate)?;
+        while self.peek_operator() == Some(",") {
+            self.position += 1;
+            value = self.conditional(evaluate)?;
+        }
+        Ok(value)
+    }
+}
+
+/// Evaluates the controlling expression of `#if` or `#elif`, after macro
+/// expansion and replacement of `defined`.
+pub fn evaluate(tokens: &[PpToken], directive: &PpToken) -> Result<bool, PreprocessError> {
+    if tokens.is_empty() {
+        return Err(PreprocessError::InvalidExpression {
+            message: format!("`#{}` with no expression", directive.text),
+            span: directive.span.clone(),
+        });
+    }
+    let mut evaluator = Evaluator {
+        tokens,
+        position: 0,
+        span: &directive.span,
+    };
+    let value = evaluator.comma(true)?;
+    if let Some(token) = evaluator.peek() {
+        return Err(PreprocessError::InvalidExpression {
+            message: format!("unexpected `{}`", token.text),
+            span: token.span.clone(),
+        });
+    }
+    Ok(value.is_true())
+}
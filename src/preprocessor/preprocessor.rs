@@ -0,0 +1,1189 @@
+use super::error::PreprocessError;
+use super::expression;
+use super::token::{self, HideSet, PpKind, PpToken};
+use crate::diagnostics::diagnostic::Diagnostic;
+use crate::lexer::span::Span;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Includes nested deeper than this are assumed to be recursive.
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+const BUILTIN_FILE: &str = "<built-in>";
+
+/// Macros every translation unit starts with, describing the x86-64 Linux
+/// target. `__DATE__` and `__TIME__` are added when preprocessing starts.
+const PREDEFINED: &str = "\
+#define __STDC__ 1
+#define __STDC_VERSION__ 201710L
+#define __STDC_HOSTED__ 1
+#define __x86_64__ 1
+#define __x86_64 1
+#define __linux__ 1
+#define __linux 1
+#define __unix__ 1
+#define __unix 1
+#define __ELF__ 1
+#define __LP64__ 1
+#define _LP64 1
+#define __CHAR_BIT__ 8
+#define __SIZEOF_SHORT__ 2
+#define __SIZEOF_INT__ 4
+#define __SIZEOF_LONG__ 8
+#define __SIZEOF_LONG_LONG__ 8
+#define __SIZEOF_POINTER__ 8
+#define __SIZEOF_SIZE_T__ 8
+#define __SIZEOF_WCHAR_T__ 4
+#define __SIZEOF_FLOAT__ 4
+#define __SIZEOF_DOUBLE__ 8
+#define __SIZEOF_LONG_DOUBLE__ 16
+#define __ORDER_LITTLE_ENDIAN__ 1234
+#define __ORDER_BIG_ENDIAN__ 4321
+#define __BYTE_ORDER__ __ORDER_LITTLE_ENDIAN__
+#define __SCHAR_MAX__ 0x7f
+#define __SHRT_MAX__ 0x7fff
+#define __INT_MAX__ 0x7fffffff
+#define __LONG_MAX__ 0x7fffffffffffffffL
+#define __LONG_LONG_MAX__ 0x7fffffffffffffffLL
+#define __WCHAR_MAX__ 0x7fffffff
+#define __WCHAR_MIN__ (-__WCHAR_MAX__ - 1)
+#define __SIZE_MAX__ 0xffffffffffffffffUL
+#define __PTRDIFF_MAX__ 0x7fffffffffffffffL
+#define __INTMAX_MAX__ 0x7fffffffffffffffL
+#define __UINTMAX_MAX__ 0xffffffffffffffffUL
+#define __SIZE_TYPE__ long unsigned int
+#define __PTRDIFF_TYPE__ long int
+#define __WCHAR_TYPE__ int
+#define __WINT_TYPE__ unsigned int
+";
+
+/// Directories searched after the `-I` ones, in the order gcc uses.
+const SYSTEM_INCLUDE_PATHS: [&str; 3] = [
+    "/usr/local/include",
+    "/usr/include/x86_64-linux-gnu",
+    "/usr/include",
+];
+
+#[derive(Clone, PartialEq, Debug)]
+enum MacroKind {
+    Object,
+    /// Function-like macro. A variadic macro has `__VA_ARGS__` as its last
+    /// parameter.
+    Function {
+        parameters: Vec<Rc<str>>,
+        variadic: bool,
+    },
+    File,
+    Line,
+    Counter,
+}
+
+#[derive(Debug)]
+struct Macro {
+    kind: MacroKind,
+    replacement: Vec<PpToken>,
+    span: Span,
+}
+
+impl Macro {
+    fn same_definition(&self, other: &Macro) -> bool {
+        self.kind == other.kind
+            && self.replacement.len() == other.replacement.len()
+            && self
+                .replacement
+                .iter()
+                .zip(&other.replacement)
+                .all(|(a, b)| a.text == b.text && a.leading_space == b.leading_space)
+    }
+}
+
+/// A file being preprocessed.
+struct File {
+    path: Rc<str>,
+    /// Name reported by `__FILE__`, spelled as a string literal.
+    presumed: Rc<str>,
+    /// Difference between the line reported by `__LINE__` and the physical
+    /// line, changed by `#line`.
+    line_delta: i64,
+    /// Index in the search path where the file was found, which is where
+    /// `#include_next` continues from.
+    search_index: Option<usize>,
+}
+
+/// An open `#if` group.
+struct Conditional {
+    directive: PpToken,
+    /// Whether the enclosing group is being processed at all.
+    parent_active: bool,
+    /// Whether one of the branches so far was selected.
+    taken: bool,
+    active: bool,
+    seen_else: bool,
+}
+
+pub struct Preprocessor {
+    macros: HashMap<Rc<str>, Rc<Macro>>,
+    search_paths: Vec<PathBuf>,
+    files: Vec<File>,
+    conditionals: Vec<Conditional>,
+    /// Canonical paths of files containing `#pragma once`.
+    once: HashSet<PathBuf>,
+    counter: u64,
+    output: String,
+    /// Output column, and the source line the current output line started
+    /// with, used to keep tokens at their original columns.
+    column: u32,
+    origin: Option<(Rc<str>, u32)>,
+    last: Option<char>,
+    pub warnings: Vec<Diagnostic>,
+}
+
+fn escape(text: &str, literal: &mut String) {
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            literal.push('\\');
+        }
+        literal.push(c);
+    }
+}
+
+fn string_literal(text: &str) -> String {
+    let mut literal = String::from("\"");
+    escape(text, &mut literal);
+    literal.push('"');
+    literal
+}
+
+/// The `#` operator: spells `tokens` as a string literal, escaping `"` and
+/// `\` only inside string and character literals (C17 6.10.3.2p2).
+fn stringize(tokens: &[PpToken]) -> String {
+    let mut literal = String::from("\"");
+    for (index, token) in tokens.iter().enumerate() {
+        if index > 0 && token.leading_space {
+            literal.push(' ');
+        }
+        match token.kind {
+            PpKind::StringLiteral | PpKind::CharLiteral => escape(&token.text, &mut literal),
+            _ => literal.push_str(&token.text),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn union(hide_set: &HideSet, other: &HideSet) -> HideSet {
+    if hide_set.is_empty() {
+        return other.clone();
+    }
+    let mut union = (**hide_set).clone();
+    union.extend(other.iter().cloned());
+    Rc::new(union)
+}
+
+/// Whether printing `b` right after `a` would make them lex differently.
+fn needs_space(a: char, b: char) -> bool {
+    let word = |c: char| c.is_alphanumeric() || c == '_' || c == '$' || c == '.';
+    let separator = |c: char| "()[]{};,\"'".contains(c);
+    (word(a) && (word(b) || b == '"' || b == '\''))
+        || ("eEpP".contains(a) && (b == '+' || b == '-'))
+        || (a.is_ascii_punctuation() && b.is_ascii_punctuation() && !separator(a) && !separator(b))
+}
+
+/// `__DATE__` and `__TIME__` for the given number of seconds since the
+/// Unix epoch, in UTC.
+fn date_and_time(seconds: u64) -> (String, String) {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (
+        format!("\"{} {:2} {}\"", MONTHS[month as usize - 1], day, year),
+        format!(
+            "\"{:02}:{:02}:{:02}\"",
+            time / 3600,
+            time / 60 % 60,
+            time % 60
+        ),
+    )
+}
+
+/// gcc's own header directory (for `stddef.h`, `stdarg.h`, ...), using
+/// the newest installed version.
+fn compiler_include_path() -> Option<PathBuf> {
+    let root = Path::new("/usr/lib/gcc/x86_64-linux-gnu");
+    let mut versions: Vec<(Vec<u32>, PathBuf)> = fs::read_dir(root)
+        .ok()?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let name = entry.file_name().into_string().ok()?;
+            let version = name
+                .split('.')
+                .map(|part| part.parse().ok())
+                .collect::<Option<_>>()?;
+            Some((version, entry.path().join("include")))
+        })
+        .filter(|(_, path)| path.is_dir())
+        .collect();
+    versions.sort();
+    versions.pop().map(|(_, path)| path)
+}
+
+impl Preprocessor {
+    pub fn new(include_paths: &[String]) -> Preprocessor {
+        let mut search_paths: Vec<PathBuf> = include_paths.iter().map(PathBuf::from).collect();
+        search_paths.extend(compiler_include_path());
+        search_paths.extend(SYSTEM_INCLUDE_PATHS.iter().map(PathBuf::from));
+        let mut preprocessor = Preprocessor {
+            macros: HashMap::new(),
+            search_paths,
+            files: Vec::new(),
+            conditionals: Vec::new(),
+            once: HashSet::new(),
+            counter: 0,
+            output: String::new(),
+            column: 1,
+            origin: None,
+            last: None,
+            warnings: Vec::new(),
+        };
+        let file: Rc<str> = Rc::from(BUILTIN_FILE);
+        let span = Span::new(file, 0, 0, 0, 0);
+        for (name, kind) in [
+            ("__FILE__", MacroKind::File),
+            ("__LINE__", MacroKind::Line),
+            ("__COUNTER__", MacroKind::Counter),
+        ] {
+            let definition = Macro {
+                kind,
+                replacement: Vec::new(),
+                span: span.clone(),
+            };
+            preprocessor
+                .macros
+                .insert(Rc::from(name), Rc::new(definition));
+        }
+        preprocessor
+    }
+
+    fn file(&self) -> &File {
+        self.files.last().unwrap()
+    }
+
+    fn active(&self) -> bool {
+        self.conditionals.last().is_none_or(|group| group.active)
+    }
+
+    /// Preprocesses a translation unit, returning the resulting text.
+    pub fn run(&mut self, source: &str, path: &str) -> Result<String, PreprocessError> {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        let (date, time) = date_and_time(seconds);
+        let predefined = format!("{PREDEFINED}#define __DATE__ {date}\n#define __TIME__ {time}\n");
+        self.process(&predefined, Rc::from(BUILTIN_FILE), None, 0)?;
+        self.output.clear();
+        self.process(source, Rc::from(path), None, 0)?;
+        Ok(std::mem::take(&mut self.output))
+    }
+
+    fn process(
+        &mut self,
+        source: &str,
+        path: Rc<str>,
+        search_index: Option<usize>,
+        depth: usize,
+    ) -> Result<(), PreprocessError> {
+        let tokens = token::tokenize(source, &path)?;
+        self.files.push(File {
+            presumed: Rc::from(string_literal(&path)),
+            path,
+            line_delta: 0,
+            search_index,
+        });
+        self.marker(1, if depth > 0 { " 1" } else { "" });
+        let open = self.conditionals.len();
+        let mut pending = VecDeque::new();
+        for line in tokens.split_inclusive(|token| token.kind == PpKind::Newline) {
+            let (newline, line) = line.split_last().unwrap();
+            match line.first() {
+                Some(hash) if hash.kind == PpKind::Punctuator && hash.is("#") => {
+                    self.flush(&mut pending)?;
+                    self.emit(vec![newline.clone()]);
+                    self.directive(hash, &line[1..], depth)?;
+                }
+                _ if self.active() => {
+                    pending.extend(line.iter().cloned());
+                    pending.push_back(newline.clone());
+                }
+                _ => pending.push_back(newline.clone()),
+            }
+        }
+        self.flush(&mut pending)?;
+        if self.conditionals.len() > open {
+            let group = self.conditionals.pop().unwrap();
+            return Err(PreprocessError::UnterminatedConditional {
+                directive: group.directive.text.to_string(),
+                span: group.directive.span,
+            });
+        }
+        self.files.pop();
+        Ok(())
+    }
+
+    /// Prints a line marker `# 12 "file.h"` saying where the next output
+    /// line comes from, so that the lexer can report original locations.
+    /// The flag is ` 1` when entering an included file and ` 2` when
+    /// returning from one, as in gcc's output.
+    fn marker(&mut self, line: i64, flag: &str) {
+        let presumed = self.file().presumed.clone();
+        self.output += &format!("# {line} {presumed}{flag}\n");
+    }
+
+    /// Expands and prints the text lines collected since the last
+    /// directive.
+    fn flush(&mut self, pending: &mut VecDeque<PpToken>) -> Result<(), PreprocessError> {
+        let tokens = self.expand(std::mem::take(pending))?;
+        self.emit(tokens);
+        Ok(())
+    }
+
+    fn emit(&mut self, tokens: Vec<PpToken>) {
+        for token in tokens {
+            match token.kind {
+                PpKind::Newline => {
+                    self.output.push('\n');
+                    self.column = 1;
+                    self.origin = None;
+                    self.last = None;
+                    continue;
+                }
+                PpKind::Placemarker => continue,
+                _ => {}
+            }
+            let origin = (token.span.file.clone(), token.span.line);
+            let first = token.text.chars().next().unwrap();
+            let start = self.column;
+            // Tokens produced by macros cannot keep their columns.
+            let placed = token.hide_set.is_empty();
+            if placed && (self.origin.is_none() || self.origin.as_ref() == Some(&origin)) {
+                self.origin.get_or_insert(origin);
+                while self.column < token.span.column {
+                    self.output.push(' ');
+                    self.column += 1;
+                }
+            }
+            let separate = self
+                .last
+                .is_some_and(|last| token.leading_space || needs_space(last, first));
+            if self.column == start && separate {
+                self.output.push(' ');
+                self.column += 1;
+            }
+            self.output.push_str(&token.text);
+            self.column += token.text.chars().count() as u32;
+            self.last = token.text.chars().last();
+        }
+    }
+
+    fn directive(
+        &mut self,
+        hash: &PpToken,
+        line: &[PpToken],
+        depth: usize,
+    ) -> Result<(), PreprocessError> {
+        // A lone `#` is the null directive.
+        let Some(name) = line.first() else {
+            return Ok(());
+        };
+        let rest = &line[1..];
+        let active = self.active();
+        match &*name.text {
+            "if" | "ifdef" | "ifndef" => {
+                let value = active
+                    && match &*name.text {
+                        "if" => self.condition(name, rest)?,
+                        "ifdef" => self.is_defined(name, rest)?,
+                        _ => !self.is_defined(name, rest)?,
+                    };
+                self.conditionals.push(Conditional {
+                    directive: name.clone(),
+                    parent_active: active,
+                    taken: value,
+                    active: value,
+                    seen_else: false,
+                });
+            }
+            "elif" | "else" | "endif" => {
+                let Some(group) = self.conditionals.last() else {
+                    return Err(PreprocessError::UnmatchedConditional {
+                        directive: name.text.to_string(),
+                        span: name.span.clone(),
+                    });
+                };
+                if group.seen_else && &*name.text != "endif" {
+                    return Err(PreprocessError::ConditionalAfterElse {
+                        directive: name.text.to_string(),
+                        span: name.span.clone(),
+                    });
+                }
+                let (parent_active, taken) = (group.parent_active, group.taken);
+                let value = match &*name.text {
+                    "endif" => {
+                        self.conditionals.pop();
+                        return Ok(());
+                    }
+                    "elif" => parent_active && !taken && self.condition(name, rest)?,
+                    _ => parent_active && !taken,
+                };
+                let group = self.conditionals.last_mut().unwrap();
+                group.active = value;
+                group.taken |= value;
+                group.seen_else = &*name.text == "else";
+            }
+            _ if !active => {}
+            "include" | "include_next" => self.include(name, rest, depth)?,
+            "define" => self.define(name, rest)?,
+            "undef" => {
+                let macro_name = self.macro_name(name, rest)?;
+                self.macros.remove(&macro_name.text);
+            }
+            "line" => self.line(name, rest)?,
+            // GNU line marker, `# 12 "file.c"`.
+            _ if name.kind == PpKind::Number => self.line(hash, line)?,
+            "error" => {
+                return Err(PreprocessError::ErrorDirective {
+                    message: Self::spell(rest),
+                    span: name.span.clone(),
+                })
+            }
+            "warning" => self.warnings.push(Diagnostic::warning(
+                format!("#warning {}", Self::spell(rest)),
+                &name.span,
+            )),
+            "pragma" => {
+                if rest.first().is_some_and(|token| token.is("once")) {
+                    if let Ok(path) = fs::canonicalize(&*self.file().path) {
+                        self.once.insert(path);
+                    }
+                }
+            }
+            "ident" | "sccs" => {}
+            _ => {
+                return Err(PreprocessError::InvalidDirective {
+                    name: name.text.to_string(),
+                    span: name.span.clone(),
+                })
+            }
+        }
+        Ok(())
+    }
+
+    /// Spells tokens the way they appeared, for messages and `<...>`
+    /// header names.
+    fn spell(tokens: &[PpToken]) -> String {
+        let mut text = String::new();
+        for (index, token) in tokens.iter().enumerate() {
+            if index > 0 && token.leading_space {
+                text.push(' ');
+            }
+            text.push_str(&token.text);
+        }
+        text
+    }
+
+    fn macro_name<'t>(
+        &self,
+        directive: &PpToken,
+        rest: &'t [PpToken],
+    ) -> Result<&'t PpToken, PreprocessError> {
+        rest.first()
+            .filter(|token| token.kind == PpKind::Identifier && &*token.text != "defined")
+            .ok_or_else(|| PreprocessError::ExpectedMacroName {
+                directive: directive.text.to_string(),
+                span: rest.first().unwrap_or(directive).span.clone(),
+            })
+    }
+
+    fn is_defined(&self, directive: &PpToken, rest: &[PpToken]) -> Result<bool, PreprocessError> {
+        let name = self.macro_name(directive, rest)?;
+        Ok(self.macros.contains_key(&name.text))
+    }
+
+    fn define(&mut self, directive: &PpToken, rest: &[PpToken]) -> Result<(), PreprocessError> {
+        let name = self.macro_name(directive, rest)?;
+        let mut body = &rest[1..];
+        let mut kind = MacroKind::Object;
+        if body
+            .first()
+            .is_some_and(|token| token.is("(") && !token.leading_space)
+        {
+            let invalid = |token: &PpToken| PreprocessError::InvalidMacroParameters {
+                span: token.span.clone(),
+            };
+            let mut parameters: Vec<Rc<str>> = Vec::new();
+            let mut variadic = false;
+            let mut index = 1;
+            loop {
+                let token = body.get(index).ok_or_else(|| invalid(&body[index - 1]))?;
+                index += 1;
+                if token.is(")") && (parameters.is_empty() || variadic) {
+                    break;
+                }
+                if variadic {
+                    return Err(invalid(token));
+                }
+                if token.is("...") {
+                    variadic = true;
+                    parameters.push(Rc::from("__VA_ARGS__"));
+                    continue;
+                }
+                if token.kind != PpKind::Identifier || parameters.contains(&token.text) {
+                    return Err(invalid(token));
+                }
+                parameters.push(token.text.clone());
+                let separator = body.get(index).ok_or_else(|| invalid(token))?;
+                index += 1;
+                if separator.is(")") {
+                    break;
+                }
+                if !separator.is(",") {
+                    return Err(invalid(separator));
+                }
+            }
+            body = &body[index..];
+            for (index, token) in body.iter().enumerate() {
+                let parameter = body
+                    .get(index + 1)
+                    .is_some_and(|next| parameters.contains(&next.text));
+                if token.is("#") && !parameter {
+                    return Err(PreprocessError::StringizeWithoutParameter {
+                        span: token.span.clone(),
+                    });
+                }
+            }
+            kind = MacroKind::Function {
+                parameters,
+                variadic,
+            };
+        }
+        if let Some(token) = [body.first(), body.last()]
+            .into_iter()
+            .flatten()
+            .find(|token| token.is("##"))
+        {
+            return Err(PreprocessError::PasteAtEdge {
+                span: token.span.clone(),
+            });
+        }
+        let mut replacement = body.to_vec();
+        if let Some(first) = replacement.first_mut() {
+            first.leading_space = false;
+        }
+        let definition = Macro {
+            kind,
+            replacement,
+            span: name.span.clone(),
+        };
+        if let Some(previous) = self.macros.get(&name.text) {
+            if !previous.same_definition(&definition) {
+                let mut warning =
+                    Diagnostic::warning(format!("`{}` redefined", name.text), &name.span);
+                if &*previous.span.file != BUILTIN_FILE {
+                    warning =
+                        warning.with_note(format!("previous definition is at {}", previous.span));
+                }
+                self.warnings.push(warning);
+            }
+        }
+        self.macros.insert(name.text.clone(), Rc::new(definition));
+        Ok(())
+    }
+
+    /// Evaluates the expression of `#if` or `#elif`.
+    fn condition(
+        &mut self,
+        directive: &PpToken,
+        rest: &[PpToken],
+    ) -> Result<bool, PreprocessError> {
+        let mut tokens = VecDeque::new();
+        let mut index = 0;
+        while let Some(token) = rest.get(index) {
+            index += 1;
+            if !token.is("defined") && !token.is("__has_include") {
+                tokens.push_back(token.clone());
+                continue;
+            }
+            let invalid = |message: &str| PreprocessError::InvalidExpression {
+                message: format!("`{}` {message}", token.text),
+                span: token.span.clone(),
+            };
+            let parenthesized = rest.get(index).is_some_and(|next| next.is("("));
+            if parenthesized {
+                index += 1;
+            }
+            let value = if token.is("defined") {
+                let name = rest
+                    .get(index)
+                    .filter(|name| name.kind == PpKind::Identifier)
+                    .ok_or_else(|| invalid("requires an identifier"))?;
+                index += 1;
+                self.macros.contains_key(&name.text)
+            } else {
+                let end = rest[index..]
+                    .iter()
+                    .position(|token| token.is(")"))
+                    .filter(|_| parenthesized)
+                    .ok_or_else(|| invalid("requires a header name in parentheses"))?;
+                let header = self.header_name(token, &rest[index..index + end])?;
+                index += end;
+                self.resolve(&header.0, header.1, false).is_some()
+            };
+            if parenthesized {
+                if !rest.get(index).is_some_and(|token| token.is(")")) {
+                    return Err(invalid("is missing `)`"));
+                }
+                index += 1;
+            }
+            tokens.push_back(token.with_text(PpKind::Number, if value { "1" } else { "0" }));
+        }
+        let tokens = self.expand(tokens)?;
+        expression::evaluate(&tokens, directive)
+    }
+
+    /// The file name of `#include`, and whether it was quoted rather than
+    /// in angle brackets.
+    fn header_name(
+        &mut self,
+        directive: &PpToken,
+        rest: &[PpToken],
+    ) -> Result<(String, bool), PreprocessError> {
+        let direct = rest.first().is_some_and(|token| {
+            token.is("<") || (token.kind == PpKind::StringLiteral && token.text.starts_with('"'))
+        });
+        let tokens = if direct {
+            rest.to_vec()
+        } else {
+            // A computed include: macro-expand the line first.
+            self.expand(rest.iter().cloned().collect())?
+        };
+        let invalid = || PreprocessError::InvalidInclude {
+            span: rest.first().unwrap_or(directive).span.clone(),
+        };
+        match tokens.first() {
+            Some(token) if token.kind == PpKind::StringLiteral && token.text.starts_with('"') => {
+                Ok((token.text[1..token.text.len() - 1].to_string(), true))
+            }
+            Some(token) if token.is("<") => {
+                let end = tokens
+                    .iter()
+                    .position(|token| token.is(">"))
+                    .ok_or_else(invalid)?;
+                let mut name = Self::spell(&tokens[1..end]);
+                if tokens.get(1).is_some_and(|token| token.leading_space) {
+                    name.insert(0, ' ');
+                }
+                Ok((name, false))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Finds an included file, returning its path and where in the search
+    /// path it was found.
+    fn resolve(&self, name: &str, quoted: bool, next: bool) -> Option<(PathBuf, Option<usize>)> {
+        if Path::new(name).is_absolute() {
+            return Path::new(name)
+                .is_file()
+                .then(|| (PathBuf::from(name), None));
+        }
+        let file = self.file();
+        if quoted && !next {
+            let directory = Path::new(&*file.path).parent().unwrap_or(Path::new(""));
+            let path = directory.join(name);
+            if path.is_file() {
+                return Some((path, None));
+            }
+        }
+        let start = match file.search_index {
+            Some(index) if next => index + 1,
+            _ => 0,
+        };
+        self.search_paths
+            .iter()
+            .enumerate()
+            .skip(start)
+            .map(|(index, directory)| (directory.join(name), Some(index)))
+            .find(|(path, _)| path.is_file())
+    }
+
+    fn include(
+        &mut self,
+        directive: &PpToken,
+        rest: &[PpToken],
+        depth: usize,
+    ) -> Result<(), PreprocessError> {
+        let (name, quoted) = self.header_name(directive, rest)?;
+        let next = &*directive.text == "include_next";
+        let (path, search_index) =
+            self.resolve(&name, quoted, next)
+                .ok_or_else(|| PreprocessError::IncludeNotFound {
+                    name: name.clone(),
+                    span: rest[0].span.to(&rest[rest.len() - 1].span),
+                })?;
+        if fs::canonicalize(&path).is_ok_and(|path| self.once.contains(&path)) {
+            return Ok(());
+        }
+        if depth >= MAX_INCLUDE_DEPTH {
+            return Err(PreprocessError::IncludeTooDeep {
+                span: directive.span.clone(),
+            });
+        }
+        let path = path.to_string_lossy().into_owned();
+        let source = fs::read(&path).map_err(|error| PreprocessError::Io {
+            path: path.clone(),
+            error,
+            span: directive.span.clone(),
+        })?;
+        let source = String::from_utf8_lossy(&source);
+        self.process(&source, Rc::from(path), search_index, depth + 1)?;
+        let line = directive.span.line as i64 + 1 + self.file().line_delta;
+        self.marker(line, " 2");
+        Ok(())
+    }
+
+    /// `#line 12 "file.c"`, which changes what `__LINE__`, `__FILE__` and
+    /// diagnostics report for the following lines.
+    fn line(&mut self, directive: &PpToken, rest: &[PpToken]) -> Result<(), PreprocessError> {
+        let tokens = self.expand(rest.iter().cloned().collect())?;
+        let invalid = || PreprocessError::InvalidLineDirective {
+            span: rest.first().unwrap_or(directive).span.clone(),
+        };
+        let line: i64 = match tokens.first() {
+            Some(token) if token.kind == PpKind::Number => token
+                .text
+                .parse()
+                .ok()
+                .filter(|line| *line > 0)
+                .ok_or_else(invalid)?,
+            _ => return Err(invalid()),
+        };
+        let presumed = match tokens.get(1) {
+            Some(token) if token.kind == PpKind::StringLiteral && token.text.starts_with('"') => {
+                Some(token.text.clone())
+            }
+            None => None,
+            _ => return Err(invalid()),
+        };
+        // Line markers may carry flags after the file name; `#line` may not.
+        let extra = tokens.iter().skip(2);
+        if extra.clone().any(|token| token.kind != PpKind::Number)
+            || (directive.is("line") && tokens.len() > 2)
+        {
+            return Err(invalid());
+        }
+        let file = self.files.last_mut().unwrap();
+        file.line_delta = line - (directive.span.line as i64 + 1);
+        if let Some(presumed) = presumed {
+            file.presumed = presumed;
+        }
+        self.marker(line, "");
+        Ok(())
+    }
+
+    /// Macro-expands tokens (C17 6.10.3), using hide sets to stop
+    /// recursion.
+    fn expand(&mut self, mut input: VecDeque<PpToken>) -> Result<Vec<PpToken>, PreprocessError> {
+        let mut output = Vec::new();
+        while let Some(token) = input.pop_front() {
+            let definition = match self.macros.get(&token.text) {
+                Some(definition)
+                    if token.kind == PpKind::Identifier
+                        && !token.hide_set.contains(&token.text) =>
+                {
+                    definition.clone()
+                }
+                _ => {
+                    output.push(token);
+                    continue;
+                }
+            };
+            let expansion = match &definition.kind {
+                MacroKind::Object => {
+                    let name = Rc::new(std::iter::once(token.text.clone()).collect());
+                    let hide_set = union(&token.hide_set, &name);
+                    self.substitute(&token, &definition, &[], &hide_set)?
+                }
+                MacroKind::Function { parameters, .. } => {
+                    // The invocation may continue on the following lines.
+                    let newlines = input
+                        .iter()
+                        .take_while(|token| token.kind == PpKind::Newline)
+                        .count();
+                    if !input.get(newlines).is_some_and(|next| next.is("(")) {
+                        output.push(token);
+                        continue;
+                    }
+                    input.drain(..=newlines);
+                    let (arguments, close, skipped) =
+                        self.arguments(&token, &definition, parameters.len(), &mut input)?;
+                    let hide_set = token
+                        .hide_set
+                        .intersection(&close.hide_set)
+                        .cloned()
+                        .chain(std::iter::once(token.text.clone()))
+                        .collect();
+                    let mut expansion =
+                        self.substitute(&token, &definition, &arguments, &Rc::new(hide_set))?;
+                    // Keep the lines after the invocation where they were.
+                    let newline = close.with_text(PpKind::Newline, "\n");
+                    expansion.extend(std::iter::repeat_n(newline, newlines + skipped));
+                    expansion
+                }
+                MacroKind::File => {
+                    let presumed = self.file().presumed.clone();
+                    vec![token.with_text(PpKind::StringLiteral, &presumed)]
+                }
+                MacroKind::Line => {
+                    let line = token.span.line as i64 + self.file().line_delta;
+                    vec![token.with_text(PpKind::Number, &line.to_string())]
+                }
+                MacroKind::Counter => {
+                    self.counter += 1;
+                    let counter = (self.counter - 1).to_string();
+                    vec![token.with_text(PpKind::Number, &counter)]
+                }
+            };
+            for token in expansion.into_iter().rev() {
+                input.push_front(token);
+            }
+        }
+        Ok(output)
+    }
+
+    /// Collects the arguments of a function-like macro invocation up to
+    /// the closing parenthesis. Also returns the parenthesis and the number
+    /// of line breaks inside the invocation.
+    fn arguments(
+        &self,
+        name: &PpToken,
+        definition: &Macro,
+        parameter_count: usize,
+        input: &mut VecDeque<PpToken>,
+    ) -> Result<(Vec<Vec<PpToken>>, PpToken, usize), PreprocessError> {
+        let mut arguments = vec![Vec::new()];
+        let mut depth = 0;
+        let mut newlines = 0;
+        let mut space = false;
+        let close = loop {
+            let Some(mut token) = input.pop_front() else {
+                return Err(PreprocessError::UnterminatedArguments {
+                    name: name.text.to_string(),
+                    span: name.span.clone(),
+                });
+            };
+            if token.kind == PpKind::Newline {
+                newlines += 1;
+                space = true;
+                continue;
+            }
+            token.leading_space |= space;
+            space = false;
+            if token.is(")") && depth == 0 {
+                break token;
+            }
+            if token.is("(") {
+                depth += 1;
+            } else if token.is(")") {
+                depth -= 1;
+            } else if token.is(",") && depth == 0 {
+                arguments.push(Vec::new());
+                continue;
+            }
+            arguments.last_mut().unwrap().push(token);
+        };
+        let variadic = matches!(definition.kind, MacroKind::Function { variadic: true, .. });
+        if parameter_count == 0 && arguments.len() == 1 && arguments[0].is_empty() {
+            arguments.clear();
+        }
+        if variadic && arguments.len() == parameter_count - 1 {
+            // GNU extension: the variable arguments may be left out entirely.
+            arguments.push(Vec::new());
+        }
+        if variadic && arguments.len() > parameter_count {
+            // Everything from the last parameter on is `__VA_ARGS__`.
+            let extra = arguments.split_off(parameter_count);
+            let last = arguments.last_mut().unwrap();
+            for argument in extra {
+                last.push(close.with_text(PpKind::Punctuator, ","));
+                last.extend(argument);
+            }
+        }
+        if arguments.len() != parameter_count {
+            return Err(PreprocessError::ArgumentCount {
+                name: name.text.to_string(),
+                expected: parameter_count,
+                found: arguments.len(),
+                span: name.span.to(&close.span),
+            });
+        }
+        Ok((arguments, close, newlines))
+    }
+
+    /// Replaces the parameters in a macro's replacement list, then applies
+    /// `#` and `##` (C17 6.10.3.1-3).
+    fn substitute(
+        &mut self,
+        name: &PpToken,
+        definition: &Macro,
+        arguments: &[Vec<PpToken>],
+        hide_set: &HideSet,
+    ) -> Result<Vec<PpToken>, PreprocessError> {
+        let (parameters, variadic) = match &definition.kind {
+            MacroKind::Function {
+                parameters,
+                variadic,
+            } => (parameters.as_slice(), *variadic),
+            _ => (&[][..], false),
+        };
+        let parameter = |token: &PpToken| {
+            if token.kind != PpKind::Identifier {
+                return None;
+            }
+            parameters
+                .iter()
+                .position(|parameter| *parameter == token.text)
+        };
+        let placemarker = |token: &PpToken| token.with_text(PpKind::Placemarker, "");
+        let replacement = &definition.replacement;
+        let mut result: Vec<PpToken> = Vec::new();
+        let mut index = 0;
+        while let Some(token) = replacement.get(index) {
+            index += 1;
+            let mut local = token.clone();
+            local.span = name.span.clone();
+            if token.is("#") && !parameters.is_empty() {
+                let argument = &arguments[parameter(&replacement[index]).unwrap()];
+                index += 1;
+                let text = stringize(argument);
+                result.push(local.with_text(PpKind::StringLiteral, &text));
+            } else if token.is("##") {
+                let right = &replacement[index];
+                index += 1;
+                let operand = match parameter(right) {
+                    Some(position) => arguments[position].clone(),
+                    None => {
+                        let mut right = right.clone();
+                        right.span = name.span.clone();
+                        vec![right]
+                    }
+                };
+                let left = result.pop().unwrap_or_else(|| placemarker(&local));
+                let va_args = variadic && parameter(right) == Some(parameters.len() - 1);
+                if va_args && left.is(",") {
+                    // GNU extension: `, ## __VA_ARGS__` drops the comma when
+                    // there are no variable arguments.
+                    if operand.is_empty() {
+                        // Leave a placemarker for a following `##`.
+                        result.push(placemarker(&local));
+                    } else {
+                        result.push(left);
+                        result.extend(operand);
+                    }
+                    continue;
+                }
+                let mut operand = operand.into_iter();
+                match operand.next() {
+                    Some(first) => {
+                        result.push(Self::paste(&left, &first)?);
+                        result.extend(operand);
+                    }
+                    None => result.push(left),
+                }
+            } else if let Some(position) = parameter(token) {
+                let argument = &arguments[position];
+                let start = result.len();
+                if replacement.get(index).is_some_and(|next| next.is("##")) {
+                    if argument.is_empty() {
+                        result.push(placemarker(&local));
+                    }
+                    result.extend(argument.iter().cloned());
+                } else {
+                    let expanded = self.expand(argument.iter().cloned().collect())?;
+                    result.extend(expanded);
+                }
+                if let Some(first) = result.get_mut(start) {
+                    first.leading_space = token.leading_space;
+                }
+            } else {
+                result.push(local);
+            }
+        }
+        let mut result: Vec<PpToken> = result
+            .into_iter()
+            .filter(|token| token.kind != PpKind::Placemarker)
+            .map(|mut token| {
+                token.hide_set = union(&token.hide_set, hide_set);
+                token
+            })
+            .collect();
+        if let Some(first) = result.first_mut() {
+            first.leading_space = name.leading_space;
+        }
+        Ok(result)
+    }
+
+    fn paste(left: &PpToken, right: &PpToken) -> Result<PpToken, PreprocessError> {
+        if left.kind == PpKind::Placemarker {
+            return Ok(right.clone());
+        }
+        if right.kind == PpKind::Placemarker {
+            return Ok(left.clone());
+        }
+        let text = format!("{}{}", left.text, right.text);
+        token::single_token(&text, left).ok_or_else(|| PreprocessError::InvalidPaste {
+            left: left.text.to_string(),
+            right: right.text.to_string(),
+            span: left.span.clone(),
+        })
+    }
+}
+
+/// Runs the preprocessor on the source of `file`, returning the
+/// preprocessed text and any warnings.
+pub fn preprocess(
+    source: &str,
+    file: &str,
+    include_paths: &[String],
+    debug_mode: bool,
+) -> Result<(String, Vec<Diagnostic>), PreprocessError> {
+    let mut preprocessor = Preprocessor::new(include_paths);
+    let output = preprocessor.run(source, file)?;
+    if debug_mode {
+        println!("{output}");
+    }
+    Ok((output, preprocessor.warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The non-empty lines of the output without line markers, with runs of
+    /// whitespace collapsed.
+    fn lines(source: &str) -> Vec<String> {
+        let (output, _) = preprocess(source, "test.c", &[], false).unwrap();
+        output
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|line| !line.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn stringize_escapes_only_inside_literals() {
+        assert_eq!(
+            lines(
+                "#define str(s) # s\n\
+                 str(: @\\n)\n\
+                 str(\\)\n\
+                 str(\"a\\n\" 'b' '\\'' \"\\\\\")\n\
+                 str(  a   +\n b  )\n"
+            ),
+            [
+                r#"": @\n""#,
+                r#""\""#,
+                r#""\"a\\n\" 'b' '\\'' \"\\\\\"""#,
+                r#""a + b""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn paste() {
+        assert_eq!(
+            lines(
+                "#define cat(a, b) a ## b\n\
+                 #define ident(x) x\n\
+                 cat(x, 1) cat(, y) cat(z, ) [cat(,)]\n\
+                 cat(<, <=) ident(cat(1, e)+3)\n"
+            ),
+            // `1e+3` would lex as a single number.
+            ["x1 y z [ ]", "<<= 1e +3"]
+        );
+    }
+
+    #[test]
+    fn invalid_paste_is_an_error() {
+        let source = "#define cat(a, b) a ## b\ncat(+, /)\n";
+        assert!(matches!(
+            preprocess(source, "test.c", &[], false),
+            Err(PreprocessError::InvalidPaste { .. })
+        ));
+    }
+
+    #[test]
+    fn variadic_macros() {
+        assert_eq!(
+            lines(
+                "#define f(fmt, ...) g(fmt, ## __VA_ARGS__)\n\
+                 #define all(...) [__VA_ARGS__]\n\
+                 #define tail(...) , ## __VA_ARGS__ ## x\n\
+                 f(a) f(a, b) f(a, b, c)\n\
+                 all() all(1, (2, 3))\n\
+                 tail() tail(a)\n"
+            ),
+            ["g(a) g(a, b) g(a, b, c)", "[] [1, (2, 3)]", "x ,ax"]
+        );
+    }
+
+    #[test]
+    fn conditionals() {
+        assert_eq!(
+            lines(
+                "#define A 2\n\
+                 #if A > 1 && defined(A)\n\
+                 one\n\
+                 #elif 1\n\
+                 two\n\
+                 #else\n\
+                 three\n\
+                 #endif\n\
+                 #ifdef B\n\
+                 four\n\
+                 #elif A == 2 ? 0 : 1\n\
+                 five\n\
+                 #else\n\
+                 #if 0\n\
+                 #error skipped\n\
+                 #endif\n\
+                 six\n\
+                 #endif\n\
+                 #ifndef B\n\
+                 seven\n\
+                 #endif\n"
+            ),
+            ["one", "six", "seven"]
+        );
+    }
+
+    #[test]
+    fn unterminated_conditional_is_an_error() {
+        assert!(preprocess("#if 1\nx\n", "test.c", &[], false).is_err());
+    }
+}
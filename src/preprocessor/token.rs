@@ -0,0 +1,287 @@
+use super::error::PreprocessError;
+use crate::lexer::span::Span;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PpKind {
+    Identifier,
+    Number,
+    CharLiteral,
+    StringLiteral,
+    Punctuator,
+    /// Any other single non-whitespace character, e.g. a stray `@` or `\`.
+    Other,
+    Newline,
+    /// Produced by `##` when one of its operands is an empty argument.
+    Placemarker,
+}
+
+/// Names of the macros a token was produced by, which must not be expanded
+/// again while rescanning it.
+pub type HideSet = Rc<BTreeSet<Rc<str>>>;
+
+/// A preprocessing token (C17 6.4), as seen by the directive and macro
+/// expansion logic.
+#[derive(Clone, Debug)]
+pub struct PpToken {
+    pub kind: PpKind,
+    pub text: Rc<str>,
+    /// Whitespace (or a comment) came before the token on its line.
+    pub leading_space: bool,
+    pub span: Span,
+    pub hide_set: HideSet,
+}
+
+impl PpToken {
+    pub fn is(&self, text: &str) -> bool {
+        (self.kind == PpKind::Punctuator || self.kind == PpKind::Identifier) && &*self.text == text
+    }
+
+    /// Copy of the token with different spelling, keeping its location.
+    pub fn with_text(&self, kind: PpKind, text: &str) -> PpToken {
+        PpToken {
+            kind,
+            text: Rc::from(text),
+            ..self.clone()
+        }
+    }
+}
+
+/// Punctuators longest first, including `#` and `##` which only exist in
+/// the preprocessor.
+const PUNCTUATORS: [&str; 48] = [
+    "<<=", ">>=", "...", "##", "--", "++", "&&", "||", "<<", ">>", "==", "!=", "<=", ">=", "+=",
+    "-=", "*=", "/=", "%=", "&=", "|=", "^=", "->", "(", ")", "{", "}", "[", "]", ";", "~", "-",
+    "+", "*", "/", "%", "&", "|", "^", "!", "<", ">", "=", "?", ":", ",", ".", "#",
+];
+
+fn is_identifier_start(c: u8) -> bool {
+    c.is_ascii_alphabetic() || c == b'_' || c == b'$' || c >= 0x80
+}
+
+fn is_identifier_continue(c: u8) -> bool {
+    is_identifier_start(c) || c.is_ascii_digit()
+}
+
+/// Splits source text into preprocessing tokens after removing
+/// backslash-newline line splices and replacing comments by whitespace.
+struct Tokenizer<'a> {
+    text: String,
+    /// Offsets in `text` where a line splice was removed.
+    splices: Vec<usize>,
+    next_splice: usize,
+    file: &'a Rc<str>,
+    offset: usize,
+    line: u32,
+    column: u32,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn new(source: &str, file: &'a Rc<str>) -> Tokenizer<'a> {
+        let mut text = String::with_capacity(source.len());
+        let mut splices = Vec::new();
+        let mut rest = source;
+        while let Some(index) = rest.find('\\') {
+            text.push_str(&rest[..index]);
+            let after = &rest[index + 1..];
+            let newline = if after.starts_with('\n') {
+                1
+            } else if after.starts_with("\r\n") {
+                2
+            } else {
+                0
+            };
+            if newline > 0 {
+                splices.push(text.len());
+                rest = &after[newline..];
+            } else {
+                text.push('\\');
+                rest = after;
+            }
+        }
+        text.push_str(rest);
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        Tokenizer {
+            text,
+            splices,
+            next_splice: 0,
+            file,
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek_at(&self, n: usize) -> Option<u8> {
+        self.text.as_bytes().get(self.offset + n).copied()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.peek_at(0)
+    }
+
+    fn bump(&mut self) {
+        let c = self.peek().unwrap();
+        self.offset += 1;
+        if c == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if c & 0xC0 != 0x80 {
+            self.column += 1;
+        }
+        while self.splices.get(self.next_splice) == Some(&self.offset) {
+            self.next_splice += 1;
+            self.line += 1;
+            self.column = 1;
+        }
+    }
+
+    fn bump_n(&mut self, n: usize) {
+        for _ in 0..n {
+            self.bump();
+        }
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.offset..]
+    }
+
+    /// Skips whitespace other than newlines and comments. Returns whether
+    /// anything was skipped.
+    fn skip_space(&mut self) -> Result<bool, PreprocessError> {
+        let start = self.offset;
+        loop {
+            match self.peek() {
+                Some(b' ' | b'\t' | b'\r' | b'\x0b' | b'\x0c') => self.bump(),
+                Some(b'/') if self.peek_at(1) == Some(b'/') => {
+                    while self.peek().is_some_and(|c| c != b'\n') {
+                        self.bump();
+                    }
+                }
+                Some(b'/') if self.peek_at(1) == Some(b'*') => {
+                    let span = Span::new(
+                        self.file.clone(),
+                        self.line,
+                        self.column,
+                        self.offset,
+                        self.offset + 2,
+                    );
+                    match self.rest()[2..].find("*/") {
+                        Some(end) => self.bump_n(end + 4),
+                        None => return Err(PreprocessError::UnterminatedComment { span }),
+                    }
+                }
+                _ => return Ok(self.offset != start),
+            }
+        }
+    }
+
+    /// Length of a character constant or string literal starting at the
+    /// cursor, if there is a terminated one.
+    fn quoted_length(&self) -> Option<usize> {
+        let rest = self.rest().as_bytes();
+        let prefix = ["u8", "u", "U", "L", ""]
+            .iter()
+            .find(|prefix| {
+                rest.starts_with(prefix.as_bytes())
+                    && matches!(rest.get(prefix.len()), Some(b'"' | b'\''))
+            })?
+            .len();
+        let quote = rest[prefix];
+        let mut index = prefix + 1;
+        loop {
+            match rest.get(index)? {
+                b'\\' => index += 2,
+                b'\n' => return None,
+                c if *c == quote => return Some(index + 1),
+                _ => index += 1,
+            }
+        }
+    }
+
+    fn number_length(&self) -> usize {
+        let rest = self.rest().as_bytes();
+        let mut index = 0;
+        while let Some(&c) = rest.get(index) {
+            let sign = (c == b'+' || c == b'-') && index > 0 && b"eEpP".contains(&rest[index - 1]);
+            if !(is_identifier_continue(c) || c == b'.' || sign) {
+                break;
+            }
+            index += 1;
+        }
+        index
+    }
+
+    fn next(&mut self) -> Result<Option<(PpKind, usize)>, PreprocessError> {
+        let rest = self.rest().as_bytes();
+        let Some(&c) = rest.first() else {
+            return Ok(None);
+        };
+        let token = if c == b'\n' {
+            (PpKind::Newline, 1)
+        } else if let Some(length) = self.quoted_length() {
+            let kind = if rest[..length].ends_with(b"\"") {
+                PpKind::StringLiteral
+            } else {
+                PpKind::CharLiteral
+            };
+            (kind, length)
+        } else if c.is_ascii_digit() || (c == b'.' && rest.get(1).is_some_and(u8::is_ascii_digit)) {
+            (PpKind::Number, self.number_length())
+        } else if is_identifier_start(c) {
+            let length = rest
+                .iter()
+                .position(|c| !is_identifier_continue(*c))
+                .unwrap_or(rest.len());
+            (PpKind::Identifier, length)
+        } else if let Some(punctuator) = PUNCTUATORS.iter().find(|p| rest.starts_with(p.as_bytes()))
+        {
+            (PpKind::Punctuator, punctuator.len())
+        } else {
+            let length = self.rest().chars().next().unwrap().len_utf8();
+            (PpKind::Other, length)
+        };
+        Ok(Some(token))
+    }
+}
+
+/// Tokenizes a whole file. Every line, including the last one, ends with a
+/// `Newline` token.
+pub fn tokenize(source: &str, file: &Rc<str>) -> Result<Vec<PpToken>, PreprocessError> {
+    let mut tokenizer = Tokenizer::new(source, file);
+    let mut tokens = Vec::new();
+    let hide_set = HideSet::default();
+    loop {
+        let leading_space = tokenizer.skip_space()?;
+        let Some((kind, length)) = tokenizer.next()? else {
+            return Ok(tokens);
+        };
+        let start = tokenizer.offset;
+        let (line, column) = (tokenizer.line, tokenizer.column);
+        let text = Rc::from(&tokenizer.text[start..start + length]);
+        tokenizer.bump_n(length);
+        tokens.push(PpToken {
+            kind,
+            text,
+            leading_space,
+            span: Span::new(file.clone(), line, column, start, start + length),
+            hide_set: hide_set.clone(),
+        });
+    }
+}
+
+/// Tokenizes text which is not part of a file, e.g. the result of `##` or
+/// a predefined macro. Returns `None` unless it is exactly one token.
+pub fn single_token(text: &str, location: &PpToken) -> Option<PpToken> {
+    let file = location.span.file.clone();
+    let tokens = tokenize(text, &file).ok()?;
+    match tokens.as_slice() {
+        [token, newline] if newline.kind == PpKind::Newline && !token.leading_space => {
+            Some(location.with_text(token.kind, &token.text))
+        }
+        _ => None,
+    }
+}